@@ -68,6 +68,17 @@ async fn main() -> Result<(), edgehog_device_runtime::error::DeviceManagerError>
         astarte_ignore_ssl: Some(false),
         telemetry_config: Some(vec![]),
         astarte_message_hub: None,
+        gnss: None,
+        battery: None,
+        exec_sources: None,
+        storage_usage: None,
+        systemd: None,
+        process_watch: None,
+        network_config: None,
+        diagnostics: None,
+        telemetry_period_limits: None,
+        metrics: None,
+        alerts: None,
     };
 
     let astarte_options = astarte_map_options(&device_options).await?;
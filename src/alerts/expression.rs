@@ -0,0 +1,420 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Conditions of the alert rules, comparing two arithmetic expressions over the numeric fields of
+//! a telemetry sample, e.g. `freeBytes / totalBytes * 100 < 10`.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::error::DeviceManagerError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Comparison {
+    fn holds(self, lhs: f64, rhs: f64) -> bool {
+        match self {
+            Comparison::Lt => lhs < rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs > rhs,
+            Comparison::Ge => lhs >= rhs,
+            Comparison::Eq => lhs == rhs,
+            Comparison::Ne => lhs != rhs,
+        }
+    }
+
+    /// Moves the threshold by the hysteresis in the direction that keeps the condition true.
+    fn clear_threshold(self, threshold: f64, hysteresis: f64) -> f64 {
+        match self {
+            Comparison::Lt | Comparison::Le => threshold + hysteresis,
+            Comparison::Gt | Comparison::Ge => threshold - hysteresis,
+            Comparison::Eq | Comparison::Ne => threshold,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    Field(String),
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, Operator, Box<Expr>),
+}
+
+impl Expr {
+    fn evaluate(&self, values: &HashMap<String, f64>) -> Option<f64> {
+        match self {
+            Expr::Number(value) => Some(*value),
+            Expr::Field(field) => values.get(field).copied(),
+            Expr::Neg(expr) => expr.evaluate(values).map(|value| -value),
+            Expr::Binary(lhs, op, rhs) => {
+                let lhs = lhs.evaluate(values)?;
+                let rhs = rhs.evaluate(values)?;
+
+                Some(match op {
+                    Operator::Add => lhs + rhs,
+                    Operator::Sub => lhs - rhs,
+                    Operator::Mul => lhs * rhs,
+                    Operator::Div => lhs / rhs,
+                })
+            }
+        }
+    }
+}
+
+/// Result of a condition on a sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Evaluation {
+    /// Value of the left side of the comparison.
+    pub value: f64,
+    /// Value of the right side of the comparison.
+    pub threshold: f64,
+    /// The condition holds, the alert has to be raised.
+    pub raise: bool,
+    /// The condition holds within the hysteresis, a raised alert has to be kept.
+    pub keep: bool,
+}
+
+/// Comparison between two arithmetic expressions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    source: String,
+    lhs: Expr,
+    comparison: Comparison,
+    rhs: Expr,
+}
+
+impl Condition {
+    /// Evaluates the condition on the fields of a sample.
+    ///
+    /// Returns `None` if a field is missing or the result is not a finite number, e.g. on a
+    /// division by zero.
+    pub fn evaluate(&self, values: &HashMap<String, f64>, hysteresis: f64) -> Option<Evaluation> {
+        let value = self.lhs.evaluate(values)?;
+        let threshold = self.rhs.evaluate(values)?;
+        if !value.is_finite() || !threshold.is_finite() {
+            return None;
+        }
+
+        let clear_threshold = self.comparison.clear_threshold(threshold, hysteresis);
+
+        Some(Evaluation {
+            value,
+            threshold,
+            raise: self.comparison.holds(value, threshold),
+            keep: self.comparison.holds(value, clear_threshold),
+        })
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = DeviceManagerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+
+        let lhs = parser.sum()?;
+        let comparison = match parser.next() {
+            Some(Token::Comparison(comparison)) => comparison,
+            _ => return Err(parse_error(s, "expected a comparison")),
+        };
+        let rhs = parser.sum()?;
+
+        if parser.pos != parser.tokens.len() {
+            return Err(parse_error(s, "unexpected trailing input"));
+        }
+
+        Ok(Condition {
+            source: s.trim().to_string(),
+            lhs,
+            comparison,
+            rhs,
+        })
+    }
+}
+
+fn parse_error(expression: &str, reason: &str) -> DeviceManagerError {
+    DeviceManagerError::AlertRuleError(format!("{reason} in '{expression}'"))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Field(String),
+    Operator(Operator),
+    Comparison(Comparison),
+    Open,
+    Close,
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, DeviceManagerError> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        let token = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+
+                let number: String = chars[start..i].iter().collect();
+                let number = number
+                    .parse()
+                    .map_err(|_| parse_error(expression, &format!("invalid number {number}")))?;
+                tokens.push(Token::Number(number));
+                continue;
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+
+                let name: String = chars[start..i].iter().collect();
+                tokens.push(match name.as_str() {
+                    "true" => Token::Number(1.0),
+                    "false" => Token::Number(0.0),
+                    _ => Token::Field(name),
+                });
+                continue;
+            }
+            '+' => Token::Operator(Operator::Add),
+            '-' => Token::Operator(Operator::Sub),
+            '*' => Token::Operator(Operator::Mul),
+            '/' => Token::Operator(Operator::Div),
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '<' | '>' | '=' | '!' => {
+                let (comparison, len) = match (c, next) {
+                    ('<', Some('=')) => (Comparison::Le, 2),
+                    ('<', _) => (Comparison::Lt, 1),
+                    ('>', Some('=')) => (Comparison::Ge, 2),
+                    ('>', _) => (Comparison::Gt, 1),
+                    ('=', Some('=')) => (Comparison::Eq, 2),
+                    ('!', Some('=')) => (Comparison::Ne, 2),
+                    _ => return Err(parse_error(expression, &format!("unexpected '{c}'"))),
+                };
+
+                i += len;
+                tokens.push(Token::Comparison(comparison));
+                continue;
+            }
+            c => return Err(parse_error(expression, &format!("unexpected '{c}'"))),
+        };
+
+        i += 1;
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent parser of the arithmetic expressions, with the usual precedence.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        if token.is_some() {
+            self.pos += 1;
+        }
+
+        token
+    }
+
+    fn peek_operator(&self, operators: &[Operator]) -> Option<Operator> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Operator(op)) if operators.contains(op) => Some(*op),
+            _ => None,
+        }
+    }
+
+    fn error(&self, reason: &str) -> DeviceManagerError {
+        DeviceManagerError::AlertRuleError(format!("{reason} at token {}", self.pos + 1))
+    }
+
+    fn sum(&mut self) -> Result<Expr, DeviceManagerError> {
+        let mut expr = self.product()?;
+        while let Some(op) = self.peek_operator(&[Operator::Add, Operator::Sub]) {
+            self.pos += 1;
+            expr = Expr::Binary(Box::new(expr), op, Box::new(self.product()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expr, DeviceManagerError> {
+        let mut expr = self.unary()?;
+        while let Some(op) = self.peek_operator(&[Operator::Mul, Operator::Div]) {
+            self.pos += 1;
+            expr = Expr::Binary(Box::new(expr), op, Box::new(self.unary()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, DeviceManagerError> {
+        match self.next() {
+            Some(Token::Operator(Operator::Sub)) => Ok(Expr::Neg(Box::new(self.unary()?))),
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Field(field)) => Ok(Expr::Field(field)),
+            Some(Token::Open) => {
+                let expr = self.sum()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err(self.error("expected ')'")),
+                }
+            }
+            Some(_) => Err(self.error("expected a number, a field or '('")),
+            None => Err(self.error("unexpected end of the expression")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::alerts::expression::Condition;
+
+    fn values(pairs: &[(&str, f64)]) -> HashMap<String, f64> {
+        pairs
+            .iter()
+            .map(|(field, value)| (field.to_string(), *value))
+            .collect()
+    }
+
+    #[test]
+    fn parse_and_evaluate_test() {
+        let condition: Condition = "freeBytes / totalBytes * 100 < 10".parse().unwrap();
+        let sample = values(&[("freeBytes", 5.0), ("totalBytes", 100.0)]);
+
+        let evaluation = condition.evaluate(&sample, 0.0).unwrap();
+        assert_eq!(evaluation.value, 5.0);
+        assert_eq!(evaluation.threshold, 10.0);
+        assert!(evaluation.raise);
+        assert!(evaluation.keep);
+
+        let condition: Condition = "-(a - 2) * 3 >= -3 + b".parse().unwrap();
+        let evaluation = condition
+            .evaluate(&values(&[("a", 4.0), ("b", 1.0)]), 0.0)
+            .unwrap();
+        assert_eq!(evaluation.value, -6.0);
+        assert_eq!(evaluation.threshold, -2.0);
+        assert!(!evaluation.raise);
+
+        let condition: Condition = "readOnly == true".parse().unwrap();
+        assert!(
+            condition
+                .evaluate(&values(&[("readOnly", 1.0)]), 0.0)
+                .unwrap()
+                .raise
+        );
+    }
+
+    #[test]
+    fn hysteresis_test() {
+        let condition: Condition = "temperature > 80".parse().unwrap();
+
+        let evaluation = condition
+            .evaluate(&values(&[("temperature", 78.0)]), 5.0)
+            .unwrap();
+        assert!(!evaluation.raise);
+        assert!(evaluation.keep);
+
+        let evaluation = condition
+            .evaluate(&values(&[("temperature", 74.0)]), 5.0)
+            .unwrap();
+        assert!(!evaluation.keep);
+
+        let condition: Condition = "free < 10".parse().unwrap();
+        let evaluation = condition.evaluate(&values(&[("free", 12.0)]), 5.0).unwrap();
+        assert!(!evaluation.raise);
+        assert!(evaluation.keep);
+    }
+
+    #[test]
+    fn missing_or_invalid_values_test() {
+        let condition: Condition = "free / total < 0.1".parse().unwrap();
+
+        assert_eq!(condition.evaluate(&values(&[("free", 1.0)]), 0.0), None);
+        assert_eq!(
+            condition.evaluate(&values(&[("free", 0.0), ("total", 0.0)]), 0.0),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_errors_test() {
+        for expression in [
+            "",
+            "temperature",
+            "temperature > ",
+            "temperature > 80 80",
+            "(temperature > 80",
+            "temperature = 80",
+            "temperature > 8.0.0",
+            "temperature # 80",
+            "a < b < c",
+        ] {
+            assert!(
+                expression.parse::<Condition>().is_err(),
+                "{expression} should not parse"
+            );
+        }
+    }
+}
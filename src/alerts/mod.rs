@@ -0,0 +1,892 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Threshold alerts evaluated on the device, on samples of the telemetry sources taken on the
+//! period of each rule.
+//!
+//! The rules are configured locally and through `io.edgehog.devicemanager.config.AlertRules`, the
+//! remote fields override the local ones of the rule with the same name.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use astarte_device_sdk::types::AstarteType;
+use astarte_device_sdk::AstarteAggregate;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tokio::time::{interval, Duration, Instant};
+
+use crate::data::Publisher;
+use crate::error::DeviceManagerError;
+use crate::repository::file_state_repository::FileStateRepository;
+use crate::repository::StateRepository;
+use crate::telemetry::send_policy::as_f64;
+use crate::telemetry::{self, Sampler, TelemetryMessage, TelemetrySources};
+
+use self::expression::Condition;
+
+mod expression;
+
+const ALERT_EVENT_INTERFACE: &str = "io.edgehog.devicemanager.AlertEvent";
+const ALERT_RULES_PATH: &str = "alert_rules.json";
+// granularity of the periods of the rules
+const EVALUATION_TICK: Duration = Duration::from_secs(1);
+
+/// Configuration of the alert rules.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct AlertsOptions {
+    /// Default seconds between two evaluations of a rule, 0 disables the alerts.
+    #[serde(default = "default_evaluation_period")]
+    pub evaluation_period: u64,
+    #[serde(default)]
+    pub rules: Vec<AlertRuleConfig>,
+}
+
+fn default_evaluation_period() -> u64 {
+    30
+}
+
+impl Default for AlertsOptions {
+    fn default() -> Self {
+        AlertsOptions {
+            evaluation_period: default_evaluation_period(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AlertRuleConfig {
+    pub name: String,
+    /// Telemetry interface sampled for the rule, whether it's enabled or not.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// Path of the interface, by default the rule is evaluated on every path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Comparison over the fields of the interface, e.g. `freeBytes / totalBytes * 100 < 10`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expression: Option<String>,
+    /// Seconds between two samples of the interface, `evaluation_period` by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period_seconds: Option<u64>,
+    /// Distance from the threshold the value has to go back before the alert is cleared.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hysteresis: Option<f64>,
+    /// Minimum seconds between two raises of the alert on the same path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cooldown_seconds: Option<u64>,
+    /// LED set to `led_behavior` when the alert is raised.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub led_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub led_behavior: Option<String>,
+}
+
+impl AlertRuleConfig {
+    fn new(name: &str) -> Self {
+        AlertRuleConfig {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Overrides the fields of the local configuration with the ones set remotely.
+    fn merge(&self, remote: &AlertRuleConfig) -> AlertRuleConfig {
+        AlertRuleConfig {
+            name: self.name.clone(),
+            interface: remote.interface.clone().or_else(|| self.interface.clone()),
+            path: remote.path.clone().or_else(|| self.path.clone()),
+            expression: remote
+                .expression
+                .clone()
+                .or_else(|| self.expression.clone()),
+            period_seconds: remote.period_seconds.or(self.period_seconds),
+            hysteresis: remote.hysteresis.or(self.hysteresis),
+            cooldown_seconds: remote.cooldown_seconds.or(self.cooldown_seconds),
+            led_id: remote.led_id.clone().or_else(|| self.led_id.clone()),
+            led_behavior: remote
+                .led_behavior
+                .clone()
+                .or_else(|| self.led_behavior.clone()),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == AlertRuleConfig::new(&self.name)
+    }
+}
+
+#[derive(Debug, Clone, AstarteAggregate, PartialEq)]
+#[allow(non_snake_case)]
+pub struct AlertEvent {
+    pub rule: String,
+    pub interface: String,
+    pub path: String,
+    /// "Any of: Raised, Cleared"
+    pub status: String,
+    /// Value of the left side of the expression.
+    pub value: f64,
+    /// Value of the right side of the expression.
+    pub threshold: f64,
+}
+
+#[derive(Debug, Default)]
+struct AlertState {
+    raised: bool,
+    last_raised: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct AlertRule {
+    interface: String,
+    path: Option<String>,
+    condition: Condition,
+    period: Duration,
+    next_evaluation: Instant,
+    hysteresis: f64,
+    cooldown: Duration,
+    led: Option<(String, String)>,
+    /// State of the alert on each path of the interface.
+    states: HashMap<String, AlertState>,
+}
+
+impl AlertRule {
+    /// Compiles a rule, returns `None` while its interface or expression are not configured.
+    fn compile(
+        config: &AlertRuleConfig,
+        sources: &TelemetrySources,
+        default_period: u64,
+    ) -> Result<Option<Self>, DeviceManagerError> {
+        let (Some(interface), Some(expression)) = (&config.interface, &config.expression) else {
+            return Ok(None);
+        };
+
+        if !telemetry::is_supported(sources, interface) {
+            return Err(DeviceManagerError::AlertRuleError(format!(
+                "unknown interface {interface}"
+            )));
+        }
+
+        let led = match (&config.led_id, &config.led_behavior) {
+            (Some(led_id), Some(behavior)) => Some((led_id.clone(), behavior.clone())),
+            _ => None,
+        };
+
+        Ok(Some(AlertRule {
+            interface: interface.clone(),
+            path: config.path.clone(),
+            condition: expression.parse()?,
+            period: Duration::from_secs(config.period_seconds.unwrap_or(default_period).max(1)),
+            next_evaluation: Instant::now(),
+            hysteresis: config.hysteresis.unwrap_or(0.0).abs(),
+            cooldown: Duration::from_secs(config.cooldown_seconds.unwrap_or(0)),
+            led,
+            states: HashMap::new(),
+        }))
+    }
+
+    /// Evaluates the rule on a sample, returns the event to publish if the alert changed state.
+    fn evaluate(
+        &mut self,
+        name: &str,
+        path: &str,
+        values: &HashMap<String, f64>,
+        now: Instant,
+    ) -> Option<AlertEvent> {
+        if self
+            .path
+            .as_deref()
+            .map_or(false, |rule_path| rule_path != path)
+        {
+            return None;
+        }
+
+        let evaluation = self.condition.evaluate(values, self.hysteresis)?;
+        let state = self.states.entry(path.to_string()).or_default();
+
+        let status = if !state.raised && evaluation.raise {
+            let cooling_down = state.last_raised.map_or(false, |last_raised| {
+                now.duration_since(last_raised) < self.cooldown
+            });
+            if cooling_down {
+                return None;
+            }
+
+            state.raised = true;
+            state.last_raised = Some(now);
+
+            "Raised"
+        } else if state.raised && !evaluation.keep {
+            state.raised = false;
+
+            "Cleared"
+        } else {
+            return None;
+        };
+
+        Some(AlertEvent {
+            rule: name.to_string(),
+            interface: self.interface.clone(),
+            path: path.to_string(),
+            status: status.to_string(),
+            value: evaluation.value,
+            threshold: evaluation.threshold,
+        })
+    }
+}
+
+/// Fields of a sample usable in the expressions, booleans are converted to 0 and 1.
+fn numeric_values(values: &HashMap<String, AstarteType>) -> HashMap<String, f64> {
+    values
+        .iter()
+        .filter_map(|(field, value)| {
+            let value = match value {
+                AstarteType::Boolean(value) => f64::from(u8::from(*value)),
+                value => as_f64(value)?,
+            };
+
+            Some((field.clone(), value))
+        })
+        .collect()
+}
+
+fn set_string(field: &mut Option<String>, data: &AstarteType) -> bool {
+    match data {
+        AstarteType::String(value) => *field = Some(value.clone()),
+        AstarteType::Unset => *field = None,
+        _ => return false,
+    }
+
+    true
+}
+
+pub struct Alerts {
+    evaluation_period: u64,
+    sources: Arc<TelemetrySources>,
+    store_directory: String,
+    local: HashMap<String, AlertRuleConfig>,
+    remote: RwLock<HashMap<String, AlertRuleConfig>>,
+    rules: RwLock<HashMap<String, AlertRule>>,
+}
+
+impl Alerts {
+    pub async fn new(
+        opts: AlertsOptions,
+        sources: Arc<TelemetrySources>,
+        store_directory: String,
+    ) -> Self {
+        let local: HashMap<String, AlertRuleConfig> = opts
+            .rules
+            .into_iter()
+            .map(|rule| (rule.name.clone(), rule))
+            .collect();
+
+        let repository = FileStateRepository::new(store_directory.clone(), ALERT_RULES_PATH.into());
+        let mut remote = HashMap::new();
+        if StateRepository::<Vec<AlertRuleConfig>>::exists(&repository).await {
+            match StateRepository::<Vec<AlertRuleConfig>>::read(&repository).await {
+                Ok(saved) => {
+                    remote = saved
+                        .into_iter()
+                        .map(|rule| (rule.name.clone(), rule))
+                        .collect();
+                }
+                Err(err) => error!("couldn't read the saved alert rules: {err}"),
+            }
+        }
+
+        let alerts = Alerts {
+            evaluation_period: opts.evaluation_period,
+            sources,
+            store_directory,
+            local,
+            remote: RwLock::new(remote),
+            rules: RwLock::new(HashMap::new()),
+        };
+
+        let names: HashSet<String> = alerts
+            .local
+            .keys()
+            .chain(alerts.remote.read().await.keys())
+            .cloned()
+            .collect();
+        for name in names {
+            alerts.reload_rule(&name).await;
+        }
+
+        alerts
+    }
+
+    /// Evaluates the rules on their periods, independently of the telemetry configuration of
+    /// their interfaces.
+    pub async fn run(self: Arc<Self>, publisher: impl Publisher) {
+        if self.evaluation_period == 0 {
+            return;
+        }
+
+        let mut ticker = interval(EVALUATION_TICK);
+        loop {
+            ticker.tick().await;
+
+            self.evaluate_due(&publisher, Instant::now()).await;
+        }
+    }
+
+    /// Samples the interfaces of the rules whose period elapsed and evaluates them.
+    async fn evaluate_due(&self, publisher: &impl Publisher, now: Instant) {
+        let interfaces: HashSet<String> = self
+            .rules
+            .read()
+            .await
+            .values()
+            .filter(|rule| rule.next_evaluation <= now)
+            .map(|rule| rule.interface.clone())
+            .collect();
+
+        for interface in interfaces {
+            let samples = telemetry::get_data(&interface, &self.sources, Sampler::Alerts)
+                .await
+                .unwrap_or_else(|err| {
+                    warn!("couldn't sample {interface} for the alert rules: {err}");
+
+                    Vec::new()
+                });
+
+            self.evaluate(publisher, &interface, &samples, now).await;
+        }
+    }
+
+    /// Evaluates the due rules of the interface on its samples.
+    async fn evaluate(
+        &self,
+        publisher: &impl Publisher,
+        interface: &str,
+        samples: &[TelemetryMessage],
+        now: Instant,
+    ) {
+        let mut events = Vec::new();
+
+        {
+            let mut rules = self.rules.write().await;
+            let mut due: Vec<(&String, &mut AlertRule)> = rules
+                .iter_mut()
+                .filter(|(_, rule)| rule.interface == interface && rule.next_evaluation <= now)
+                .collect();
+            for (_, rule) in due.iter_mut() {
+                rule.next_evaluation = now + rule.period;
+            }
+
+            for sample in samples {
+                let values = match sample.payload.values() {
+                    Ok(values) => numeric_values(&values),
+                    Err(err) => {
+                        warn!("couldn't read the {interface} sample: {err}");
+                        continue;
+                    }
+                };
+
+                let key = sample.key();
+                for (name, rule) in due.iter_mut() {
+                    if let Some(event) = rule.evaluate(name.as_str(), &key, &values, now) {
+                        let led = rule.led.clone().filter(|_| event.status == "Raised");
+                        events.push((event, led));
+                    }
+                }
+            }
+        }
+
+        for (event, led) in events {
+            info!(
+                "alert {} {} on {}/{}: {} (threshold {})",
+                event.rule,
+                event.status.to_lowercase(),
+                event.interface,
+                event.path,
+                event.value,
+                event.threshold
+            );
+
+            if let Err(err) = publisher
+                .send_object(ALERT_EVENT_INTERFACE, "/event", event)
+                .await
+            {
+                error!("couldn't publish the alert event: {err}");
+            }
+
+            if let Some((led_id, behavior)) = led {
+                tokio::spawn(crate::led_behavior::set_behavior(led_id, behavior));
+            }
+        }
+    }
+
+    /// handle io.edgehog.devicemanager.config.AlertRules
+    pub async fn config_event(&self, rule_name: &str, endpoint: &str, data: &AstarteType) {
+        {
+            let mut remote = self.remote.write().await;
+            let config = remote
+                .entry(rule_name.to_string())
+                .or_insert_with(|| AlertRuleConfig::new(rule_name));
+
+            let valid = match (endpoint, data) {
+                ("interface", data) => set_string(&mut config.interface, data),
+                ("path", data) => set_string(&mut config.path, data),
+                ("expression", data) => set_string(&mut config.expression, data),
+                ("ledId", data) => set_string(&mut config.led_id, data),
+                ("ledBehavior", data) => set_string(&mut config.led_behavior, data),
+                ("hysteresis", AstarteType::Double(hysteresis)) => {
+                    config.hysteresis = Some(*hysteresis);
+                    true
+                }
+                ("hysteresis", AstarteType::Unset) => {
+                    config.hysteresis = None;
+                    true
+                }
+                ("cooldownSeconds", AstarteType::LongInteger(cooldown)) => {
+                    config.cooldown_seconds = Some(u64::try_from(*cooldown).unwrap_or(0));
+                    true
+                }
+                ("cooldownSeconds", AstarteType::Integer(cooldown)) => {
+                    config.cooldown_seconds = Some(u64::try_from(*cooldown).unwrap_or(0));
+                    true
+                }
+                ("cooldownSeconds", AstarteType::Unset) => {
+                    config.cooldown_seconds = None;
+                    true
+                }
+                ("periodSeconds", AstarteType::LongInteger(period)) => {
+                    config.period_seconds = Some(u64::try_from(*period).unwrap_or(0));
+                    true
+                }
+                ("periodSeconds", AstarteType::Integer(period)) => {
+                    config.period_seconds = Some(u64::try_from(*period).unwrap_or(0));
+                    true
+                }
+                ("periodSeconds", AstarteType::Unset) => {
+                    config.period_seconds = None;
+                    true
+                }
+                _ => false,
+            };
+
+            if config.is_empty() {
+                remote.remove(rule_name);
+            }
+
+            if !valid {
+                warn!("Received malformed data from io.edgehog.devicemanager.config.AlertRules: {endpoint} {data:?}");
+
+                return;
+            }
+        }
+
+        self.reload_rule(rule_name).await;
+        self.save_remote_rules().await;
+    }
+
+    /// Compiles the rule from its local and remote configuration, keeping the state of its
+    /// alerts if it still samples the same interface.
+    async fn reload_rule(&self, name: &str) {
+        let config = {
+            let remote = self.remote.read().await;
+            match (self.local.get(name), remote.get(name)) {
+                (Some(local), Some(remote)) => local.merge(remote),
+                (Some(local), None) => local.clone(),
+                (None, Some(remote)) => remote.clone(),
+                (None, None) => AlertRuleConfig::new(name),
+            }
+        };
+
+        let mut rules = self.rules.write().await;
+        let previous = rules.remove(name);
+
+        let mut rule = match AlertRule::compile(&config, &self.sources, self.evaluation_period) {
+            Ok(Some(rule)) => rule,
+            Ok(None) => {
+                debug!("alert rule {name} is not complete");
+
+                return;
+            }
+            Err(err) => {
+                warn!("ignoring the alert rule {name}: {err}");
+
+                return;
+            }
+        };
+
+        if let Some(previous) = previous.filter(|previous| previous.interface == rule.interface) {
+            rule.states = previous.states;
+        }
+
+        debug!(
+            "alert rule {name}: {} on {}",
+            rule.condition, rule.interface
+        );
+        rules.insert(name.to_string(), rule);
+    }
+
+    async fn save_remote_rules(&self) {
+        let rules: Vec<AlertRuleConfig> = self.remote.read().await.values().cloned().collect();
+
+        let repository =
+            FileStateRepository::new(self.store_directory.clone(), ALERT_RULES_PATH.into());
+        if let Err(err) = repository.write(&rules).await {
+            error!("couldn't save the alert rules: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use astarte_device_sdk::types::AstarteType;
+    use tempdir::TempDir;
+    use tokio::time::{Duration, Instant};
+
+    use crate::alerts::{
+        AlertEvent, AlertRule, AlertRuleConfig, Alerts, AlertsOptions, ALERT_EVENT_INTERFACE,
+    };
+    use crate::data::MockPublisher;
+    use crate::telemetry::storage_usage::DiskUsage;
+    use crate::telemetry::{TelemetryMessage, TelemetryPayload, TelemetrySources};
+
+    const STORAGE_USAGE: &str = "io.edgehog.devicemanager.StorageUsage";
+    const SYSTEM_STATUS: &str = "io.edgehog.devicemanager.SystemStatus";
+    const MOUNT_POINT_USAGE: &str = "io.edgehog.devicemanager.MountPointUsage";
+
+    fn disk_full_rule() -> AlertRuleConfig {
+        AlertRuleConfig {
+            name: "diskFull".to_string(),
+            interface: Some(STORAGE_USAGE.to_string()),
+            expression: Some("freeBytes / totalBytes * 100 < 10".to_string()),
+            hysteresis: Some(2.0),
+            ..Default::default()
+        }
+    }
+
+    fn storage_sample(path: &str, free_bytes: i64) -> TelemetryMessage {
+        TelemetryMessage {
+            path: path.to_string(),
+            payload: TelemetryPayload::StorageUsage(DiskUsage {
+                totalBytes: 100,
+                freeBytes: free_bytes,
+            }),
+        }
+    }
+
+    async fn alerts(rules: Vec<AlertRuleConfig>, store_directory: &TempDir) -> Alerts {
+        Alerts::new(
+            AlertsOptions {
+                evaluation_period: 30,
+                rules,
+            },
+            Arc::new(TelemetrySources::default()),
+            store_directory.path().to_str().unwrap().to_string(),
+        )
+        .await
+    }
+
+    fn expect_event(publisher: &mut MockPublisher, path: &'static str, status: &'static str) {
+        expect_rule_event(publisher, "diskFull", path, status);
+    }
+
+    fn expect_rule_event(
+        publisher: &mut MockPublisher,
+        rule: &'static str,
+        path: &'static str,
+        status: &'static str,
+    ) {
+        publisher
+            .expect_send_object()
+            .withf(
+                move |interface: &str, event_path: &str, event: &AlertEvent| {
+                    interface == ALERT_EVENT_INTERFACE
+                        && event_path == "/event"
+                        && event.rule == rule
+                        && event.path == path
+                        && event.status == status
+                },
+            )
+            .once()
+            .returning(|_: &str, _: &str, _: AlertEvent| Ok(()));
+    }
+
+    #[test]
+    fn rule_hysteresis_and_cooldown_test() {
+        let sources = TelemetrySources::default();
+        let mut config = disk_full_rule();
+        config.cooldown_seconds = Some(60);
+        let mut rule = AlertRule::compile(&config, &sources, 30).unwrap().unwrap();
+
+        let sample = |free: f64| {
+            HashMap::from([
+                ("freeBytes".to_string(), free),
+                ("totalBytes".to_string(), 100.0),
+            ])
+        };
+        let start = Instant::now();
+
+        let event = rule
+            .evaluate("diskFull", "data", &sample(5.0), start)
+            .unwrap();
+        assert_eq!(event.status, "Raised");
+        assert_eq!(event.value, 5.0);
+        assert_eq!(event.threshold, 10.0);
+
+        // still raised within the hysteresis
+        assert_eq!(
+            rule.evaluate("diskFull", "data", &sample(11.0), start),
+            None
+        );
+
+        let event = rule
+            .evaluate("diskFull", "data", &sample(12.0), start)
+            .unwrap();
+        assert_eq!(event.status, "Cleared");
+
+        // not raised again during the cooldown
+        let later = start + Duration::from_secs(30);
+        assert_eq!(rule.evaluate("diskFull", "data", &sample(5.0), later), None);
+
+        let later = start + Duration::from_secs(60);
+        let event = rule
+            .evaluate("diskFull", "data", &sample(5.0), later)
+            .unwrap();
+        assert_eq!(event.status, "Raised");
+
+        // other paths have their own state
+        let event = rule
+            .evaluate("diskFull", "boot", &sample(5.0), start)
+            .unwrap();
+        assert_eq!(event.status, "Raised");
+    }
+
+    #[test]
+    fn rule_path_filter_test() {
+        let sources = TelemetrySources::default();
+        let mut config = disk_full_rule();
+        config.path = Some("data".to_string());
+        let mut rule = AlertRule::compile(&config, &sources, 30).unwrap().unwrap();
+
+        let sample = HashMap::from([
+            ("freeBytes".to_string(), 5.0),
+            ("totalBytes".to_string(), 100.0),
+        ]);
+
+        assert_eq!(
+            rule.evaluate("diskFull", "boot", &sample, Instant::now()),
+            None
+        );
+        assert!(rule
+            .evaluate("diskFull", "data", &sample, Instant::now())
+            .is_some());
+    }
+
+    #[test]
+    fn compile_rules_test() {
+        let sources = TelemetrySources::default();
+
+        let incomplete = AlertRuleConfig {
+            expression: None,
+            ..disk_full_rule()
+        };
+        assert!(AlertRule::compile(&incomplete, &sources, 30)
+            .unwrap()
+            .is_none());
+
+        let unknown = AlertRuleConfig {
+            interface: Some("com.example.Unknown".to_string()),
+            ..disk_full_rule()
+        };
+        assert!(AlertRule::compile(&unknown, &sources, 30).is_err());
+
+        let invalid = AlertRuleConfig {
+            expression: Some("freeBytes <".to_string()),
+            ..disk_full_rule()
+        };
+        assert!(AlertRule::compile(&invalid, &sources, 30).is_err());
+    }
+
+    #[tokio::test]
+    async fn publish_events_test() {
+        let dir = TempDir::new("alerts").unwrap();
+        let alerts = alerts(vec![disk_full_rule()], &dir).await;
+        let start = Instant::now();
+
+        let mut publisher = MockPublisher::new();
+        expect_event(&mut publisher, "data", "Raised");
+        alerts
+            .evaluate(
+                &publisher,
+                STORAGE_USAGE,
+                &[storage_sample("data", 5), storage_sample("boot", 50)],
+                start,
+            )
+            .await;
+        publisher.checkpoint();
+
+        // not evaluated again before the end of its period
+        alerts
+            .evaluate(
+                &publisher,
+                STORAGE_USAGE,
+                &[storage_sample("data", 20)],
+                start + Duration::from_secs(10),
+            )
+            .await;
+
+        expect_event(&mut publisher, "data", "Cleared");
+        alerts
+            .evaluate(
+                &publisher,
+                STORAGE_USAGE,
+                &[storage_sample("data", 20)],
+                start + Duration::from_secs(30),
+            )
+            .await;
+        publisher.checkpoint();
+
+        // the rules of other interfaces are not evaluated
+        alerts
+            .evaluate(
+                &publisher,
+                MOUNT_POINT_USAGE,
+                &[TelemetryMessage {
+                    path: "data".to_string(),
+                    payload: TelemetryPayload::Properties(
+                        MOUNT_POINT_USAGE,
+                        HashMap::from([("/data/freeBytes".to_string(), AstarteType::Integer(0))]),
+                    ),
+                }],
+                start + Duration::from_secs(60),
+            )
+            .await;
+    }
+
+    #[tokio::test]
+    async fn evaluate_due_test() {
+        // the rule samples the interface on its own period, so a long telemetry period, an
+        // on_change policy or the interface being disabled don't delay the alert
+        let dir = TempDir::new("alerts").unwrap();
+        let rule = AlertRuleConfig {
+            name: "lowMemory".to_string(),
+            interface: Some(SYSTEM_STATUS.to_string()),
+            expression: Some("availMemoryBytes >= 0".to_string()),
+            period_seconds: Some(60),
+            cooldown_seconds: Some(3600),
+            ..Default::default()
+        };
+        let alerts = alerts(vec![rule], &dir).await;
+        let start = Instant::now();
+
+        let mut publisher = MockPublisher::new();
+        expect_rule_event(&mut publisher, "lowMemory", "", "Raised");
+        alerts.evaluate_due(&publisher, start).await;
+        publisher.checkpoint();
+
+        assert_eq!(
+            alerts.rules.read().await["lowMemory"].next_evaluation,
+            start + Duration::from_secs(60)
+        );
+        alerts
+            .evaluate_due(&publisher, start + Duration::from_secs(30))
+            .await;
+        alerts
+            .evaluate_due(&publisher, start + Duration::from_secs(60))
+            .await;
+        assert_eq!(
+            alerts.rules.read().await["lowMemory"].next_evaluation,
+            start + Duration::from_secs(120)
+        );
+    }
+
+    #[tokio::test]
+    async fn remote_rules_test() {
+        let dir = TempDir::new("alerts").unwrap();
+        let local = AlertRuleConfig {
+            expression: Some("freeBytes < 1".to_string()),
+            ..disk_full_rule()
+        };
+        let alerts = alerts(vec![local], &dir).await;
+
+        // the remote expression overrides the local one
+        alerts
+            .config_event(
+                "diskFull",
+                "expression",
+                &AstarteType::String("freeBytes / totalBytes * 100 < 10".to_string()),
+            )
+            .await;
+
+        let mut publisher = MockPublisher::new();
+        expect_event(&mut publisher, "data", "Raised");
+        alerts
+            .evaluate(
+                &publisher,
+                STORAGE_USAGE,
+                &[storage_sample("data", 5)],
+                Instant::now(),
+            )
+            .await;
+
+        // a rule defined only remotely, saved across restarts
+        alerts
+            .config_event(
+                "readOnly",
+                "interface",
+                &AstarteType::String(MOUNT_POINT_USAGE.to_string()),
+            )
+            .await;
+        alerts
+            .config_event(
+                "readOnly",
+                "expression",
+                &AstarteType::String("readOnly == true".to_string()),
+            )
+            .await;
+        alerts
+            .config_event("readOnly", "cooldownSeconds", &AstarteType::LongInteger(60))
+            .await;
+        alerts
+            .config_event("readOnly", "periodSeconds", &AstarteType::Integer(300))
+            .await;
+        alerts
+            .config_event("readOnly", "hysteresis", &AstarteType::Boolean(true))
+            .await;
+
+        let restarted = alerts(Vec::new(), &dir).await;
+        let rules = restarted.rules.read().await;
+        assert!(rules.contains_key("readOnly"));
+        assert_eq!(rules["readOnly"].cooldown, Duration::from_secs(60));
+        assert_eq!(rules["readOnly"].period, Duration::from_secs(300));
+        assert_eq!(rules["readOnly"].hysteresis, 0.0);
+        assert!(!rules.contains_key("diskFull"));
+        drop(rules);
+
+        // unsetting the expression removes the remote only rule
+        alerts
+            .config_event("readOnly", "expression", &AstarteType::Unset)
+            .await;
+        assert!(!alerts.rules.read().await.contains_key("readOnly"));
+    }
+}
@@ -0,0 +1,349 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! History of the device boots and detection of the unclean shutdowns.
+//!
+//! The runtime writes a shutdown marker in `store_directory` when it is terminated or before it
+//! reboots the device. On the first start after a boot, the marker tells whether the previous boot
+//! ended cleanly and why.
+
+use std::path::Path;
+
+use astarte_device_sdk::types::AstarteType;
+use astarte_device_sdk::AstarteAggregate;
+use chrono::{DateTime, TimeZone, Utc};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::error::DeviceManagerError;
+use crate::repository::file_state_repository::FileStateRepository;
+use crate::repository::StateRepository;
+use crate::telemetry::{base_image, os_info};
+
+const BOOT_HISTORY_PATH: &str = "boot_history.json";
+const SHUTDOWN_MARKER_PATH: &str = "shutdown_marker.json";
+const WATCHDOG_BOOT_STATUS_PATH: &str = "/sys/class/watchdog/watchdog0/bootstatus";
+const MAX_BOOT_RECORDS: usize = 20;
+// WDIOF_CARDRESET, the last reboot was caused by the watchdog
+const WATCHDOG_CARD_RESET: u32 = 0x20;
+
+/// Why the runtime stopped, or is about to reboot the device.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ShutdownReason {
+    /// The runtime was terminated, e.g. on a shutdown or reboot not requested to the runtime.
+    Shutdown,
+    /// Reboot to complete an OTA update.
+    Ota,
+    /// Reboot requested with `io.edgehog.devicemanager.Commands`.
+    Command,
+}
+
+impl ShutdownReason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ShutdownReason::Shutdown => "shutdown",
+            ShutdownReason::Ota => "ota",
+            ShutdownReason::Command => "command",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct ShutdownMarker {
+    boot_id: String,
+    reason: ShutdownReason,
+}
+
+/// Record of the current boot, for `io.edgehog.devicemanager.BootRecord`.
+#[derive(Debug, Clone, AstarteAggregate, PartialEq)]
+#[allow(non_snake_case)]
+pub struct BootRecord {
+    pub bootId: String,
+    pub startTime: DateTime<Utc>,
+    pub runtimeVersion: String,
+    pub osVersion: String,
+    pub baseImageVersion: String,
+    /// Whether the previous boot ended with the runtime terminated or rebooting the device.
+    pub previousShutdownClean: bool,
+    /// "Reason of the reboot, any of: ota, command, shutdown, watchdog, unknown, or empty for the first recorded boot"
+    pub rebootReason: String,
+}
+
+/// Writes the shutdown marker of the current boot.
+///
+/// A marker with a reboot reason is kept when the runtime is terminated by the reboot itself.
+pub async fn write_shutdown_marker(
+    store_directory: &str,
+    reason: ShutdownReason,
+) -> Result<(), DeviceManagerError> {
+    let boot_id = procfs::sys::kernel::random::boot_id()?;
+    let repository = marker_repository(store_directory);
+
+    if reason == ShutdownReason::Shutdown && repository.exists().await {
+        if let Ok(marker) = repository.read().await {
+            if marker.boot_id == boot_id {
+                debug!("keeping the {:?} shutdown marker", marker.reason);
+
+                return Ok(());
+            }
+        }
+    }
+
+    repository.write(&ShutdownMarker { boot_id, reason }).await
+}
+
+/// Records the current boot in the history, returning its record.
+pub async fn record_boot(store_directory: &str) -> Result<BootRecord, DeviceManagerError> {
+    let boot_id = procfs::sys::kernel::random::boot_id()?;
+    let start_time = Utc
+        .timestamp_opt(procfs::boot_time_secs()? as i64, 0)
+        .single()
+        .unwrap_or_else(Utc::now);
+
+    let (previous_shutdown_clean, reboot_reason) = update_history(
+        store_directory,
+        &boot_id,
+        start_time,
+        Path::new(WATCHDOG_BOOT_STATUS_PATH),
+    )
+    .await?;
+
+    let os_version = os_info::get_os_info()
+        .await
+        .ok()
+        .and_then(|os_info| get_string(os_info.get("/osVersion")));
+    let base_image_version = base_image::get_base_image()
+        .await
+        .ok()
+        .and_then(|base_image| get_string(base_image.get("/version")));
+
+    Ok(BootRecord {
+        bootId: boot_id,
+        startTime: start_time,
+        runtimeVersion: env!("CARGO_PKG_VERSION").to_string(),
+        osVersion: os_version.unwrap_or_default(),
+        baseImageVersion: base_image_version.unwrap_or_default(),
+        previousShutdownClean: previous_shutdown_clean,
+        rebootReason: reboot_reason,
+    })
+}
+
+/// Adds the boot to the history if it's a new one, returning whether the previous boot ended
+/// cleanly and the reboot reason.
+///
+/// When the runtime is restarted during the same boot, the marker is removed since the boot is
+/// not over, and the values computed at the first start are returned.
+async fn update_history(
+    store_directory: &str,
+    boot_id: &str,
+    start_time: DateTime<Utc>,
+    watchdog_boot_status: &Path,
+) -> Result<(bool, String), DeviceManagerError> {
+    let history_repository: Box<dyn StateRepository<Vec<BootHistoryEntry>>> = Box::new(
+        FileStateRepository::new(store_directory.to_string(), BOOT_HISTORY_PATH.to_string()),
+    );
+    let marker_repository = marker_repository(store_directory);
+
+    let mut history: Vec<BootHistoryEntry> = if history_repository.exists().await {
+        history_repository.read().await.unwrap_or_else(|err| {
+            warn!("couldn't read the boot history, starting a new one: {err}");
+
+            Vec::new()
+        })
+    } else {
+        Vec::new()
+    };
+
+    let marker = if marker_repository.exists().await {
+        let marker = marker_repository.read().await.ok();
+        marker_repository.clear().await?;
+        marker
+    } else {
+        None
+    };
+
+    if let Some(current) = history.last().filter(|last| last.boot_id == boot_id) {
+        debug!("runtime restarted in the same boot");
+
+        return Ok((
+            current.previous_shutdown_clean,
+            current.reboot_reason.clone(),
+        ));
+    }
+
+    let (previous_shutdown_clean, reboot_reason) = match (history.last(), marker) {
+        (None, _) => (true, String::new()),
+        (Some(previous), Some(marker)) if marker.boot_id == previous.boot_id => {
+            (true, marker.reason.as_str().to_string())
+        }
+        (Some(_), _) if is_watchdog_reset(watchdog_boot_status) => (false, "watchdog".to_string()),
+        (Some(_), _) => (false, "unknown".to_string()),
+    };
+
+    info!("new boot {boot_id}, reboot reason '{reboot_reason}'");
+
+    history.push(BootHistoryEntry {
+        boot_id: boot_id.to_string(),
+        start_time: start_time.timestamp(),
+        previous_shutdown_clean,
+        reboot_reason: reboot_reason.clone(),
+    });
+
+    let excess = history.len().saturating_sub(MAX_BOOT_RECORDS);
+    history.drain(..excess);
+
+    history_repository.write(&history).await?;
+
+    Ok((previous_shutdown_clean, reboot_reason))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct BootHistoryEntry {
+    boot_id: String,
+    /// Unix timestamp of the boot.
+    start_time: i64,
+    previous_shutdown_clean: bool,
+    reboot_reason: String,
+}
+
+fn marker_repository(store_directory: &str) -> Box<dyn StateRepository<ShutdownMarker>> {
+    Box::new(FileStateRepository::new(
+        store_directory.to_string(),
+        SHUTDOWN_MARKER_PATH.to_string(),
+    ))
+}
+
+fn is_watchdog_reset(boot_status_path: &Path) -> bool {
+    std::fs::read_to_string(boot_status_path)
+        .ok()
+        .and_then(|status| status.trim().parse::<u32>().ok())
+        .map_or(false, |status| status & WATCHDOG_CARD_RESET != 0)
+}
+
+fn get_string(value: Option<&AstarteType>) -> Option<String> {
+    match value {
+        Some(AstarteType::String(value)) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::boot_history::{
+        marker_repository, update_history, BootHistoryEntry, ShutdownMarker, ShutdownReason,
+        BOOT_HISTORY_PATH, MAX_BOOT_RECORDS,
+    };
+    use crate::repository::file_state_repository::FileStateRepository;
+    use crate::repository::StateRepository;
+    use chrono::Utc;
+    use std::path::Path;
+    use tempdir::TempDir;
+
+    const NO_WATCHDOG: &str = "/nonexistent/bootstatus";
+
+    async fn boot(store_directory: &str, boot_id: &str) -> (bool, String) {
+        update_history(store_directory, boot_id, Utc::now(), Path::new(NO_WATCHDOG))
+            .await
+            .unwrap()
+    }
+
+    async fn write_marker(store_directory: &str, boot_id: &str, reason: ShutdownReason) {
+        marker_repository(store_directory)
+            .write(&ShutdownMarker {
+                boot_id: boot_id.to_string(),
+                reason,
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn boot_history_test() {
+        let dir = TempDir::new("boot_history").unwrap();
+        let store = dir.path().to_str().unwrap();
+
+        assert_eq!(boot(store, "boot-1").await, (true, String::new()));
+
+        // clean reboot requested with a command
+        write_marker(store, "boot-1", ShutdownReason::Command).await;
+        assert_eq!(boot(store, "boot-2").await, (true, "command".to_string()));
+
+        // power loss
+        assert_eq!(boot(store, "boot-3").await, (false, "unknown".to_string()));
+
+        // runtime restarted in the same boot, the marker of the restart is discarded
+        write_marker(store, "boot-3", ShutdownReason::Shutdown).await;
+        assert_eq!(boot(store, "boot-3").await, (false, "unknown".to_string()));
+        assert_eq!(boot(store, "boot-4").await, (false, "unknown".to_string()));
+
+        // stale marker of an older boot
+        write_marker(store, "boot-3", ShutdownReason::Ota).await;
+        assert_eq!(boot(store, "boot-5").await, (false, "unknown".to_string()));
+
+        write_marker(store, "boot-5", ShutdownReason::Ota).await;
+        assert_eq!(boot(store, "boot-6").await, (true, "ota".to_string()));
+
+        let history: Vec<BootHistoryEntry> =
+            FileStateRepository::new(store.to_string(), BOOT_HISTORY_PATH.to_string())
+                .read()
+                .await
+                .unwrap();
+        let boot_ids: Vec<&str> = history.iter().map(|entry| entry.boot_id.as_str()).collect();
+        assert_eq!(
+            boot_ids,
+            ["boot-1", "boot-2", "boot-3", "boot-4", "boot-5", "boot-6"]
+        );
+    }
+
+    #[tokio::test]
+    async fn watchdog_reset_test() {
+        let dir = TempDir::new("boot_history").unwrap();
+        let store = dir.path().to_str().unwrap();
+        let boot_status = dir.path().join("bootstatus");
+        std::fs::write(&boot_status, "32\n").unwrap();
+
+        boot(store, "boot-1").await;
+        let res = update_history(store, "boot-2", Utc::now(), &boot_status)
+            .await
+            .unwrap();
+
+        assert_eq!(res, (false, "watchdog".to_string()));
+    }
+
+    #[tokio::test]
+    async fn boot_history_limit_test() {
+        let dir = TempDir::new("boot_history").unwrap();
+        let store = dir.path().to_str().unwrap();
+
+        for i in 0..MAX_BOOT_RECORDS + 5 {
+            boot(store, &format!("boot-{i}")).await;
+        }
+
+        let history: Vec<BootHistoryEntry> =
+            FileStateRepository::new(store.to_string(), BOOT_HISTORY_PATH.to_string())
+                .read()
+                .await
+                .unwrap();
+
+        assert_eq!(history.len(), MAX_BOOT_RECORDS);
+        assert_eq!(history[0].boot_id, "boot-5");
+    }
+}
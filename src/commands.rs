@@ -18,14 +18,39 @@
  * SPDX-License-Identifier: Apache-2.0
  */
 
-use log::error;
+use std::sync::Arc;
+
+use log::{error, warn};
+
+use crate::boot_history::{write_shutdown_marker, ShutdownReason};
+use crate::data::Publisher;
+use crate::diagnostics::Diagnostics;
 
 /// handle io.edgehog.devicemanager.Commands
-pub(crate) async fn execute_command(command: &str) {
+pub(crate) async fn execute_command<P>(
+    publisher: &P,
+    command: &str,
+    store_directory: &str,
+    diagnostics: &Arc<Diagnostics>,
+) where
+    P: Publisher + Clone + 'static,
+{
     match command {
         "Reboot" => {
+            if let Err(err) = write_shutdown_marker(store_directory, ShutdownReason::Command).await
+            {
+                warn!("couldn't record the reboot reason: {err}");
+            }
+
             crate::power_management::reboot().await.unwrap();
         }
+        "RunDiagnostics" => {
+            let publisher = publisher.clone();
+            let diagnostics = diagnostics.clone();
+            tokio::spawn(async move {
+                diagnostics.run_and_publish(&publisher).await;
+            });
+        }
         _ => {
             error!("command not recognized");
         }
@@ -24,6 +24,7 @@ use astarte_device_sdk::{
     registration, AstarteAggregate, AstarteDeviceDataEvent, AstarteDeviceSdk, AstarteError,
 };
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
 
 use crate::data::{Publisher, Subscriber};
@@ -62,6 +63,21 @@ impl Publisher for AstarteDeviceSdkLib {
             .await
     }
 
+    async fn send_object_with_timestamp<T: 'static>(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        data: T,
+        timestamp: DateTime<Utc>,
+    ) -> Result<(), AstarteError>
+    where
+        T: AstarteAggregate + Send,
+    {
+        self.device_sdk
+            .send_object_with_timestamp(interface_name, interface_path, data, timestamp)
+            .await
+    }
+
     async fn send(
         &self,
         interface_name: &str,
@@ -177,15 +193,133 @@ async fn get_credentials_secret_from_registration(
     }
 }
 
+/// Looks up the MQTT broker of the device on the Astarte pairing API.
+#[derive(Debug, Clone)]
+pub struct BrokerLookup {
+    sdk_options: AstarteDeviceSdkConfigOptions,
+    store_directory: String,
+    ignore_ssl: bool,
+}
+
+impl BrokerLookup {
+    /// Returns `None` when the runtime doesn't connect to Astarte through the device SDK.
+    pub fn new(opts: &DeviceManagerOptions) -> Option<Self> {
+        let sdk_options = opts.astarte_device_sdk.clone()?;
+
+        Some(BrokerLookup {
+            sdk_options,
+            store_directory: opts.store_directory.clone(),
+            ignore_ssl: opts.astarte_ignore_ssl == Some(true),
+        })
+    }
+
+    /// Address of the broker, as `host:port`.
+    pub async fn broker_address(&self) -> Result<String, DeviceManagerError> {
+        let device_id = get_device_id(self.sdk_options.device_id.clone()).await?;
+
+        let credentials_secret: String = match &self.sdk_options.credentials_secret {
+            Some(secret) if !secret.is_empty() => secret.clone(),
+            _ => {
+                FileStateRepository::new(
+                    self.store_directory.clone(),
+                    format!("credentials_{}.json", device_id),
+                )
+                .read()
+                .await?
+            }
+        };
+
+        let url = format!(
+            "{}/v1/{}/devices/{}",
+            self.sdk_options.pairing_url.trim_end_matches('/'),
+            self.sdk_options.realm,
+            device_id
+        );
+        let status = reqwest::Client::builder()
+            .danger_accept_invalid_certs(self.ignore_ssl)
+            .build()?
+            .get(url)
+            .bearer_auth(credentials_secret)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        parse_broker_address(&status)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceStatus {
+    data: DeviceStatusData,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceStatusData {
+    protocols: DeviceProtocols,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceProtocols {
+    astarte_mqtt_v1: AstarteMqttV1,
+}
+
+#[derive(Debug, Deserialize)]
+struct AstarteMqttV1 {
+    broker_url: String,
+}
+
+/// Reads the broker address from the device status returned by the pairing API.
+fn parse_broker_address(status: &str) -> Result<String, DeviceManagerError> {
+    let status: DeviceStatus = serde_json::from_str(status)?;
+    let broker_url = status.data.protocols.astarte_mqtt_v1.broker_url;
+
+    let url = reqwest::Url::parse(&broker_url)
+        .map_err(|err| DeviceManagerError::FatalError(format!("invalid broker url: {err}")))?;
+    let port = url.port().or(match url.scheme() {
+        "mqtts" => Some(8883),
+        "mqtt" => Some(1883),
+        _ => None,
+    });
+
+    match (url.host_str(), port) {
+        (Some(host), Some(port)) => Ok(format!("{host}:{port}")),
+        _ => Err(DeviceManagerError::FatalError(format!(
+            "invalid broker url: {broker_url}"
+        ))),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::data::astarte_device_sdk_lib::{
         get_credentials_secret, get_credentials_secret_from_registration, get_device_id,
-        AstarteDeviceSdkConfigOptions,
+        parse_broker_address, AstarteDeviceSdkConfigOptions,
     };
     use crate::repository::MockStateRepository;
     use crate::{AstarteLibrary, DeviceManagerError, DeviceManagerOptions};
 
+    #[test]
+    fn parse_broker_address_test() {
+        let status = |broker_url: &str| {
+            format!(
+                r#"{{"data": {{"protocols": {{"astarte_mqtt_v1": {{"broker_url": "{broker_url}"}}}}}}}}"#
+            )
+        };
+
+        assert_eq!(
+            parse_broker_address(&status("mqtts://broker.astarte.example.com:8884/")).unwrap(),
+            "broker.astarte.example.com:8884"
+        );
+        assert_eq!(
+            parse_broker_address(&status("mqtts://broker.astarte.example.com/")).unwrap(),
+            "broker.astarte.example.com:8883"
+        );
+        assert!(parse_broker_address(&status("broker.astarte.example.com")).is_err());
+        assert!(parse_broker_address("{}").is_err());
+    }
+
     #[tokio::test]
     async fn device_id_test() {
         assert_eq!(
@@ -212,6 +346,17 @@ mod tests {
             download_directory: "".to_string(),
             astarte_ignore_ssl: Some(false),
             telemetry_config: Some(vec![]),
+            gnss: None,
+            battery: None,
+            exec_sources: None,
+            storage_usage: None,
+            systemd: None,
+            process_watch: None,
+            network_config: None,
+            diagnostics: None,
+            telemetry_period_limits: None,
+            metrics: None,
+            alerts: None,
         };
         assert_eq!(
             get_credentials_secret(
@@ -244,6 +389,17 @@ mod tests {
             download_directory: "".to_string(),
             astarte_ignore_ssl: Some(false),
             telemetry_config: Some(vec![]),
+            gnss: None,
+            battery: None,
+            exec_sources: None,
+            storage_usage: None,
+            systemd: None,
+            process_watch: None,
+            network_config: None,
+            diagnostics: None,
+            telemetry_period_limits: None,
+            metrics: None,
+            alerts: None,
         };
         assert!(get_credentials_secret(
             "device_id",
@@ -278,6 +434,17 @@ mod tests {
             download_directory: "".to_string(),
             astarte_ignore_ssl: Some(false),
             telemetry_config: Some(vec![]),
+            gnss: None,
+            battery: None,
+            exec_sources: None,
+            storage_usage: None,
+            systemd: None,
+            process_watch: None,
+            network_config: None,
+            diagnostics: None,
+            telemetry_period_limits: None,
+            metrics: None,
+            alerts: None,
         };
 
         assert!(get_credentials_secret(
@@ -312,6 +479,17 @@ mod tests {
             download_directory: "".to_string(),
             astarte_ignore_ssl: Some(false),
             telemetry_config: Some(vec![]),
+            gnss: None,
+            battery: None,
+            exec_sources: None,
+            storage_usage: None,
+            systemd: None,
+            process_watch: None,
+            network_config: None,
+            diagnostics: None,
+            telemetry_period_limits: None,
+            metrics: None,
+            alerts: None,
         };
 
         assert!(get_credentials_secret(
@@ -340,6 +518,17 @@ mod tests {
             astarte_ignore_ssl: Some(false),
             telemetry_config: Some(vec![]),
             astarte_message_hub: None,
+            gnss: None,
+            battery: None,
+            exec_sources: None,
+            storage_usage: None,
+            systemd: None,
+            process_watch: None,
+            network_config: None,
+            diagnostics: None,
+            telemetry_period_limits: None,
+            metrics: None,
+            alerts: None,
         };
 
         let state_mock = MockStateRepository::<String>::new();
@@ -101,13 +101,14 @@ impl AstarteMessageHubNode {
         interface_name: &str,
         interface_path: &str,
         payload: proto_message_hub::astarte_message::Payload,
+        timestamp: Option<pbjson_types::Timestamp>,
     ) -> Result<(), astarte_device_sdk::AstarteError> {
         use proto_message_hub::message_hub_client::MessageHubClient;
 
         let astarte_message = proto_message_hub::AstarteMessage {
             interface_name: interface_name.to_string(),
             path: interface_path.to_string(),
-            timestamp: None,
+            timestamp,
             payload: Some(payload),
         };
 
@@ -139,7 +140,31 @@ impl Publisher for AstarteMessageHubNode {
             .try_into()
             .map_err(|_| astarte_device_sdk::AstarteError::Conversion)?;
 
-        self.send_payload(interface_name, interface_path, payload)
+        self.send_payload(interface_name, interface_path, payload, None)
+            .await
+    }
+
+    async fn send_object_with_timestamp<T: 'static>(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        data: T,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    ) -> Result<(), astarte_device_sdk::AstarteError>
+    where
+        T: astarte_device_sdk::AstarteAggregate + Send,
+    {
+        let payload: proto_message_hub::astarte_message::Payload = data
+            .astarte_aggregate()?
+            .try_into()
+            .map_err(|_| astarte_device_sdk::AstarteError::Conversion)?;
+
+        let timestamp = pbjson_types::Timestamp {
+            seconds: timestamp.timestamp(),
+            nanos: timestamp.timestamp_subsec_nanos() as i32,
+        };
+
+        self.send_payload(interface_name, interface_path, payload, Some(timestamp))
             .await
     }
 
@@ -155,7 +180,7 @@ impl Publisher for AstarteMessageHubNode {
             .try_into()
             .map_err(|_| astarte_device_sdk::AstarteError::Conversion)?;
 
-        self.send_payload(interface_name, interface_path, payload)
+        self.send_payload(interface_name, interface_path, payload, None)
             .await
     }
 }
@@ -21,6 +21,7 @@
 use astarte_device_sdk::types::AstarteType;
 use astarte_device_sdk::{AstarteAggregate, AstarteDeviceDataEvent, AstarteError};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 #[cfg(test)]
 use mockall::automock;
 
@@ -38,7 +39,15 @@ pub trait Publisher: Send + Sync {
     ) -> Result<(), AstarteError>
     where
         T: AstarteAggregate + Send;
-    //TODO add send_object_with_timestamp to this trait
+    async fn send_object_with_timestamp<T: 'static>(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        data: T,
+        timestamp: DateTime<Utc>,
+    ) -> Result<(), AstarteError>
+    where
+        T: AstarteAggregate + Send;
     async fn send(
         &self,
         interface_name: &str,
@@ -0,0 +1,509 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Connectivity diagnostics run by the `RunDiagnostics` command of
+//! `io.edgehog.devicemanager.Commands`.
+
+use std::future::Future;
+use std::io;
+use std::mem::size_of;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use astarte_device_sdk::AstarteAggregate;
+use log::{error, info};
+use nix::libc;
+use serde::Deserialize;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::{sleep, timeout, Duration, Instant};
+use uuid::Uuid;
+
+use crate::data::astarte_device_sdk_lib::BrokerLookup;
+use crate::data::Publisher;
+use crate::telemetry::net_if_properties::get_default_route_interfaces;
+
+const DIAGNOSTICS_REPORT_INTERFACE: &str = "io.edgehog.devicemanager.DiagnosticsReport";
+const IPV4_UDP_HEADERS: usize = 28;
+const IPV6_UDP_HEADERS: usize = 48;
+// largest UDP payload, the loopback MTU is larger than an IP packet
+const MAX_UDP_PAYLOAD: usize = 65507;
+// each probe can lower the path MTU by one hop
+const MTU_PROBES: usize = 8;
+// time for the ICMP answers to a probe to arrive
+const MTU_PROBE_WAIT: Duration = Duration::from_millis(500);
+
+/// Configuration of the connectivity diagnostics.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct DiagnosticsOptions {
+    /// Host of the Astarte pairing API, by default the one of `astarte_device_sdk.pairing_url`.
+    pub pairing_host: Option<String>,
+    /// Address of the MQTT broker, as `host:port`, by default read from the Astarte pairing API.
+    pub broker_address: Option<String>,
+    /// Addresses of the OTA servers, as `host:port`.
+    #[serde(default)]
+    pub ota_addresses: Vec<String>,
+    /// Timeout of each check.
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+fn default_timeout_seconds() -> u64 {
+    5
+}
+
+impl Default for DiagnosticsOptions {
+    fn default() -> Self {
+        DiagnosticsOptions {
+            pairing_host: None,
+            broker_address: None,
+            ota_addresses: Vec::new(),
+            timeout_seconds: default_timeout_seconds(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, AstarteAggregate, PartialEq)]
+#[allow(non_snake_case)]
+pub struct DiagnosticResult {
+    /// Identifies the results of the same run.
+    pub runId: String,
+    /// "Any of: defaultRoute, dns, tcp, mtu"
+    pub check: String,
+    pub target: String,
+    pub passed: bool,
+    pub durationMillis: i32,
+    /// Outcome of the check, e.g. the resolved addresses or the error.
+    pub detail: String,
+}
+
+#[derive(Debug)]
+pub struct Diagnostics {
+    options: DiagnosticsOptions,
+    broker_lookup: Option<BrokerLookup>,
+}
+
+impl Diagnostics {
+    pub fn new(
+        mut options: DiagnosticsOptions,
+        pairing_url: Option<&str>,
+        broker_lookup: Option<BrokerLookup>,
+    ) -> Self {
+        if options.pairing_host.is_none() {
+            options.pairing_host = pairing_url
+                .and_then(|url| reqwest::Url::parse(url).ok())
+                .and_then(|url| url.host_str().map(str::to_string));
+        }
+
+        Diagnostics {
+            options,
+            broker_lookup,
+        }
+    }
+
+    /// Runs the checks and publishes their results.
+    pub async fn run_and_publish(&self, publisher: &impl Publisher) {
+        for result in self.run().await {
+            info!(
+                "diagnostic {} {}: {} ({})",
+                result.check, result.target, result.passed, result.detail
+            );
+
+            if let Err(err) = publisher
+                .send_object(DIAGNOSTICS_REPORT_INTERFACE, "/result", result)
+                .await
+            {
+                error!("couldn't publish the diagnostic result: {err}");
+            }
+        }
+    }
+
+    async fn run(&self) -> Vec<DiagnosticResult> {
+        let run_id = Uuid::new_v4().to_string();
+        let mut results = Vec::new();
+
+        results.push(
+            self.run_check(&run_id, "defaultRoute", "", async {
+                let mut interfaces: Vec<String> =
+                    get_default_route_interfaces().into_iter().collect();
+                interfaces.sort();
+
+                if interfaces.is_empty() {
+                    Err("no default route".to_string())
+                } else {
+                    Ok(interfaces.join(" "))
+                }
+            })
+            .await,
+        );
+
+        if let Some(host) = &self.options.pairing_host {
+            results.push(self.run_check(&run_id, "dns", host, check_dns(host)).await);
+        }
+
+        let broker_address = match self.broker_address().await {
+            Ok(address) => address,
+            Err(err) => {
+                results.push(
+                    self.run_check(&run_id, "tcp", "broker", async { Err(err) })
+                        .await,
+                );
+
+                None
+            }
+        };
+
+        let tcp_addresses = broker_address.iter().chain(&self.options.ota_addresses);
+        for address in tcp_addresses {
+            results.push(
+                self.run_check(&run_id, "tcp", address, check_tcp(address))
+                    .await,
+            );
+        }
+
+        let mtu_address = broker_address
+            .as_ref()
+            .or_else(|| self.options.ota_addresses.first());
+        if let Some(address) = mtu_address {
+            results.push(
+                self.run_check(&run_id, "mtu", address, check_mtu(address))
+                    .await,
+            );
+        }
+
+        results
+    }
+
+    /// Returns the configured broker address or looks it up, `None` if it is unknown.
+    async fn broker_address(&self) -> Result<Option<String>, String> {
+        if let Some(address) = &self.options.broker_address {
+            return Ok(Some(address.clone()));
+        }
+
+        match &self.broker_lookup {
+            Some(lookup) => lookup
+                .broker_address()
+                .await
+                .map(Some)
+                .map_err(|err| format!("couldn't look up the broker address: {err}")),
+            None => Ok(None),
+        }
+    }
+
+    async fn run_check<F>(
+        &self,
+        run_id: &str,
+        check: &str,
+        target: &str,
+        probe: F,
+    ) -> DiagnosticResult
+    where
+        F: Future<Output = Result<String, String>>,
+    {
+        let start = Instant::now();
+
+        let res = timeout(Duration::from_secs(self.options.timeout_seconds), probe)
+            .await
+            .unwrap_or_else(|_| Err("timed out".to_string()));
+
+        let (passed, detail) = match res {
+            Ok(detail) => (true, detail),
+            Err(err) => (false, err),
+        };
+
+        DiagnosticResult {
+            runId: run_id.to_string(),
+            check: check.to_string(),
+            target: target.to_string(),
+            passed,
+            durationMillis: i32::try_from(start.elapsed().as_millis()).unwrap_or(i32::MAX),
+            detail,
+        }
+    }
+}
+
+/// Resolves the host, returning its addresses.
+async fn check_dns(host: &str) -> Result<String, String> {
+    let addresses: Vec<String> = tokio::net::lookup_host((host, 0))
+        .await
+        .map_err(|err| err.to_string())?
+        .map(|address| address.ip().to_string())
+        .collect();
+
+    if addresses.is_empty() {
+        return Err("no addresses".to_string());
+    }
+
+    Ok(addresses.join(" "))
+}
+
+/// Connects to the address, returning the connected one.
+async fn check_tcp(address: &str) -> Result<String, String> {
+    let stream = TcpStream::connect(address)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    stream
+        .peer_addr()
+        .map(|address| address.to_string())
+        .map_err(|err| err.to_string())
+}
+
+/// Probes the path MTU towards the address with UDP datagrams that can't be fragmented.
+///
+/// The probes start from the MTU of the route and get smaller each time a router on the path
+/// answers that it can't forward them, lowering the path MTU known by the kernel. The probe is
+/// confirmed when the host itself answers, usually with a port unreachable.
+async fn check_mtu(address: &str) -> Result<String, String> {
+    let address = tokio::net::lookup_host(address)
+        .await
+        .map_err(|err| err.to_string())?
+        .next()
+        .ok_or_else(|| "no addresses".to_string())?;
+
+    let (local, headers, ipv6) = match address {
+        SocketAddr::V4(_) => (
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            IPV4_UDP_HEADERS,
+            false,
+        ),
+        SocketAddr::V6(_) => (
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+            IPV6_UDP_HEADERS,
+            true,
+        ),
+    };
+
+    let socket = UdpSocket::bind(local)
+        .await
+        .map_err(|err| err.to_string())?;
+    socket
+        .connect(address)
+        .await
+        .map_err(|err| err.to_string())?;
+    set_dont_fragment(socket.as_raw_fd(), ipv6).map_err(|err| err.to_string())?;
+
+    let route_mtu = path_mtu(socket.as_raw_fd(), ipv6).map_err(|err| err.to_string())?;
+    let mut mtu = route_mtu;
+    let mut confirmed = false;
+    for _ in 0..MTU_PROBES {
+        let size = (mtu as usize).saturating_sub(headers).min(MAX_UDP_PAYLOAD);
+        match socket.send(&vec![0; size]).await {
+            Ok(_) => {}
+            // lowered by the answer to the previous probe
+            Err(err) if err.raw_os_error() == Some(libc::EMSGSIZE) => {
+                mtu = path_mtu(socket.as_raw_fd(), ipv6).map_err(|err| err.to_string())?;
+                continue;
+            }
+            Err(err) if err.raw_os_error() == Some(libc::ECONNREFUSED) => {
+                confirmed = true;
+                break;
+            }
+            Err(err) => return Err(err.to_string()),
+        }
+
+        sleep(MTU_PROBE_WAIT).await;
+
+        let current = path_mtu(socket.as_raw_fd(), ipv6).map_err(|err| err.to_string())?;
+        if current == mtu {
+            confirmed = matches!(
+                socket.take_error(),
+                Ok(Some(err)) if err.raw_os_error() == Some(libc::ECONNREFUSED)
+            );
+            break;
+        }
+        mtu = current;
+    }
+
+    let outcome = if confirmed {
+        "confirmed by the host"
+    } else {
+        "not confirmed by the host"
+    };
+
+    Ok(format!("path mtu {mtu}, route mtu {route_mtu}, {outcome}"))
+}
+
+/// Forbids the fragmentation of the datagrams sent on the socket, so the ones larger than the
+/// path MTU are answered with an ICMP error by the routers.
+fn set_dont_fragment(fd: RawFd, ipv6: bool) -> io::Result<()> {
+    let (level, name, value) = if ipv6 {
+        (
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_DO,
+        )
+    } else {
+        (
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            libc::IP_PMTUDISC_DO,
+        )
+    };
+
+    // SAFETY: the option value is a c_int valid for the whole call, and its size is passed
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Path MTU known by the kernel towards the peer of a connected socket.
+fn path_mtu(fd: RawFd, ipv6: bool) -> io::Result<u32> {
+    let (level, name) = if ipv6 {
+        (libc::IPPROTO_IPV6, libc::IPV6_MTU)
+    } else {
+        (libc::IPPROTO_IP, libc::IP_MTU)
+    };
+
+    let mut value: libc::c_int = 0;
+    let mut len = size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: the option value is a c_int valid for the whole call, and its size is passed
+    let res = unsafe {
+        libc::getsockopt(
+            fd,
+            level,
+            name,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(value as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use crate::diagnostics::{check_dns, check_mtu, check_tcp, Diagnostics, DiagnosticsOptions};
+
+    /// Local stand-in for a remote server, accepting the connections in background.
+    async fn stand_in_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                drop(stream);
+            }
+        });
+
+        address
+    }
+
+    /// Address where nothing is listening.
+    async fn closed_address() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn check_dns_test() {
+        let addresses = check_dns("localhost").await.unwrap();
+        assert!(!addresses.is_empty());
+
+        assert!(check_dns("nonexistent.invalid").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn check_tcp_test() {
+        let address = stand_in_server().await;
+        assert_eq!(check_tcp(&address).await.unwrap(), address);
+
+        assert!(check_tcp(&closed_address().await).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn check_mtu_test() {
+        // nothing listens on the UDP port, so the host answers with a port unreachable
+        let detail = check_mtu(&closed_address().await).await.unwrap();
+        assert!(detail.starts_with("path mtu "));
+        assert!(detail.ends_with(", confirmed by the host"));
+
+        assert!(check_mtu("nonexistent.invalid:1883").await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn check_timeout_test() {
+        let diagnostics = Diagnostics::new(DiagnosticsOptions::default(), None, None);
+
+        let result = diagnostics
+            .run_check(
+                "run",
+                "tcp",
+                "blackhole",
+                std::future::pending::<Result<String, String>>(),
+            )
+            .await;
+
+        assert!(!result.passed);
+        assert_eq!(result.detail, "timed out");
+    }
+
+    #[tokio::test]
+    async fn run_test() {
+        let broker = stand_in_server().await;
+        let ota = closed_address().await;
+        let options = DiagnosticsOptions {
+            broker_address: Some(broker.clone()),
+            ota_addresses: vec![ota.clone()],
+            ..Default::default()
+        };
+
+        let diagnostics = Diagnostics::new(options, Some("http://localhost:4003"), None);
+        let results = diagnostics.run().await;
+
+        let checks: Vec<(&str, &str)> = results
+            .iter()
+            .map(|result| (result.check.as_str(), result.target.as_str()))
+            .collect();
+        assert_eq!(
+            checks,
+            [
+                ("defaultRoute", ""),
+                ("dns", "localhost"),
+                ("tcp", broker.as_str()),
+                ("tcp", ota.as_str()),
+                ("mtu", broker.as_str()),
+            ]
+        );
+        assert!(results
+            .iter()
+            .all(|result| result.runId == results[0].runId));
+        assert!(results[1].passed);
+        assert!(results[2].passed);
+        assert!(!results[3].passed);
+        assert!(results[4].passed);
+    }
+}
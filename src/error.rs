@@ -63,4 +63,28 @@ pub enum DeviceManagerError {
 
     #[error(transparent)]
     TonicStatus(#[from] tonic::Status),
+
+    #[error("gpsd error ({0})")]
+    GpsdError(String),
+
+    #[error("exec telemetry source error ({0})")]
+    ExecSourceError(String),
+
+    #[error("package inventory error ({0})")]
+    PackageInventoryError(String),
+
+    #[error("network configuration error ({0})")]
+    NetworkConfigError(String),
+
+    #[error("time settings error ({0})")]
+    TimeSettingsError(String),
+
+    #[error("invalid cron expression ({0})")]
+    CronError(String),
+
+    #[error(transparent)]
+    HyperError(#[from] hyper::Error),
+
+    #[error("alert rule error ({0})")]
+    AlertRuleError(String),
 }
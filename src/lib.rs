@@ -21,7 +21,7 @@
 use std::sync::Arc;
 
 use astarte_device_sdk::types::AstarteType;
-use astarte_device_sdk::{Aggregation, AstarteDeviceDataEvent};
+use astarte_device_sdk::{Aggregation, AstarteDeviceDataEvent, AstarteError};
 use log::{debug, info, warn};
 use serde::Deserialize;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
@@ -31,21 +31,30 @@ use crate::data::{Publisher, Subscriber};
 use crate::device::DeviceProxy;
 use crate::error::DeviceManagerError;
 use crate::ota::ota_handler::OtaHandler;
+use crate::telemetry::exec_source::ExecValue;
 use crate::telemetry::{TelemetryMessage, TelemetryPayload};
 
+mod alerts;
+pub mod boot_history;
 mod commands;
 pub mod data;
 mod device;
+mod diagnostics;
 pub mod error;
 mod led_behavior;
+mod metrics;
+mod network_config;
+mod network_manager;
 mod ota;
 mod power_management;
 pub mod repository;
 #[cfg(feature = "systemd")]
 pub mod systemd_wrapper;
 mod telemetry;
+mod time_settings;
 
 const MAX_OTA_OPERATION: usize = 2;
+const HARDWARE_INFO_INTERFACE: &str = "io.edgehog.devicemanager.HardwareInfo";
 
 #[derive(Deserialize, Debug, Clone)]
 pub enum AstarteLibrary {
@@ -65,6 +74,17 @@ pub struct DeviceManagerOptions {
     pub download_directory: String,
     pub astarte_ignore_ssl: Option<bool>,
     pub telemetry_config: Option<Vec<telemetry::TelemetryInterfaceConfig>>,
+    pub telemetry_period_limits: Option<telemetry::PeriodLimits>,
+    pub gnss: Option<telemetry::gnss::GnssOptions>,
+    pub battery: Option<telemetry::battery_status::BatteryOptions>,
+    pub exec_sources: Option<Vec<telemetry::exec_source::ExecSourceOptions>>,
+    pub storage_usage: Option<telemetry::storage_usage::StorageUsageOptions>,
+    pub systemd: Option<telemetry::systemd_units::SystemdUnitsOptions>,
+    pub process_watch: Option<telemetry::process_watch::ProcessWatchOptions>,
+    pub network_config: Option<network_config::NetworkConfigOptions>,
+    pub diagnostics: Option<diagnostics::DiagnosticsOptions>,
+    pub metrics: Option<metrics::MetricsOptions>,
+    pub alerts: Option<alerts::AlertsOptions>,
 }
 
 pub struct DeviceManager<T: Publisher + Subscriber + Clone> {
@@ -73,6 +93,9 @@ pub struct DeviceManager<T: Publisher + Subscriber + Clone> {
     ota_event_channel: Sender<AstarteDeviceDataEvent>,
     data_event_channel: Sender<AstarteDeviceDataEvent>,
     telemetry: Arc<RwLock<telemetry::Telemetry>>,
+    alerts: Arc<alerts::Alerts>,
+    metrics: Option<Arc<metrics::Metrics>>,
+    store_directory: String,
 }
 
 impl<T: Publisher + Subscriber + Clone + 'static> DeviceManager<T> {
@@ -85,7 +108,8 @@ impl<T: Publisher + Subscriber + Clone + 'static> DeviceManager<T> {
 
         info!("Starting");
 
-        let ota_handler = OtaHandler::new(&opts).await?;
+        let ota_handler = Arc::new(OtaHandler::new(&opts).await?);
+        let broker_lookup = data::astarte_device_sdk_lib::BrokerLookup::new(&opts);
 
         ota_handler.ensure_pending_ota_is_done(&publisher).await?;
 
@@ -94,33 +118,100 @@ impl<T: Publisher + Subscriber + Clone + 'static> DeviceManager<T> {
 
         let (telemetry_tx, telemetry_rx) = channel(32);
 
+        let metrics = match opts.metrics {
+            Some(metrics_opts) => {
+                let mut metrics = metrics::Metrics::new(Some(ota_handler.clone()));
+                metrics.register_queue("ota_events", &ota_tx);
+                metrics.register_queue("data_events", &data_tx);
+                metrics.register_queue("telemetry", &telemetry_tx);
+
+                let metrics = Arc::new(metrics);
+                tokio::spawn(metrics.clone().listen(&metrics_opts.listen_address)?);
+
+                Some(metrics)
+            }
+            None => None,
+        };
+
+        tokio::spawn(telemetry::peripherals::watch(
+            telemetry_tx.clone(),
+            opts.store_directory.clone(),
+        ));
+        tokio::spawn(telemetry::net_if_properties::watch(
+            telemetry_tx.clone(),
+            opts.store_directory.clone(),
+        ));
+
+        let sources = telemetry::TelemetrySources {
+            battery: telemetry::battery_status::Battery::new(opts.battery.unwrap_or_default()),
+            gnss: telemetry::gnss::Gnss::new(opts.gnss.unwrap_or_default()),
+            exec: telemetry::exec_source::ExecSources::new(
+                opts.exec_sources.unwrap_or_default(),
+                &opts.interfaces_directory,
+            ),
+            storage: opts.storage_usage.unwrap_or_default(),
+            block_devices: telemetry::storage_health::BlockDevices::new(
+                opts.store_directory.clone(),
+            ),
+            systemd: telemetry::systemd_units::SystemdUnits::new(opts.systemd.unwrap_or_default()),
+            processes: telemetry::process_watch::ProcessWatch::new(
+                opts.process_watch.unwrap_or_default(),
+            ),
+        };
+
         let tel = telemetry::Telemetry::from_default_config(
             opts.telemetry_config,
+            sources,
+            opts.telemetry_period_limits.unwrap_or_default(),
             telemetry_tx,
             opts.store_directory.clone(),
         )
         .await;
+        tel.spawn_event_watchers();
+
+        let alerts = Arc::new(
+            alerts::Alerts::new(
+                opts.alerts.unwrap_or_default(),
+                tel.sources(),
+                opts.store_directory.clone(),
+            )
+            .await,
+        );
+
+        let network_config = network_config::NetworkConfig::new(
+            opts.network_config.unwrap_or_default(),
+            broker_lookup.clone(),
+        );
+        let diagnostics = diagnostics::Diagnostics::new(
+            opts.diagnostics.unwrap_or_default(),
+            opts.astarte_device_sdk
+                .as_ref()
+                .map(|sdk| sdk.pairing_url.as_str()),
+            broker_lookup,
+        );
 
         let device_runtime = Self {
             publisher,
             ota_event_channel: ota_tx,
             data_event_channel: data_tx,
             telemetry: Arc::new(RwLock::new(tel)),
+            alerts,
+            metrics,
+            store_directory: opts.store_directory,
         };
 
         device_runtime.init_ota_event(ota_handler, ota_rx);
-        device_runtime.init_data_event(data_rx);
+        device_runtime.init_data_event(data_rx, network_config, diagnostics);
         device_runtime.init_telemetry_event(telemetry_rx);
         Ok(device_runtime)
     }
 
     fn init_ota_event(
         &self,
-        ota_handler: OtaHandler,
+        ota_handler: Arc<OtaHandler>,
         mut ota_rx: Receiver<AstarteDeviceDataEvent>,
     ) {
         let publisher = self.publisher.clone();
-        let ota_handler = Arc::new(ota_handler);
         tokio::spawn(async move {
             while let Some(data_event) = ota_rx.recv().await {
                 match (
@@ -148,8 +239,18 @@ impl<T: Publisher + Subscriber + Clone + 'static> DeviceManager<T> {
         });
     }
 
-    fn init_data_event(&self, mut data_rx: Receiver<AstarteDeviceDataEvent>) {
+    fn init_data_event(
+        &self,
+        mut data_rx: Receiver<AstarteDeviceDataEvent>,
+        network_config: network_config::NetworkConfig,
+        diagnostics: diagnostics::Diagnostics,
+    ) {
+        let publisher = self.publisher.clone();
         let self_telemetry = self.telemetry.clone();
+        let store_directory = self.store_directory.clone();
+        let alerts = self.alerts.clone();
+        let network_config = Arc::new(network_config);
+        let diagnostics = Arc::new(diagnostics);
         tokio::spawn(async move {
             while let Some(data_event) = data_rx.recv().await {
                 match (
@@ -166,7 +267,15 @@ impl<T: Publisher + Subscriber + Clone + 'static> DeviceManager<T> {
                         "io.edgehog.devicemanager.Commands",
                         ["request"],
                         Aggregation::Individual(AstarteType::String(command)),
-                    ) => commands::execute_command(command).await,
+                    ) => {
+                        commands::execute_command(
+                            &publisher,
+                            command,
+                            &store_directory,
+                            &diagnostics,
+                        )
+                        .await
+                    }
                     (
                         "io.edgehog.devicemanager.config.Telemetry",
                         ["request", interface_name, endpoint],
@@ -188,6 +297,28 @@ impl<T: Publisher + Subscriber + Clone + 'static> DeviceManager<T> {
                             behavior.clone(),
                         ));
                     }
+                    (
+                        "io.edgehog.devicemanager.config.TimeSettings",
+                        [endpoint],
+                        Aggregation::Individual(data),
+                    ) => time_settings::set_time_setting(endpoint, data).await,
+                    (
+                        "io.edgehog.devicemanager.config.AlertRules",
+                        [rule_name, endpoint],
+                        Aggregation::Individual(data),
+                    ) => alerts.config_event(rule_name, endpoint, data).await,
+                    (
+                        "io.edgehog.devicemanager.config.NetworkConnection",
+                        ["request"],
+                        Aggregation::Object(data),
+                    ) => {
+                        let publisher = publisher.clone();
+                        let data = data.clone();
+                        let network_config = network_config.clone();
+                        tokio::spawn(async move {
+                            network_config.handle_request(&publisher, data).await;
+                        });
+                    }
                     _ => {
                         warn!("Receiving data from an unknown path/interface: {data_event:?}");
                     }
@@ -198,9 +329,21 @@ impl<T: Publisher + Subscriber + Clone + 'static> DeviceManager<T> {
 
     fn init_telemetry_event(&self, mut telemetry_rx: Receiver<TelemetryMessage>) {
         let publisher = self.publisher.clone();
+        let metrics = self.metrics.clone();
         tokio::spawn(async move {
             while let Some(msg) = telemetry_rx.recv().await {
-                Self::send_telemetry(&publisher, msg).await;
+                if let Some(metrics) = &metrics {
+                    metrics.record_telemetry(&msg);
+                }
+
+                let interface_name = msg.payload.interface_name().to_string();
+                if let Err(err) = Self::send_telemetry(&publisher, msg).await {
+                    debug!("couldn't publish on {interface_name}: {err}");
+
+                    if let Some(metrics) = &metrics {
+                        metrics.record_publish_failure(&interface_name);
+                    }
+                }
             }
         });
     }
@@ -214,6 +357,8 @@ impl<T: Publisher + Subscriber + Clone + 'static> DeviceManager<T> {
             tel_clone.write().await.run_telemetry().await;
         });
 
+        tokio::spawn(self.alerts.clone().run(self.publisher.clone()));
+
         loop {
             match self.publisher.on_event().await {
                 Ok(data_event) => {
@@ -233,15 +378,93 @@ impl<T: Publisher + Subscriber + Clone + 'static> DeviceManager<T> {
         }
     }
 
+    /// Saves the state kept in memory before the runtime is terminated.
+    pub async fn shutdown(&self) {
+        let sources = self.telemetry.read().await.sources();
+        if let Err(err) = sources.block_devices.save().await {
+            warn!("couldn't save the write counters: {err}");
+        }
+    }
+
     pub async fn init(&self) -> Result<(), DeviceManagerError> {
         #[cfg(feature = "systemd")]
         systemd_wrapper::systemd_notify_status("Sending initial telemetry");
 
         self.send_initial_telemetry().await?;
+        // the boot history is not needed by the runtime, so it doesn't prevent it from starting
+        if let Err(err) = self.send_boot_record().await {
+            warn!("couldn't publish the boot record: {err}");
+        }
+        if let Err(err) = self.send_package_inventory().await {
+            warn!("couldn't publish the package inventory: {err}");
+        }
 
         Ok(())
     }
 
+    async fn send_boot_record(&self) -> Result<(), DeviceManagerError> {
+        let boot_record = boot_history::record_boot(&self.store_directory).await?;
+
+        self.publisher
+            .send_object("io.edgehog.devicemanager.BootRecord", "/boot", boot_record)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Publishes the changes of the installed packages since the last published inventory.
+    async fn send_package_inventory(&self) -> Result<(), DeviceManagerError> {
+        const INTERFACE: &str = "io.edgehog.devicemanager.PackageInventory";
+
+        let inventory = match telemetry::package_inventory::read_inventory().await {
+            Ok(inventory) => inventory,
+            Err(err) => {
+                warn!("couldn't read the package inventory: {err}");
+
+                return Ok(());
+            }
+        };
+
+        let packages = telemetry::package_inventory::PackageInventory::new(&self.store_directory);
+        let diff = packages.diff(&inventory).await;
+        if diff.is_empty() {
+            return Ok(());
+        }
+
+        let changed = diff
+            .changed
+            .into_iter()
+            .map(|(name, version)| (name, AstarteType::String(version)));
+        let removed = diff
+            .removed
+            .into_iter()
+            .map(|name| (name, AstarteType::Unset));
+
+        let mut failed = 0;
+        for (name, data) in changed.chain(removed) {
+            let path = telemetry::package_inventory::package_path(&name);
+            if let Err(err) = self.publisher.send(INTERFACE, &path, data).await {
+                warn!("couldn't publish the package {name}: {err}");
+                failed += 1;
+            }
+        }
+
+        // the inventory is published again at the next start, until all the packages are sent
+        if failed > 0 {
+            warn!("{failed} packages of the inventory weren't published");
+
+            return Ok(());
+        }
+
+        if let Some(hash) = diff.hash {
+            self.publisher
+                .send(INTERFACE, "/inventoryHash", AstarteType::String(hash))
+                .await?;
+        }
+
+        packages.save(inventory).await
+    }
+
     pub async fn send_initial_telemetry(&self) -> Result<(), DeviceManagerError> {
         let device = &self.publisher;
 
@@ -251,7 +474,7 @@ impl<T: Publisher + Subscriber + Clone + 'static> DeviceManager<T> {
                 telemetry::os_info::get_os_info().await?,
             ),
             (
-                "io.edgehog.devicemanager.HardwareInfo",
+                HARDWARE_INFO_INTERFACE,
                 telemetry::hardware_info::get_hardware_info()?,
             ),
             (
@@ -262,6 +485,10 @@ impl<T: Publisher + Subscriber + Clone + 'static> DeviceManager<T> {
                 "io.edgehog.devicemanager.NetworkInterfaceProperties",
                 telemetry::net_if_properties::get_network_interface_properties().await?,
             ),
+            (
+                telemetry::peripherals::PERIPHERALS_INTERFACE,
+                telemetry::peripherals::get_peripherals()?,
+            ),
             (
                 "io.edgehog.devicemanager.SystemInfo",
                 telemetry::system_info::get_system_info()?,
@@ -273,13 +500,20 @@ impl<T: Publisher + Subscriber + Clone + 'static> DeviceManager<T> {
         ];
 
         for (ifc, fields) in data {
+            if ifc == HARDWARE_INFO_INTERFACE {
+                self.record_telemetry(TelemetryPayload::Properties(ifc, fields.clone()), "");
+            }
+
             for (path, data) in fields {
                 device.send(ifc, &path, data).await?;
             }
         }
 
-        let disks = telemetry::storage_usage::get_storage_usage()?;
+        let sources = self.telemetry.read().await.sources();
+        let disks = telemetry::storage_usage::get_storage_usage(&sources.storage)?;
         for (disk_name, storage) in disks {
+            self.record_telemetry(TelemetryPayload::StorageUsage(storage.clone()), &disk_name);
+
             device
                 .send_object(
                     "io.edgehog.devicemanager.StorageUsage",
@@ -289,12 +523,18 @@ impl<T: Publisher + Subscriber + Clone + 'static> DeviceManager<T> {
                 .await?;
         }
 
-        for wifi_scan_result in telemetry::wifi_scan::get_wifi_scan_results()? {
+        let mount_points = telemetry::storage_usage::get_mount_point_usage(&sources.storage)?;
+        for (mount_point, usage) in mount_points {
+            self.record_telemetry(
+                TelemetryPayload::MountPointUsage(usage.clone()),
+                &mount_point,
+            );
+
             device
                 .send_object(
-                    "io.edgehog.devicemanager.WiFiScanResults",
-                    "/ap",
-                    wifi_scan_result,
+                    "io.edgehog.devicemanager.MountPointUsage",
+                    format!("/{}", mount_point).as_str(),
+                    usage,
                 )
                 .await?;
         }
@@ -302,36 +542,101 @@ impl<T: Publisher + Subscriber + Clone + 'static> DeviceManager<T> {
         Ok(())
     }
 
-    async fn send_telemetry(publisher: &impl Publisher, msg: TelemetryMessage) {
+    /// Exposes in the metrics the telemetry published outside of the telemetry channel.
+    fn record_telemetry(&self, payload: TelemetryPayload, path: &str) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_telemetry(&TelemetryMessage {
+                path: path.to_string(),
+                payload,
+            });
+        }
+    }
+
+    async fn send_telemetry(
+        publisher: &impl Publisher,
+        msg: TelemetryMessage,
+    ) -> Result<(), AstarteError> {
+        let interface_name = msg.payload.interface_name().to_string();
+        let path = format!("/{}", msg.path);
+
         match msg.payload {
             TelemetryPayload::SystemStatus(data) => {
-                let _ = publisher
-                    .send_object(
-                        "io.edgehog.devicemanager.SystemStatus",
-                        "/systemStatus",
-                        data,
-                    )
-                    .await;
+                publisher
+                    .send_object(&interface_name, "/systemStatus", data)
+                    .await
             }
             TelemetryPayload::StorageUsage(data) => {
-                let _ = publisher
-                    .send_object(
-                        "io.edgehog.devicemanager.StorageUsage",
-                        format!("/{}", msg.path).as_str(),
-                        data,
-                    )
-                    .await;
+                publisher.send_object(&interface_name, &path, data).await
+            }
+            TelemetryPayload::MountPointUsage(data) => {
+                publisher.send_object(&interface_name, &path, data).await
             }
             TelemetryPayload::BatteryStatus(data) => {
-                let _ = publisher
-                    .send_object(
-                        "io.edgehog.devicemanager.BatteryStatus",
-                        format!("/{}", msg.path).as_str(),
-                        data,
-                    )
-                    .await;
+                publisher.send_object(&interface_name, &path, data).await
             }
-        };
+            TelemetryPayload::GnssPosition(data, timestamp) => {
+                publisher
+                    .send_object_with_timestamp(&interface_name, &path, data, timestamp)
+                    .await
+            }
+            TelemetryPayload::StorageHealth(data) => {
+                publisher.send_object(&interface_name, &path, data).await
+            }
+            TelemetryPayload::PressureStall(data) => {
+                publisher.send_object(&interface_name, &path, data).await
+            }
+            TelemetryPayload::OomKill(data, timestamp) => {
+                publisher
+                    .send_object_with_timestamp(&interface_name, "/oomKill", data, timestamp)
+                    .await
+            }
+            TelemetryPayload::SystemdUnitStatus(data) => {
+                publisher.send_object(&interface_name, &path, data).await
+            }
+            TelemetryPayload::SystemdUnitFailed(data) => {
+                publisher
+                    .send_object(&interface_name, "/unitFailed", data)
+                    .await
+            }
+            TelemetryPayload::ProcessStatus(data) => {
+                publisher.send_object(&interface_name, &path, data).await
+            }
+            TelemetryPayload::ProcessDisappeared(data) => {
+                publisher
+                    .send_object(&interface_name, "/processDisappeared", data)
+                    .await
+            }
+            TelemetryPayload::WifiScanResult(data) => {
+                publisher.send_object(&interface_name, &path, data).await
+            }
+            TelemetryPayload::TimeSyncStatus(data) => {
+                publisher
+                    .send_object(&interface_name, "/timeSync", data)
+                    .await
+            }
+            TelemetryPayload::Peripheral(data) => {
+                publisher.send_object(&interface_name, "/event", data).await
+            }
+            TelemetryPayload::Properties(_, properties) => {
+                let mut result = Ok(());
+                for (path, data) in properties {
+                    if let Err(err) = publisher.send(&interface_name, &path, data).await {
+                        result = Err(err);
+                    }
+                }
+
+                result
+            }
+            TelemetryPayload::Exec(_, ExecValue::Object(data)) => {
+                publisher.send_object(&interface_name, &path, data).await
+            }
+            TelemetryPayload::Exec(_, ExecValue::Individual(data)) => {
+                publisher.send(&interface_name, &path, data).await
+            }
+            TelemetryPayload::TelemetryAggregate(data) => {
+                publisher.send_object(&interface_name, &path, data).await
+            }
+        }
     }
 }
 
@@ -379,12 +684,15 @@ mod tests {
     };
     use crate::data::{Publisher, Subscriber};
     use crate::telemetry::base_image::get_base_image;
-    use crate::telemetry::battery_status::{get_battery_status, BatteryStatus};
+    use crate::telemetry::battery_status::{Battery, BatteryStatus};
     use crate::telemetry::hardware_info::get_hardware_info;
     use crate::telemetry::net_if_properties::get_network_interface_properties;
     use crate::telemetry::os_info::get_os_info;
+    use crate::telemetry::peripherals::get_peripherals;
     use crate::telemetry::runtime_info::get_runtime_info;
-    use crate::telemetry::storage_usage::{get_storage_usage, DiskUsage};
+    use crate::telemetry::storage_usage::{
+        get_mount_point_usage, get_storage_usage, DiskUsage, MountPointUsage,
+    };
     use crate::telemetry::system_info::get_system_info;
     use crate::telemetry::system_status::{get_system_status, SystemStatus};
     use crate::{
@@ -409,6 +717,16 @@ mod tests {
             where
                 T: AstarteAggregate + Send;
 
+            async fn send_object_with_timestamp<T: 'static>(
+                &self,
+                interface_name: &str,
+                interface_path: &str,
+                data: T,
+                timestamp: chrono::DateTime<chrono::Utc>,
+            ) -> Result<(), astarte_device_sdk::AstarteError>
+            where
+                T: AstarteAggregate + Send;
+
             async fn send(
                 &self,
                 interface_name: &str,
@@ -441,6 +759,17 @@ mod tests {
             download_directory: "".to_string(),
             astarte_ignore_ssl: Some(false),
             telemetry_config: Some(vec![]),
+            gnss: None,
+            battery: None,
+            exec_sources: None,
+            storage_usage: None,
+            systemd: None,
+            process_watch: None,
+            network_config: None,
+            diagnostics: None,
+            telemetry_period_limits: None,
+            metrics: None,
+            alerts: None,
         };
 
         let astarte_options = astarte_map_options(&options).await.unwrap();
@@ -467,6 +796,17 @@ mod tests {
             download_directory: "".to_string(),
             astarte_ignore_ssl: Some(false),
             telemetry_config: Some(vec![]),
+            gnss: None,
+            battery: None,
+            exec_sources: None,
+            storage_usage: None,
+            systemd: None,
+            process_watch: None,
+            network_config: None,
+            diagnostics: None,
+            telemetry_period_limits: None,
+            metrics: None,
+            alerts: None,
         };
 
         let mut mock_astarte_handler = MockAstarteHandler::new();
@@ -498,6 +838,17 @@ mod tests {
             download_directory: "".to_string(),
             astarte_ignore_ssl: Some(false),
             telemetry_config: Some(vec![]),
+            gnss: None,
+            battery: None,
+            exec_sources: None,
+            storage_usage: None,
+            systemd: None,
+            process_watch: None,
+            network_config: None,
+            diagnostics: None,
+            telemetry_period_limits: None,
+            metrics: None,
+            alerts: None,
         };
 
         let os_info = get_os_info().await.expect("failed to get os info");
@@ -538,7 +889,7 @@ mod tests {
             )
             .returning(|_: &str, _: &str, _: AstarteType| Ok(()));
 
-        let storage_usage = get_storage_usage().unwrap();
+        let storage_usage = get_storage_usage(&Default::default()).unwrap();
         mock_astarte_handler
             .expect_send_object()
             .withf(
@@ -549,6 +900,17 @@ mod tests {
             )
             .returning(|_: &str, _: &str, _: DiskUsage| Ok(()));
 
+        let mount_point_usage = get_mount_point_usage(&Default::default()).unwrap();
+        mock_astarte_handler
+            .expect_send_object()
+            .withf(
+                move |interface_name: &str, interface_path: &str, _: &MountPointUsage| {
+                    interface_name == "io.edgehog.devicemanager.MountPointUsage"
+                        && mount_point_usage.contains_key(&interface_path[1..])
+                },
+            )
+            .returning(|_: &str, _: &str, _: MountPointUsage| Ok(()));
+
         let network_iface_props = get_network_interface_properties().await.unwrap();
         mock_astarte_handler
             .expect_send()
@@ -560,6 +922,17 @@ mod tests {
             )
             .returning(|_: &str, _: &str, _: AstarteType| Ok(()));
 
+        let peripherals = get_peripherals().unwrap();
+        mock_astarte_handler
+            .expect_send()
+            .withf(
+                move |interface_name: &str, interface_path: &str, data: &AstarteType| {
+                    interface_name == "io.edgehog.devicemanager.Peripherals"
+                        && peripherals.get(interface_path).unwrap() == data
+                },
+            )
+            .returning(|_: &str, _: &str, _: AstarteType| Ok(()));
+
         let system_info = get_system_info().unwrap();
         mock_astarte_handler
             .expect_send()
@@ -603,7 +976,7 @@ mod tests {
             )
             .returning(|_: &str, _: &str, _: SystemStatus| Ok(()));
 
-        let storage_usage = get_storage_usage().unwrap();
+        let storage_usage = get_storage_usage(&Default::default()).unwrap();
         mock_astarte_handler
             .expect_send_object()
             .withf(
@@ -614,7 +987,7 @@ mod tests {
             )
             .returning(|_: &str, _: &str, _: DiskUsage| Ok(()));
 
-        let battery_status = get_battery_status().await.unwrap();
+        let battery_status = Battery::default().get_battery_status().await.unwrap();
         mock_astarte_handler
             .expect_send_object()
             .withf(
@@ -632,8 +1005,9 @@ mod tests {
                 payload: TelemetryPayload::SystemStatus(system_status),
             },
         )
-        .await;
-        for (path, payload) in get_storage_usage().unwrap() {
+        .await
+        .unwrap();
+        for (path, payload) in get_storage_usage(&Default::default()).unwrap() {
             DeviceManager::<MockAstarteHandler>::send_telemetry(
                 &mock_astarte_handler,
                 TelemetryMessage {
@@ -641,9 +1015,10 @@ mod tests {
                     payload: TelemetryPayload::StorageUsage(payload),
                 },
             )
-            .await;
+            .await
+            .unwrap();
         }
-        for (path, payload) in get_battery_status().await.unwrap() {
+        for (path, payload) in Battery::default().get_battery_status().await.unwrap() {
             DeviceManager::<MockAstarteHandler>::send_telemetry(
                 &mock_astarte_handler,
                 TelemetryMessage {
@@ -651,7 +1026,8 @@ mod tests {
                     payload: TelemetryPayload::BatteryStatus(payload),
                 },
             )
-            .await;
+            .await
+            .unwrap();
         }
     }
 }
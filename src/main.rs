@@ -19,11 +19,13 @@
  */
 
 use clap::Parser;
+use std::future::Future;
 #[cfg(feature = "systemd")]
 use std::panic::{self, PanicInfo};
 use std::path::Path;
 
 use config::read_options;
+use edgehog_device_runtime::boot_history::{write_shutdown_marker, ShutdownReason};
 use edgehog_device_runtime::error::DeviceManagerError;
 use edgehog_device_runtime::AstarteLibrary;
 
@@ -77,6 +79,8 @@ async fn main() -> Result<(), DeviceManagerError> {
             })?;
     }
 
+    let store_directory = options.store_directory.clone();
+
     match &options.astarte_library {
         AstarteLibrary::AstarteDeviceSDK => {
             use edgehog_device_runtime::data::astarte_device_sdk_lib::{
@@ -92,7 +96,9 @@ async fn main() -> Result<(), DeviceManagerError> {
 
             dm.init().await?;
 
-            dm.run().await;
+            if run_until_terminated(dm.run(), &store_directory).await? {
+                dm.shutdown().await;
+            }
         }
         AstarteLibrary::AstarteMessageHub => {
             use edgehog_device_runtime::data::astarte_message_hub_node::AstarteMessageHubNode;
@@ -112,13 +118,36 @@ async fn main() -> Result<(), DeviceManagerError> {
 
             dm.init().await?;
 
-            dm.run().await;
+            if run_until_terminated(dm.run(), &store_directory).await? {
+                dm.shutdown().await;
+            }
         }
     };
 
     Ok(())
 }
 
+/// Runs the device manager until it stops or the service is terminated, recording a clean
+/// shutdown in the latter case. Returns whether the service was terminated.
+async fn run_until_terminated(
+    run: impl Future<Output = ()>,
+    store_directory: &str,
+) -> Result<bool, DeviceManagerError> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    tokio::select! {
+        _ = run => Ok(false),
+        _ = terminate.recv() => {
+            log::info!("received SIGTERM, shutting down");
+            write_shutdown_marker(store_directory, ShutdownReason::Shutdown).await?;
+
+            Ok(true)
+        }
+    }
+}
+
 #[cfg(feature = "systemd")]
 fn systemd_panic_hook(panic_info: &PanicInfo) {
     use edgehog_device_runtime::systemd_wrapper;
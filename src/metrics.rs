@@ -0,0 +1,552 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Local HTTP endpoint exposing the telemetry and the runtime state in the Prometheus text format.
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use astarte_device_sdk::types::AstarteType;
+use hyper::server::conn::AddrIncoming;
+use hyper::server::Builder;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use log::{error, info};
+use serde::Deserialize;
+use tokio::sync::mpsc::Sender;
+
+use crate::error::DeviceManagerError;
+use crate::ota::ota_handler::OtaHandler;
+use crate::telemetry::{TelemetryMessage, TelemetryPayload};
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+const OTA_STATES: [&str; 12] = [
+    "Idle",
+    "Init",
+    "NoPendingOta",
+    "Acknowledged",
+    "Downloading",
+    "Deploying",
+    "Deployed",
+    "Rebooting",
+    "Rebooted",
+    "Success",
+    "Error",
+    "Failure",
+];
+
+/// Configuration of the Prometheus endpoint.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct MetricsOptions {
+    /// Address the HTTP listener is bound to, e.g. `127.0.0.1:9100`.
+    pub listen_address: SocketAddr,
+}
+
+type QueueDepth = Box<dyn Fn() -> Option<usize> + Send + Sync>;
+
+/// Last value of a telemetry series and the unix time it was recorded at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Gauge {
+    value: f64,
+    updated: i64,
+}
+
+/// Last values of the telemetry and counters of the runtime, rendered on each scrape.
+#[derive(Default)]
+pub struct Metrics {
+    /// Gauges by metric name, then by rendered label set.
+    telemetry: Mutex<BTreeMap<String, BTreeMap<String, Gauge>>>,
+    /// Failed publishes by interface.
+    publish_failures: Mutex<BTreeMap<String, u64>>,
+    queues: Vec<(&'static str, QueueDepth)>,
+    ota_handler: Option<Arc<OtaHandler>>,
+}
+
+impl Metrics {
+    pub fn new(ota_handler: Option<Arc<OtaHandler>>) -> Self {
+        Self {
+            ota_handler,
+            ..Default::default()
+        }
+    }
+
+    /// Exposes the number of messages waiting in the channel of `sender`.
+    ///
+    /// Only a weak reference to the channel is kept, so the metric doesn't keep it open.
+    pub fn register_queue<T: Send + 'static>(&mut self, name: &'static str, sender: &Sender<T>) {
+        let sender = sender.downgrade();
+
+        self.queues.push((
+            name,
+            Box::new(move || {
+                sender
+                    .upgrade()
+                    .map(|sender| sender.max_capacity() - sender.capacity())
+            }),
+        ));
+    }
+
+    /// Stores the numeric and boolean fields of a telemetry message as gauges.
+    ///
+    /// Strings and other non numeric values are skipped, while unset properties remove their
+    /// gauge. The time of the update is exposed as `<name>_last_update_timestamp_seconds`, so
+    /// values of sources that stopped reporting can be told apart.
+    pub fn record_telemetry(&self, msg: &TelemetryMessage) {
+        self.record_telemetry_at(msg, chrono::Utc::now().timestamp());
+    }
+
+    fn record_telemetry_at(&self, msg: &TelemetryMessage, now: i64) {
+        let Ok(values) = msg.payload.values() else {
+            return;
+        };
+
+        let prefix = format!("edgehog_{}", metric_name(msg.payload.interface_name()));
+        let mut telemetry = self.telemetry.lock().unwrap();
+
+        for (key, value) in values {
+            let (path, field) = match msg.payload {
+                TelemetryPayload::Properties(_, _) => {
+                    let key = key.trim_start_matches('/');
+                    match key.rsplit_once('/') {
+                        Some((path, field)) => (path.to_string(), field.to_string()),
+                        None => (String::new(), key.to_string()),
+                    }
+                }
+                _ => (msg.key(), key),
+            };
+
+            let name = format!("{prefix}_{}", metric_name(&field));
+            let labels = if path.is_empty() {
+                String::new()
+            } else {
+                labels(&[("path", path.as_str())])
+            };
+
+            match (gauge_value(&value), value) {
+                (Some(value), _) => {
+                    let gauge = Gauge {
+                        value,
+                        updated: now,
+                    };
+                    telemetry.entry(name).or_default().insert(labels, gauge);
+                }
+                (None, AstarteType::Unset) => {
+                    if let Some(series) = telemetry.get_mut(&name) {
+                        series.remove(&labels);
+                        if series.is_empty() {
+                            telemetry.remove(&name);
+                        }
+                    }
+                }
+                (None, _) => {}
+            }
+        }
+    }
+
+    pub fn record_publish_failure(&self, interface_name: &str) {
+        *self
+            .publish_failures
+            .lock()
+            .unwrap()
+            .entry(interface_name.to_string())
+            .or_default() += 1;
+    }
+
+    /// Renders all the metrics in the Prometheus text exposition format.
+    pub async fn render(&self) -> String {
+        let ota_status = match &self.ota_handler {
+            Some(ota_handler) => match ota_handler.get_ota_status().await {
+                Ok(status) => Some(status),
+                Err(err) => {
+                    error!("couldn't get the OTA status: {err}");
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let mut out = String::new();
+
+        let telemetry = self.telemetry.lock().unwrap();
+        for (name, series) in telemetry.iter() {
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            for (labels, gauge) in series {
+                let _ = writeln!(out, "{name}{labels} {}", format_value(gauge.value));
+            }
+        }
+        for (name, series) in telemetry.iter() {
+            let _ = writeln!(out, "# TYPE {name}_last_update_timestamp_seconds gauge");
+            for (labels, gauge) in series {
+                let _ = writeln!(
+                    out,
+                    "{name}_last_update_timestamp_seconds{labels} {}",
+                    gauge.updated
+                );
+            }
+        }
+        drop(telemetry);
+
+        let _ = writeln!(
+            out,
+            "# HELP edgehog_publish_failures_total Messages that couldn't be published to Astarte."
+        );
+        let _ = writeln!(out, "# TYPE edgehog_publish_failures_total counter");
+        for (interface_name, count) in self.publish_failures.lock().unwrap().iter() {
+            let labels = labels(&[("interface", interface_name.as_str())]);
+            let _ = writeln!(out, "edgehog_publish_failures_total{labels} {count}");
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP edgehog_queue_depth Messages waiting to be handled by the runtime."
+        );
+        let _ = writeln!(out, "# TYPE edgehog_queue_depth gauge");
+        for (queue, depth) in &self.queues {
+            if let Some(depth) = depth() {
+                let labels = labels(&[("queue", *queue)]);
+                let _ = writeln!(out, "edgehog_queue_depth{labels} {depth}");
+            }
+        }
+
+        if let Some(status) = ota_status {
+            let _ = writeln!(out, "# HELP edgehog_ota_state Current state of the OTA.");
+            let _ = writeln!(out, "# TYPE edgehog_ota_state gauge");
+            for state in OTA_STATES {
+                let labels = labels(&[("state", state)]);
+                let value = u8::from(state == status.name());
+                let _ = writeln!(out, "edgehog_ota_state{labels} {value}");
+            }
+        }
+
+        out
+    }
+
+    /// Binds the listener and returns the future serving the scrapes.
+    pub fn listen(
+        self: Arc<Self>,
+        address: &SocketAddr,
+    ) -> Result<impl Future<Output = ()>, DeviceManagerError> {
+        let builder = Server::try_bind(address)?;
+
+        info!("serving the metrics on http://{address}/metrics");
+
+        Ok(self.serve(builder))
+    }
+
+    async fn serve(self: Arc<Self>, builder: Builder<AddrIncoming>) {
+        let service = make_service_fn(move |_| {
+            let metrics = self.clone();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let metrics = metrics.clone();
+
+                    async move { Ok::<_, Infallible>(metrics.handle(req).await) }
+                }))
+            }
+        });
+
+        if let Err(err) = builder.serve(service).await {
+            error!("metrics endpoint stopped: {err}");
+        }
+    }
+
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        if req.method() != Method::GET || req.uri().path() != "/metrics" {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NOT_FOUND;
+
+            return response;
+        }
+
+        let mut response = Response::new(Body::from(self.render().await));
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static(CONTENT_TYPE),
+        );
+
+        response
+    }
+}
+
+/// Converts an interface or field name to a snake case metric name, e.g.
+/// `io.edgehog.devicemanager.SystemStatus` to `system_status`.
+fn metric_name(name: &str) -> String {
+    let name = name.rsplit('.').next().unwrap_or(name);
+    let chars: Vec<char> = name.chars().collect();
+    let mut out = String::with_capacity(name.len() + 4);
+
+    for (i, c) in chars.iter().enumerate() {
+        if !c.is_ascii_alphanumeric() {
+            out.push('_');
+            continue;
+        }
+
+        if c.is_ascii_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_lower = matches!(chars.get(i + 1), Some(next) if next.is_ascii_lowercase());
+            if prev.is_ascii_lowercase()
+                || prev.is_ascii_digit()
+                || (prev.is_ascii_uppercase() && next_lower)
+            {
+                out.push('_');
+            }
+        }
+
+        out.push(c.to_ascii_lowercase());
+    }
+
+    out
+}
+
+fn labels(pairs: &[(&str, &str)]) -> String {
+    let pairs: Vec<String> = pairs
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+
+            format!("{name}=\"{value}\"")
+        })
+        .collect();
+
+    format!("{{{}}}", pairs.join(","))
+}
+
+fn gauge_value(value: &AstarteType) -> Option<f64> {
+    match value {
+        AstarteType::Double(value) => Some(*value),
+        AstarteType::Integer(value) => Some(f64::from(*value)),
+        AstarteType::LongInteger(value) => Some(*value as f64),
+        AstarteType::Boolean(value) => Some(f64::from(u8::from(*value))),
+        _ => None,
+    }
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use astarte_device_sdk::types::AstarteType;
+    use hyper::Server;
+    use tokio::sync::mpsc;
+
+    use crate::metrics::{format_value, labels, metric_name, Metrics, CONTENT_TYPE};
+    use crate::telemetry::exec_source::ExecValue;
+    use crate::telemetry::{TelemetryMessage, TelemetryPayload};
+
+    fn exec_message(path: &str, values: &[(&str, AstarteType)]) -> TelemetryMessage {
+        TelemetryMessage {
+            path: path.to_string(),
+            payload: TelemetryPayload::Exec(
+                "com.example.SensorReadings".to_string(),
+                ExecValue::Object(
+                    values
+                        .iter()
+                        .map(|(key, value)| (key.to_string(), value.clone()))
+                        .collect(),
+                ),
+            ),
+        }
+    }
+
+    #[test]
+    fn metric_name_test() {
+        assert_eq!(
+            metric_name("io.edgehog.devicemanager.SystemStatus"),
+            "system_status"
+        );
+        assert_eq!(metric_name("availMemoryBytes"), "avail_memory_bytes");
+        assert_eq!(metric_name("NTPSynchronized"), "ntp_synchronized");
+        assert_eq!(metric_name("levelPercentage"), "level_percentage");
+        assert_eq!(metric_name("temp-1"), "temp_1");
+    }
+
+    #[test]
+    fn labels_escape_test() {
+        assert_eq!(
+            labels(&[("path", "a\"b\\c\nd"), ("queue", "telemetry")]),
+            r#"{path="a\"b\\c\nd",queue="telemetry"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn render_telemetry_test() {
+        let metrics = Metrics::default();
+
+        metrics.record_telemetry(&exec_message(
+            "sensor1",
+            &[
+                ("temperature", AstarteType::Double(21.5)),
+                ("online", AstarteType::Boolean(true)),
+                ("label", AstarteType::String("kitchen".to_string())),
+            ],
+        ));
+        metrics.record_telemetry(&exec_message(
+            "",
+            &[("uptimeSeconds", AstarteType::LongInteger(42))],
+        ));
+
+        let out = metrics.render().await;
+
+        assert!(out.contains("# TYPE edgehog_sensor_readings_temperature gauge\n"));
+        assert!(out.contains("edgehog_sensor_readings_temperature{path=\"sensor1\"} 21.5\n"));
+        assert!(out.contains("edgehog_sensor_readings_online{path=\"sensor1\"} 1\n"));
+        assert!(out.contains("edgehog_sensor_readings_uptime_seconds 42\n"));
+        assert!(!out.contains("label"));
+    }
+
+    #[tokio::test]
+    async fn last_update_timestamp_test() {
+        let metrics = Metrics::default();
+
+        metrics.record_telemetry_at(
+            &exec_message("sensor1", &[("temperature", AstarteType::Double(21.5))]),
+            1_700_000_000,
+        );
+        metrics.record_telemetry_at(
+            &exec_message("sensor2", &[("temperature", AstarteType::Double(19.0))]),
+            1_700_000_000,
+        );
+        metrics.record_telemetry_at(
+            &exec_message("sensor1", &[("temperature", AstarteType::Double(22.0))]),
+            1_700_000_060,
+        );
+
+        let out = metrics.render().await;
+
+        assert!(out.contains(
+            "# TYPE edgehog_sensor_readings_temperature_last_update_timestamp_seconds gauge\n"
+        ));
+        assert!(out.contains(
+            "edgehog_sensor_readings_temperature_last_update_timestamp_seconds{path=\"sensor1\"} 1700000060\n"
+        ));
+        assert!(out.contains(
+            "edgehog_sensor_readings_temperature_last_update_timestamp_seconds{path=\"sensor2\"} 1700000000\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn unset_properties_test() {
+        let metrics = Metrics::default();
+        let msg = |value| TelemetryMessage {
+            path: String::new(),
+            payload: TelemetryPayload::Properties(
+                "io.edgehog.devicemanager.EffectiveTelemetryConfig",
+                HashMap::from([(
+                    "/io.edgehog.devicemanager.SystemStatus/periodSeconds".to_string(),
+                    value,
+                )]),
+            ),
+        };
+
+        metrics.record_telemetry(&msg(AstarteType::LongInteger(60)));
+        let out = metrics.render().await;
+        assert!(out.contains(
+            "edgehog_effective_telemetry_config_period_seconds{path=\"io.edgehog.devicemanager.SystemStatus\"} 60\n"
+        ));
+
+        metrics.record_telemetry(&msg(AstarteType::Unset));
+        let out = metrics.render().await;
+        assert!(!out.contains("edgehog_effective_telemetry_config_period_seconds"));
+    }
+
+    #[tokio::test]
+    async fn render_runtime_metrics_test() {
+        let mut metrics = Metrics::default();
+        let (tx, _rx) = mpsc::channel::<u8>(4);
+        tx.send(1).await.unwrap();
+        tx.send(2).await.unwrap();
+        metrics.register_queue("telemetry", &tx);
+
+        let (closed_tx, closed_rx) = mpsc::channel::<u8>(4);
+        metrics.register_queue("closed", &closed_tx);
+        drop(closed_tx);
+        drop(closed_rx);
+
+        metrics.record_publish_failure("io.edgehog.devicemanager.SystemStatus");
+        metrics.record_publish_failure("io.edgehog.devicemanager.SystemStatus");
+
+        let out = metrics.render().await;
+
+        assert!(out.contains("edgehog_queue_depth{queue=\"telemetry\"} 2\n"));
+        assert!(!out.contains("queue=\"closed\""));
+        assert!(out.contains(
+            "edgehog_publish_failures_total{interface=\"io.edgehog.devicemanager.SystemStatus\"} 2\n"
+        ));
+    }
+
+    #[test]
+    fn format_value_test() {
+        assert_eq!(format_value(f64::NAN), "NaN");
+        assert_eq!(format_value(f64::INFINITY), "+Inf");
+        assert_eq!(format_value(f64::NEG_INFINITY), "-Inf");
+        assert_eq!(format_value(3.0), "3");
+    }
+
+    #[tokio::test]
+    async fn scrape_endpoint_test() {
+        let metrics = Arc::new(Metrics::default());
+        metrics.record_telemetry(&exec_message(
+            "sensor1",
+            &[("temperature", AstarteType::Double(21.5))],
+        ));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let builder = Server::from_tcp(listener).unwrap();
+        tokio::spawn(metrics.serve(builder));
+
+        let response = reqwest::get(format!("http://{address}/metrics"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(
+            response.headers()[reqwest::header::CONTENT_TYPE],
+            CONTENT_TYPE
+        );
+        let body = response.text().await.unwrap();
+        assert!(body.contains("edgehog_sensor_readings_temperature{path=\"sensor1\"} 21.5\n"));
+
+        let response = reqwest::get(format!("http://{address}/other"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+}
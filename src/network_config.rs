@@ -0,0 +1,936 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Remote configuration of the NetworkManager connection profiles, with automatic rollback when
+//! the device loses connectivity after the change.
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+use astarte_device_sdk::types::AstarteType;
+use astarte_device_sdk::AstarteAggregate;
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration, Instant};
+use uuid::Uuid;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, Value};
+
+use crate::data::astarte_device_sdk_lib::BrokerLookup;
+use crate::data::Publisher;
+use crate::error::DeviceManagerError;
+use crate::network_manager::settings::{SettingsConnectionProxy, SettingsProxy};
+use crate::network_manager::{NetworkManagerProxy, NM_STATE_CONNECTED_SITE};
+
+const NETWORK_CONNECTION_EVENT_INTERFACE: &str = "io.edgehog.devicemanager.NetworkConnectionEvent";
+const CONNECTIVITY_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const BROKER_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// NetworkManager rolls back by itself if the runtime stops before confirming the change
+const CHECKPOINT_MARGIN: Duration = Duration::from_secs(30);
+// NM_CHECKPOINT_CREATE_FLAG_DELETE_NEW_CONNECTIONS | NM_CHECKPOINT_CREATE_FLAG_DISCONNECT_NEW_DEVICES
+const CHECKPOINT_FLAGS: u32 = 0x02 | 0x04;
+
+type ConnectionSettings = HashMap<&'static str, HashMap<&'static str, Value<'static>>>;
+
+/// Configuration of the remote network configuration.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct NetworkConfigOptions {
+    /// Whether the connection profiles can be changed remotely.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Address of the MQTT broker, as `host:port`, by default read from the Astarte pairing API.
+    pub broker_address: Option<String>,
+    /// Seconds to wait for the connectivity to be re-established before rolling back a change.
+    #[serde(default = "default_rollback_timeout_seconds")]
+    pub rollback_timeout_seconds: u64,
+}
+
+fn default_rollback_timeout_seconds() -> u64 {
+    120
+}
+
+impl Default for NetworkConfigOptions {
+    fn default() -> Self {
+        NetworkConfigOptions {
+            enabled: false,
+            broker_address: None,
+            rollback_timeout_seconds: default_rollback_timeout_seconds(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operation {
+    Create,
+    Update,
+    Delete,
+}
+
+impl FromStr for Operation {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Operation, ()> {
+        match s {
+            "Create" => Ok(Operation::Create),
+            "Update" => Ok(Operation::Update),
+            "Delete" => Ok(Operation::Delete),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ConnectionType {
+    Wifi {
+        ssid: String,
+        psk: Option<String>,
+    },
+    Cellular {
+        apn: String,
+        username: Option<String>,
+        password: Option<String>,
+    },
+    Ethernet,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ipv4Config {
+    Auto,
+    Manual {
+        address: Ipv4Addr,
+        prefix: u32,
+        gateway: Option<Ipv4Addr>,
+        dns: Vec<Ipv4Addr>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ConnectionProfile {
+    interface_name: Option<String>,
+    connection_type: ConnectionType,
+    ipv4: Ipv4Config,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct NetworkConnectionRequest {
+    uuid: String,
+    operation: Operation,
+    /// Name of the NetworkManager connection profile.
+    connection_id: String,
+    /// Settings of the profile, not present when deleting it.
+    profile: Option<ConnectionProfile>,
+}
+
+fn request_error(message: impl Into<String>) -> DeviceManagerError {
+    DeviceManagerError::NetworkConfigError(message.into())
+}
+
+/// Returns the non empty string field.
+fn string_field(data: &HashMap<String, AstarteType>, key: &str) -> Option<String> {
+    match data.get(key) {
+        Some(AstarteType::String(value)) if !value.is_empty() => Some(value.clone()),
+        _ => None,
+    }
+}
+
+fn required_field(
+    data: &HashMap<String, AstarteType>,
+    key: &str,
+) -> Result<String, DeviceManagerError> {
+    string_field(data, key).ok_or_else(|| request_error(format!("missing {key}")))
+}
+
+fn parse_ipv4(value: &str, key: &str) -> Result<Ipv4Addr, DeviceManagerError> {
+    value
+        .parse()
+        .map_err(|_| request_error(format!("invalid {key} {value}")))
+}
+
+impl TryFrom<&HashMap<String, AstarteType>> for NetworkConnectionRequest {
+    type Error = DeviceManagerError;
+
+    fn try_from(data: &HashMap<String, AstarteType>) -> Result<Self, Self::Error> {
+        let uuid = required_field(data, "uuid")?;
+        let operation = required_field(data, "operation")?;
+        let operation = Operation::from_str(&operation)
+            .map_err(|_| request_error(format!("unsupported operation {operation}")))?;
+        let connection_id = required_field(data, "connectionId")?;
+
+        let profile = match operation {
+            Operation::Create | Operation::Update => Some(ConnectionProfile::try_from(data)?),
+            Operation::Delete => None,
+        };
+
+        Ok(NetworkConnectionRequest {
+            uuid,
+            operation,
+            connection_id,
+            profile,
+        })
+    }
+}
+
+impl TryFrom<&HashMap<String, AstarteType>> for ConnectionProfile {
+    type Error = DeviceManagerError;
+
+    fn try_from(data: &HashMap<String, AstarteType>) -> Result<Self, Self::Error> {
+        let connection_type = match required_field(data, "type")?.as_str() {
+            "wifi" => {
+                // the profile is replaced as a whole, a missing psk would make it open
+                let psk = match (string_field(data, "psk"), string_field(data, "security")) {
+                    (Some(psk), None) => Some(psk),
+                    (None, Some(security)) if security == "open" => None,
+                    (None, None) => {
+                        return Err(request_error(
+                            "missing psk, set security to open for an open network".to_string(),
+                        ))
+                    }
+                    (_, Some(security)) => {
+                        return Err(request_error(format!("unsupported security {security}")))
+                    }
+                };
+
+                ConnectionType::Wifi {
+                    ssid: required_field(data, "ssid")?,
+                    psk,
+                }
+            }
+            "cellular" => ConnectionType::Cellular {
+                apn: required_field(data, "apn")?,
+                username: string_field(data, "username"),
+                password: string_field(data, "password"),
+            },
+            "ethernet" => ConnectionType::Ethernet,
+            connection_type => {
+                return Err(request_error(format!(
+                    "unsupported connection type {connection_type}"
+                )))
+            }
+        };
+
+        let ipv4 = match string_field(data, "ipv4Method").as_deref() {
+            None | Some("auto") => Ipv4Config::Auto,
+            Some("manual") => {
+                let address = required_field(data, "ipv4Address")?;
+                let (address, prefix) = address.split_once('/').ok_or_else(|| {
+                    request_error(format!("missing prefix in ipv4Address {address}"))
+                })?;
+                let prefix = prefix
+                    .parse()
+                    .ok()
+                    .filter(|prefix| *prefix <= 32)
+                    .ok_or_else(|| request_error(format!("invalid ipv4Address prefix {prefix}")))?;
+
+                let gateway = string_field(data, "ipv4Gateway")
+                    .map(|gateway| parse_ipv4(&gateway, "ipv4Gateway"))
+                    .transpose()?;
+
+                let dns = match data.get("ipv4Dns") {
+                    Some(AstarteType::StringArray(dns)) => dns
+                        .iter()
+                        .map(|dns| parse_ipv4(dns, "ipv4Dns"))
+                        .collect::<Result<Vec<Ipv4Addr>, DeviceManagerError>>()?,
+                    _ => Vec::new(),
+                };
+
+                Ipv4Config::Manual {
+                    address: parse_ipv4(address, "ipv4Address")?,
+                    prefix,
+                    gateway,
+                    dns,
+                }
+            }
+            Some(method) => return Err(request_error(format!("unsupported ipv4Method {method}"))),
+        };
+
+        Ok(ConnectionProfile {
+            interface_name: string_field(data, "interfaceName"),
+            connection_type,
+            ipv4,
+        })
+    }
+}
+
+impl ConnectionProfile {
+    /// Builds the NetworkManager settings of the profile.
+    fn to_settings(&self, id: &str, uuid: &str) -> ConnectionSettings {
+        let mut settings = ConnectionSettings::new();
+
+        let connection_type = match self.connection_type {
+            ConnectionType::Wifi { .. } => "802-11-wireless",
+            ConnectionType::Cellular { .. } => "gsm",
+            ConnectionType::Ethernet => "802-3-ethernet",
+        };
+
+        let mut connection = HashMap::from([
+            ("id", Value::from(id.to_string())),
+            ("uuid", Value::from(uuid.to_string())),
+            ("type", Value::from(connection_type)),
+            ("autoconnect", Value::from(true)),
+        ]);
+        if let Some(interface_name) = &self.interface_name {
+            connection.insert("interface-name", Value::from(interface_name.clone()));
+        }
+        settings.insert("connection", connection);
+
+        match &self.connection_type {
+            ConnectionType::Wifi { ssid, psk } => {
+                settings.insert(
+                    "802-11-wireless",
+                    HashMap::from([
+                        ("ssid", Value::from(ssid.as_bytes().to_vec())),
+                        ("mode", Value::from("infrastructure")),
+                    ]),
+                );
+
+                if let Some(psk) = psk {
+                    settings.insert(
+                        "802-11-wireless-security",
+                        HashMap::from([
+                            ("key-mgmt", Value::from("wpa-psk")),
+                            ("psk", Value::from(psk.clone())),
+                        ]),
+                    );
+                }
+            }
+            ConnectionType::Cellular {
+                apn,
+                username,
+                password,
+            } => {
+                let mut gsm = HashMap::from([("apn", Value::from(apn.clone()))]);
+                if let Some(username) = username {
+                    gsm.insert("username", Value::from(username.clone()));
+                }
+                if let Some(password) = password {
+                    gsm.insert("password", Value::from(password.clone()));
+                }
+                settings.insert("gsm", gsm);
+            }
+            ConnectionType::Ethernet => {
+                settings.insert("802-3-ethernet", HashMap::new());
+            }
+        }
+
+        let ipv4 = match &self.ipv4 {
+            Ipv4Config::Auto => HashMap::from([("method", Value::from("auto"))]),
+            Ipv4Config::Manual {
+                address,
+                prefix,
+                gateway,
+                dns,
+            } => {
+                let address_data = vec![HashMap::from([
+                    ("address", Value::from(address.to_string())),
+                    ("prefix", Value::from(*prefix)),
+                ])];
+                // the addresses are in network byte order
+                let dns: Vec<u32> = dns
+                    .iter()
+                    .map(|dns| u32::from_ne_bytes(dns.octets()))
+                    .collect();
+
+                let mut ipv4 = HashMap::from([
+                    ("method", Value::from("manual")),
+                    ("address-data", Value::from(address_data)),
+                    ("dns", Value::from(dns)),
+                ]);
+                if let Some(gateway) = gateway {
+                    ipv4.insert("gateway", Value::from(gateway.to_string()));
+                }
+
+                ipv4
+            }
+        };
+        settings.insert("ipv4", ipv4);
+
+        settings
+    }
+}
+
+#[derive(Debug, Clone, AstarteAggregate, PartialEq)]
+#[allow(non_snake_case)]
+pub struct NetworkConnectionEvent {
+    pub requestUUID: String,
+    /// "Any of: Applied, RolledBack, Failure"
+    pub status: String,
+    pub message: String,
+}
+
+/// Applies the connection profile requests one at a time.
+#[derive(Debug)]
+pub struct NetworkConfig {
+    options: NetworkConfigOptions,
+    broker_lookup: Option<BrokerLookup>,
+    lock: Mutex<()>,
+}
+
+impl NetworkConfig {
+    pub fn new(options: NetworkConfigOptions, broker_lookup: Option<BrokerLookup>) -> Self {
+        NetworkConfig {
+            options,
+            broker_lookup,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// handle io.edgehog.devicemanager.config.NetworkConnection
+    pub async fn handle_request(
+        &self,
+        publisher: &impl Publisher,
+        data: HashMap<String, AstarteType>,
+    ) {
+        let _guard = self.lock.lock().await;
+
+        let event = match NetworkConnectionRequest::try_from(&data) {
+            Ok(request) if !self.options.enabled => {
+                warn!("remote network configuration is disabled");
+
+                NetworkConnectionEvent {
+                    requestUUID: request.uuid,
+                    status: "Failure".to_string(),
+                    message: "remote network configuration is disabled".to_string(),
+                }
+            }
+            Ok(request) => self.apply_request(&request).await,
+            Err(err) => {
+                error!("invalid network connection request: {err}");
+
+                NetworkConnectionEvent {
+                    requestUUID: string_field(&data, "uuid").unwrap_or_default(),
+                    status: "Failure".to_string(),
+                    message: err.to_string(),
+                }
+            }
+        };
+
+        info!(
+            "network connection request {}: {}",
+            event.requestUUID, event.status
+        );
+
+        if let Err(err) = publisher
+            .send_object(NETWORK_CONNECTION_EVENT_INTERFACE, "/event", event)
+            .await
+        {
+            error!("couldn't publish the network connection result: {err}");
+        }
+    }
+
+    async fn apply_request(&self, request: &NetworkConnectionRequest) -> NetworkConnectionEvent {
+        let (status, message) = match self.apply_with_rollback(request).await {
+            Ok(None) => ("Applied", String::new()),
+            Ok(Some(reason)) => ("RolledBack", reason),
+            Err(err) => ("Failure", err.to_string()),
+        };
+
+        NetworkConnectionEvent {
+            requestUUID: request.uuid.clone(),
+            status: status.to_string(),
+            message,
+        }
+    }
+
+    /// Applies the request inside a NetworkManager checkpoint, returns the reason of the rollback
+    /// if the change was rolled back.
+    async fn apply_with_rollback(
+        &self,
+        request: &NetworkConnectionRequest,
+    ) -> Result<Option<String>, DeviceManagerError> {
+        // resolved before the change, while the broker is known to be reachable
+        let broker_address = self.broker_address().await?;
+
+        let connection = zbus::Connection::system().await?;
+        let network_manager = NetworkManagerProxy::new(&connection).await?;
+
+        let timeout = Duration::from_secs(self.options.rollback_timeout_seconds);
+        let checkpoint = network_manager
+            .checkpoint_create(
+                &[],
+                (timeout + CHECKPOINT_MARGIN).as_secs() as u32,
+                CHECKPOINT_FLAGS,
+            )
+            .await?;
+
+        let result = match apply(&connection, &network_manager, request).await {
+            Ok(()) => wait_connectivity(&network_manager, &broker_address, timeout).await,
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(()) => {
+                // the change is applied, the checkpoint would only expire on its own
+                if let Err(err) = network_manager.checkpoint_destroy(&checkpoint).await {
+                    error!(
+                        "couldn't destroy the checkpoint of network connection request {}: {err}",
+                        request.uuid
+                    );
+                }
+
+                Ok(None)
+            }
+            Err(err) => {
+                warn!(
+                    "rolling back network connection request {}: {err}",
+                    request.uuid
+                );
+
+                network_manager.checkpoint_rollback(&checkpoint).await?;
+
+                Ok(Some(err.to_string()))
+            }
+        }
+    }
+
+    async fn broker_address(&self) -> Result<String, DeviceManagerError> {
+        if let Some(address) = &self.options.broker_address {
+            return Ok(address.clone());
+        }
+
+        match &self.broker_lookup {
+            Some(lookup) => lookup.broker_address().await.map_err(|err| {
+                request_error(format!("couldn't look up the broker address: {err}"))
+            }),
+            None => Err(request_error("unknown broker address")),
+        }
+    }
+}
+
+async fn apply(
+    connection: &zbus::Connection,
+    network_manager: &NetworkManagerProxy<'_>,
+    request: &NetworkConnectionRequest,
+) -> Result<(), DeviceManagerError> {
+    let settings = SettingsProxy::new(connection).await?;
+    let existing = find_connection(connection, &settings, &request.connection_id).await?;
+
+    let profile = || {
+        request
+            .profile
+            .as_ref()
+            .ok_or_else(|| request_error("missing connection settings"))
+    };
+
+    match (request.operation, existing) {
+        (Operation::Create, Some(_)) => Err(request_error(format!(
+            "connection {} already exists",
+            request.connection_id
+        ))),
+        (Operation::Create, None) => {
+            let uuid = Uuid::new_v4().to_string();
+            let path = settings
+                .add_connection(profile()?.to_settings(&request.connection_id, &uuid))
+                .await?;
+
+            activate(network_manager, &path).await
+        }
+        (Operation::Update, Some((path, uuid))) => {
+            settings_connection(connection, &path)
+                .await?
+                .update(profile()?.to_settings(&request.connection_id, &uuid))
+                .await?;
+
+            activate(network_manager, &path).await
+        }
+        (Operation::Delete, Some((path, _))) => {
+            settings_connection(connection, &path)
+                .await?
+                .delete()
+                .await?;
+
+            Ok(())
+        }
+        (Operation::Update | Operation::Delete, None) => Err(request_error(format!(
+            "connection {} not found",
+            request.connection_id
+        ))),
+    }
+}
+
+async fn settings_connection<'a>(
+    connection: &zbus::Connection,
+    path: &'a OwnedObjectPath,
+) -> Result<SettingsConnectionProxy<'a>, DeviceManagerError> {
+    let proxy = SettingsConnectionProxy::builder(connection)
+        .path(path.as_str())?
+        .build()
+        .await?;
+
+    Ok(proxy)
+}
+
+/// Returns the path and uuid of the connection profile with the given id.
+async fn find_connection(
+    connection: &zbus::Connection,
+    settings: &SettingsProxy<'_>,
+    id: &str,
+) -> Result<Option<(OwnedObjectPath, String)>, DeviceManagerError> {
+    for path in settings.list_connections().await? {
+        let connection_settings = settings_connection(connection, &path)
+            .await?
+            .get_settings()
+            .await?;
+
+        let field = |key: &str| {
+            connection_settings
+                .get("connection")
+                .and_then(|connection| connection.get(key))
+                .and_then(|value| String::try_from(value.clone()).ok())
+        };
+
+        if field("id").as_deref() == Some(id) {
+            let uuid = field("uuid").unwrap_or_default();
+
+            return Ok(Some((path, uuid)));
+        }
+    }
+
+    Ok(None)
+}
+
+async fn activate(
+    network_manager: &NetworkManagerProxy<'_>,
+    path: &ObjectPath<'_>,
+) -> Result<(), DeviceManagerError> {
+    // let NetworkManager choose the device and access point
+    let any = ObjectPath::try_from("/").map_err(zbus::Error::from)?;
+
+    network_manager
+        .activate_connection(path, &any, &any)
+        .await?;
+
+    Ok(())
+}
+
+/// Waits for NetworkManager to report a connection with a default route and for the broker to
+/// accept a TCP connection.
+///
+/// The Internet connectivity reported by NetworkManager is not used, since the device may reach
+/// the broker through a private network, like a private APN.
+async fn wait_connectivity(
+    network_manager: &NetworkManagerProxy<'_>,
+    broker_address: &str,
+    timeout: Duration,
+) -> Result<(), DeviceManagerError> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        if network_manager.state().await? >= NM_STATE_CONNECTED_SITE {
+            match tokio::time::timeout(BROKER_CONNECT_TIMEOUT, TcpStream::connect(broker_address))
+                .await
+            {
+                Ok(Ok(_)) => return Ok(()),
+                Ok(Err(err)) => debug!("broker {broker_address} not reachable: {err}"),
+                Err(_) => debug!("broker {broker_address} not reachable: timed out"),
+            }
+        }
+
+        if Instant::now() >= deadline {
+            return Err(request_error(format!(
+                "broker {broker_address} not reachable within {} seconds",
+                timeout.as_secs()
+            )));
+        }
+
+        debug!("waiting for the connectivity to be re-established");
+
+        sleep(CONNECTIVITY_POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::Ipv4Addr;
+
+    use astarte_device_sdk::types::AstarteType;
+    use zbus::zvariant::Value;
+
+    use crate::network_config::{
+        ConnectionProfile, ConnectionType, Ipv4Config, NetworkConfigOptions,
+        NetworkConnectionRequest, Operation,
+    };
+
+    fn request(fields: &[(&str, &str)]) -> HashMap<String, AstarteType> {
+        fields
+            .iter()
+            .map(|(key, value)| (key.to_string(), AstarteType::String(value.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn wifi_request_test() {
+        let data = request(&[
+            ("uuid", "b5d5e4c2-1b0c-4a35-9a9c-2d5a4f0c4a11"),
+            ("operation", "Create"),
+            ("connectionId", "site-wifi"),
+            ("type", "wifi"),
+            ("interfaceName", ""),
+            ("ssid", "Plant 3"),
+            ("psk", "secret-psk"),
+        ]);
+
+        let request = NetworkConnectionRequest::try_from(&data).unwrap();
+
+        assert_eq!(
+            request,
+            NetworkConnectionRequest {
+                uuid: "b5d5e4c2-1b0c-4a35-9a9c-2d5a4f0c4a11".to_string(),
+                operation: Operation::Create,
+                connection_id: "site-wifi".to_string(),
+                profile: Some(ConnectionProfile {
+                    interface_name: None,
+                    connection_type: ConnectionType::Wifi {
+                        ssid: "Plant 3".to_string(),
+                        psk: Some("secret-psk".to_string()),
+                    },
+                    ipv4: Ipv4Config::Auto,
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn open_wifi_request_test() {
+        let data = request(&[
+            ("uuid", "1"),
+            ("operation", "Create"),
+            ("connectionId", "guest"),
+            ("type", "wifi"),
+            ("ssid", "Guest"),
+            ("security", "open"),
+        ]);
+
+        let profile = NetworkConnectionRequest::try_from(&data)
+            .unwrap()
+            .profile
+            .unwrap();
+
+        assert_eq!(
+            profile.connection_type,
+            ConnectionType::Wifi {
+                ssid: "Guest".to_string(),
+                psk: None,
+            }
+        );
+        assert!(!profile
+            .to_settings("guest", "uuid-1")
+            .contains_key("802-11-wireless-security"));
+    }
+
+    #[test]
+    fn cellular_static_ip_request_test() {
+        let mut data = request(&[
+            ("uuid", "1"),
+            ("operation", "Update"),
+            ("connectionId", "modem"),
+            ("type", "cellular"),
+            ("interfaceName", "cdc-wdm0"),
+            ("apn", "iot.example"),
+            ("ipv4Method", "manual"),
+            ("ipv4Address", "10.0.0.2/30"),
+            ("ipv4Gateway", "10.0.0.1"),
+        ]);
+        data.insert(
+            "ipv4Dns".to_string(),
+            AstarteType::StringArray(vec!["1.1.1.1".to_string()]),
+        );
+
+        let profile = NetworkConnectionRequest::try_from(&data)
+            .unwrap()
+            .profile
+            .unwrap();
+
+        assert_eq!(profile.interface_name.as_deref(), Some("cdc-wdm0"));
+        assert_eq!(
+            profile.connection_type,
+            ConnectionType::Cellular {
+                apn: "iot.example".to_string(),
+                username: None,
+                password: None,
+            }
+        );
+        assert_eq!(
+            profile.ipv4,
+            Ipv4Config::Manual {
+                address: Ipv4Addr::new(10, 0, 0, 2),
+                prefix: 30,
+                gateway: Some(Ipv4Addr::new(10, 0, 0, 1)),
+                dns: vec![Ipv4Addr::new(1, 1, 1, 1)],
+            }
+        );
+    }
+
+    #[test]
+    fn delete_request_test() {
+        let data = request(&[
+            ("uuid", "1"),
+            ("operation", "Delete"),
+            ("connectionId", "site-wifi"),
+        ]);
+
+        let request = NetworkConnectionRequest::try_from(&data).unwrap();
+
+        assert_eq!(request.operation, Operation::Delete);
+        assert!(request.profile.is_none());
+    }
+
+    #[test]
+    fn invalid_request_test() {
+        let invalid = [
+            request(&[
+                ("uuid", "1"),
+                ("operation", "Create"),
+                ("connectionId", "a"),
+            ]),
+            request(&[
+                ("uuid", "1"),
+                ("operation", "Restart"),
+                ("connectionId", "a"),
+            ]),
+            request(&[
+                ("uuid", "1"),
+                ("operation", "Create"),
+                ("connectionId", "a"),
+                ("type", "wifi"),
+            ]),
+            request(&[
+                ("uuid", "1"),
+                ("operation", "Create"),
+                ("connectionId", "a"),
+                ("type", "ethernet"),
+                ("ipv4Method", "manual"),
+                ("ipv4Address", "10.0.0.300/24"),
+            ]),
+            // the prefix is required
+            request(&[
+                ("uuid", "1"),
+                ("operation", "Create"),
+                ("connectionId", "a"),
+                ("type", "ethernet"),
+                ("ipv4Method", "manual"),
+                ("ipv4Address", "10.0.0.2"),
+            ]),
+            // an open network has to be requested explicitly
+            request(&[
+                ("uuid", "1"),
+                ("operation", "Update"),
+                ("connectionId", "a"),
+                ("type", "wifi"),
+                ("ssid", "Plant 3"),
+            ]),
+            request(&[
+                ("uuid", "1"),
+                ("operation", "Update"),
+                ("connectionId", "a"),
+                ("type", "wifi"),
+                ("ssid", "Plant 3"),
+                ("psk", "secret-psk"),
+                ("security", "open"),
+            ]),
+        ];
+
+        for data in invalid {
+            assert!(NetworkConnectionRequest::try_from(&data).is_err());
+        }
+    }
+
+    #[test]
+    fn wifi_settings_test() {
+        let profile = ConnectionProfile {
+            interface_name: Some("wlan0".to_string()),
+            connection_type: ConnectionType::Wifi {
+                ssid: "Plant 3".to_string(),
+                psk: Some("secret-psk".to_string()),
+            },
+            ipv4: Ipv4Config::Auto,
+        };
+
+        let settings = profile.to_settings("site-wifi", "uuid-1");
+
+        assert_eq!(
+            settings["connection"]["type"],
+            Value::from("802-11-wireless")
+        );
+        assert_eq!(settings["connection"]["id"], Value::from("site-wifi"));
+        assert_eq!(
+            settings["connection"]["interface-name"],
+            Value::from("wlan0")
+        );
+        assert_eq!(
+            settings["802-11-wireless"]["ssid"],
+            Value::from(b"Plant 3".to_vec())
+        );
+        assert_eq!(
+            settings["802-11-wireless-security"]["psk"],
+            Value::from("secret-psk")
+        );
+        assert_eq!(settings["ipv4"]["method"], Value::from("auto"));
+    }
+
+    #[test]
+    fn static_ip_settings_test() {
+        let profile = ConnectionProfile {
+            interface_name: None,
+            connection_type: ConnectionType::Ethernet,
+            ipv4: Ipv4Config::Manual {
+                address: Ipv4Addr::new(192, 168, 1, 10),
+                prefix: 24,
+                gateway: Some(Ipv4Addr::new(192, 168, 1, 1)),
+                dns: vec![Ipv4Addr::new(192, 168, 1, 1)],
+            },
+        };
+
+        let settings = profile.to_settings("lan", "uuid-2");
+
+        assert!(!settings["connection"].contains_key("interface-name"));
+        assert_eq!(settings["ipv4"]["method"], Value::from("manual"));
+        assert_eq!(settings["ipv4"]["gateway"], Value::from("192.168.1.1"));
+        assert_eq!(
+            settings["ipv4"]["dns"],
+            Value::from(vec![u32::from_ne_bytes([192, 168, 1, 1])])
+        );
+    }
+
+    #[test]
+    fn network_config_options_test() {
+        let options: NetworkConfigOptions = toml::from_str("").unwrap();
+        assert_eq!(options, NetworkConfigOptions::default());
+        assert!(!options.enabled);
+        assert_eq!(options.rollback_timeout_seconds, 120);
+
+        let options: NetworkConfigOptions = toml::from_str(
+            r#"
+            enabled = true
+            broker_address = "broker.example.com:8883"
+            rollback_timeout_seconds = 300
+            "#,
+        )
+        .unwrap();
+        assert!(options.enabled);
+        assert_eq!(
+            options.broker_address.as_deref(),
+            Some("broker.example.com:8883")
+        );
+        assert_eq!(options.rollback_timeout_seconds, 300);
+    }
+}
@@ -106,6 +106,24 @@ impl OtaStatus {
             _ => None,
         }
     }
+
+    /// Returns the name of the state, without its data.
+    pub fn name(&self) -> &'static str {
+        match self {
+            OtaStatus::Idle => "Idle",
+            OtaStatus::Init => "Init",
+            OtaStatus::NoPendingOta => "NoPendingOta",
+            OtaStatus::Acknowledged(_) => "Acknowledged",
+            OtaStatus::Downloading(_, _) => "Downloading",
+            OtaStatus::Deploying(_, _) => "Deploying",
+            OtaStatus::Deployed(_) => "Deployed",
+            OtaStatus::Rebooting(_) => "Rebooting",
+            OtaStatus::Rebooted => "Rebooted",
+            OtaStatus::Success(_) => "Success",
+            OtaStatus::Error(_, _) => "Error",
+            OtaStatus::Failure(_, _) => "Failure",
+        }
+    }
 }
 
 /// Provides ota resource accessibility only by talking with it.
@@ -117,6 +135,7 @@ where
     pub system_update: T,
     pub state_repository: U,
     pub download_file_path: String,
+    pub store_directory: String,
     pub ota_status: Arc<RwLock<OtaStatus>>,
 }
 
@@ -134,6 +153,7 @@ where
             system_update,
             state_repository,
             download_file_path: opts.download_directory.clone(),
+            store_directory: opts.store_directory.clone(),
             ota_status: Arc::new(RwLock::new(OtaStatus::Idle)),
         })
     }
@@ -477,6 +497,16 @@ where
 
         info!("Rebooting the device");
 
+        #[cfg(not(test))]
+        if let Err(err) = crate::boot_history::write_shutdown_marker(
+            &self.store_directory,
+            crate::boot_history::ShutdownReason::Ota,
+        )
+        .await
+        {
+            warn!("couldn't record the reboot reason: {err}");
+        }
+
         #[cfg(not(test))]
         if let Err(error) = crate::power_management::reboot().await {
             let message = "Unable to run reboot command";
@@ -769,6 +799,7 @@ mod tests {
                 system_update,
                 state_repository,
                 download_file_path: "/dev/null".to_string(),
+                store_directory: "/dev/null".to_string(),
                 ota_status: Arc::new(RwLock::new(OtaStatus::Idle)),
             }
         }
@@ -779,7 +810,8 @@ mod tests {
             let mock = Ota {
                 system_update,
                 state_repository,
-                download_file_path: path,
+                download_file_path: path.clone(),
+                store_directory: path,
                 ota_status: Arc::new(RwLock::new(OtaStatus::Idle)),
             };
 
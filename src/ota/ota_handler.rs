@@ -120,7 +120,7 @@ impl OtaHandler {
         Ok(())
     }
 
-    async fn get_ota_status(&self) -> Result<OtaStatus, DeviceManagerError> {
+    pub(crate) async fn get_ota_status(&self) -> Result<OtaStatus, DeviceManagerError> {
         let (ota_status_publisher, ota_status_receiver) = oneshot::channel();
         let msg = OtaMessage::GetOtaStatus {
             respond_to: ota_status_publisher,
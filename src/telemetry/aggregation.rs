@@ -0,0 +1,255 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Aggregates the numeric fields sampled by a telemetry task over a publishing window.
+
+use std::collections::{BTreeMap, HashMap};
+
+use astarte_device_sdk::types::AstarteType;
+use astarte_device_sdk::AstarteAggregate;
+use chrono::{DateTime, Utc};
+
+use crate::telemetry::send_policy::as_f64;
+
+/// Statistics of a numeric field over a window, for `io.edgehog.devicemanager.TelemetryAggregate`.
+#[derive(Debug, Clone, AstarteAggregate, PartialEq)]
+#[allow(non_snake_case)]
+pub struct AggregatedValue {
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub last: f64,
+    pub samples: i32,
+    pub windowStart: DateTime<Utc>,
+    pub windowEnd: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Stats {
+    min: f64,
+    max: f64,
+    sum: f64,
+    last: f64,
+    count: i32,
+}
+
+impl Stats {
+    fn new(value: f64) -> Self {
+        Stats {
+            min: value,
+            max: value,
+            sum: value,
+            last: value,
+            count: 1,
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.last = value;
+        self.count += 1;
+    }
+}
+
+/// Samples of a telemetry interface collected since the start of the current window.
+#[derive(Debug)]
+pub struct Window {
+    source: String,
+    start: DateTime<Utc>,
+    fields: BTreeMap<(String, String), Stats>,
+}
+
+impl Window {
+    pub fn new(interface_name: &str, start: DateTime<Utc>) -> Self {
+        let source = interface_name
+            .rsplit('.')
+            .next()
+            .unwrap_or(interface_name)
+            .to_string();
+
+        Window {
+            source,
+            start,
+            fields: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the numeric fields of a sample on the given path, the other fields are ignored.
+    pub fn add(&mut self, path: &str, values: &HashMap<String, AstarteType>) {
+        for (field, value) in values {
+            let value = match as_f64(value) {
+                Some(value) => value,
+                None => continue,
+            };
+
+            self.fields
+                .entry((path.to_string(), field.clone()))
+                .and_modify(|stats| stats.add(value))
+                .or_insert_with(|| Stats::new(value));
+        }
+    }
+
+    /// Closes the window returning the aggregated value of each field, and starts a new one.
+    ///
+    /// The returned path is `<source>/<field>`, where source is the last segment of the interface
+    /// name followed by `.<path>` if the sampled path is not empty, e.g. `StorageUsage.sda1`. The
+    /// sampled path is escaped to a single segment, so the source can't collide with another one.
+    pub fn take(&mut self, end: DateTime<Utc>) -> Vec<(String, AggregatedValue)> {
+        let start = std::mem::replace(&mut self.start, end);
+
+        std::mem::take(&mut self.fields)
+            .into_iter()
+            .map(|((path, field), stats)| {
+                let path = path.trim_matches('/');
+                let source = if path.is_empty() {
+                    self.source.clone()
+                } else {
+                    format!("{}.{}", self.source, path_segment(path))
+                };
+
+                let value = AggregatedValue {
+                    min: stats.min,
+                    max: stats.max,
+                    avg: stats.sum / f64::from(stats.count),
+                    last: stats.last,
+                    samples: stats.count,
+                    windowStart: start,
+                    windowEnd: end,
+                };
+
+                (format!("{source}/{field}"), value)
+            })
+            .collect()
+    }
+}
+
+/// Escapes a sampled path to a valid path segment without dots, every byte other than ASCII
+/// letters, digits and `-` is written as `_` followed by its hex value, e.g. `data/logs` to
+/// `data_2flogs`.
+fn path_segment(path: &str) -> String {
+    path.bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' => char::from(byte).to_string(),
+            _ => format!("_{byte:02x}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::telemetry::aggregation::{path_segment, AggregatedValue, Window};
+    use astarte_device_sdk::types::AstarteType;
+    use chrono::{TimeZone, Utc};
+    use std::collections::HashMap;
+
+    fn sample(avail: i64, boot_id: &str) -> HashMap<String, AstarteType> {
+        HashMap::from([
+            (
+                "availMemoryBytes".to_string(),
+                AstarteType::LongInteger(avail),
+            ),
+            (
+                "bootId".to_string(),
+                AstarteType::String(boot_id.to_string()),
+            ),
+        ])
+    }
+
+    #[test]
+    fn window_aggregation_test() {
+        let start = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let end = Utc.timestamp_opt(1_700_000_300, 0).unwrap();
+
+        let mut window = Window::new("io.edgehog.devicemanager.SystemStatus", start);
+        window.add("", &sample(300, "a"));
+        window.add("", &sample(100, "a"));
+        window.add("", &sample(200, "a"));
+
+        let aggregated = window.take(end);
+
+        assert_eq!(
+            aggregated,
+            vec![(
+                "SystemStatus/availMemoryBytes".to_string(),
+                AggregatedValue {
+                    min: 100.0,
+                    max: 300.0,
+                    avg: 200.0,
+                    last: 200.0,
+                    samples: 3,
+                    windowStart: start,
+                    windowEnd: end,
+                }
+            )]
+        );
+
+        assert!(window.take(end).is_empty());
+    }
+
+    #[test]
+    fn window_paths_test() {
+        let start = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let end = Utc.timestamp_opt(1_700_000_300, 0).unwrap();
+        let next_end = Utc.timestamp_opt(1_700_000_600, 0).unwrap();
+
+        let mut window = Window::new("io.edgehog.devicemanager.StorageUsage", start);
+        window.add(
+            "sda1",
+            &HashMap::from([("freeBytes".to_string(), AstarteType::LongInteger(1))]),
+        );
+        window.add(
+            "sdb1",
+            &HashMap::from([("freeBytes".to_string(), AstarteType::LongInteger(2))]),
+        );
+
+        let paths: Vec<String> = window.take(end).into_iter().map(|(path, _)| path).collect();
+        assert_eq!(
+            paths,
+            ["StorageUsage.sda1/freeBytes", "StorageUsage.sdb1/freeBytes"]
+        );
+
+        window.add(
+            "sda1",
+            &HashMap::from([("freeBytes".to_string(), AstarteType::Double(1.5))]),
+        );
+
+        let (_, value) = window.take(next_end).pop().unwrap();
+        assert_eq!(value.windowStart, end);
+        assert_eq!(value.windowEnd, next_end);
+
+        window.add(
+            "/sensors/temp.1/",
+            &HashMap::from([("value".to_string(), AstarteType::Double(1.5))]),
+        );
+
+        let paths: Vec<String> = window.take(end).into_iter().map(|(path, _)| path).collect();
+        assert_eq!(paths, ["StorageUsage.sensors_2ftemp_2e1/value"]);
+    }
+
+    #[test]
+    fn path_segment_test() {
+        assert_eq!(path_segment("boot-efi"), "boot-efi");
+        assert_eq!(path_segment("data/logs"), "data_2flogs");
+        assert_eq!(path_segment("var.lib"), "var_2elib");
+        assert_ne!(path_segment("a/b"), path_segment("a_2fb"));
+    }
+}
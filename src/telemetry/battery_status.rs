@@ -19,13 +19,25 @@
  */
 
 use astarte_device_sdk::AstarteAggregate;
+use futures::stream::{select_all, StreamExt};
+use log::{debug, warn};
+use serde::Deserialize;
 use std::collections::HashMap;
+use std::path::Path;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::OnceCell;
+use tokio::time::{sleep, Duration};
 
 use crate::error::DeviceManagerError;
+use crate::telemetry::power_supply::{get_power_supplies, PowerSupply, POWER_SUPPLY_PATH};
 use crate::telemetry::upower::device::{BatteryState, DeviceProxy, PowerDeviceType};
 use crate::telemetry::upower::UPowerProxy;
+use crate::telemetry::{TelemetryMessage, TelemetryPayload};
 
-#[derive(Debug, AstarteAggregate, PartialEq)]
+const UPOWER_BUS_NAME: &str = "org.freedesktop.UPower";
+const WATCH_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Debug, AstarteAggregate, PartialEq, Clone)]
 #[allow(non_snake_case)]
 pub struct BatteryStatus {
     levelPercentage: f64,
@@ -47,16 +59,309 @@ impl BatteryStatus {
     }
 }
 
-pub async fn get_battery_status() -> Result<HashMap<String, BatteryStatus>, DeviceManagerError> {
-    let connection = zbus::Connection::system().await?;
-    let upower = UPowerProxy::new(&connection).await?;
-    let devices = upower.enumerate_devices().await?;
+impl From<&PowerSupply> for BatteryStatus {
+    fn from(supply: &PowerSupply) -> Self {
+        let (level_percentage, level_absolute_error) = get_power_supply_level(supply);
+
+        let is_failed = matches!(
+            supply.health.as_deref(),
+            Some(
+                "Dead"
+                    | "Overheat"
+                    | "Over voltage"
+                    | "Over current"
+                    | "Unspecified failure"
+                    | "Watchdog timer expire"
+                    | "Safety timer expire"
+            )
+        );
+
+        let status = match supply.status.as_deref() {
+            _ if supply.present == Some(false) => "Removed",
+            _ if is_failed => "Failure",
+            Some("Charging") => "Charging",
+            Some("Discharging") => "Discharging",
+            Some("Full" | "Not charging") => "Idle",
+            _ => "Unknown",
+        };
+
+        let level_absolute_error = if status == "Unknown" {
+            100_f64
+        } else {
+            level_absolute_error
+        };
+
+        BatteryStatus {
+            levelPercentage: level_percentage,
+            levelAbsoluteError: level_absolute_error,
+            status: status.to_string(),
+        }
+    }
+}
+
+/// Configuration of the battery telemetry source.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct BatteryOptions {
+    /// Level percentages that trigger a publish when crossed in either direction.
+    #[serde(default = "default_level_thresholds")]
+    pub level_thresholds: Vec<f64>,
+}
+
+fn default_level_thresholds() -> Vec<f64> {
+    vec![20.0, 10.0, 5.0]
+}
+
+impl Default for BatteryOptions {
+    fn default() -> Self {
+        BatteryOptions {
+            level_thresholds: default_level_thresholds(),
+        }
+    }
+}
+
+/// Battery telemetry source, shares a single system bus connection between the periodic reads and
+/// the UPower signals watcher.
+#[derive(Debug, Default)]
+pub struct Battery {
+    options: BatteryOptions,
+    connection: OnceCell<zbus::Connection>,
+}
+
+impl Battery {
+    pub fn new(options: BatteryOptions) -> Self {
+        Battery {
+            options,
+            connection: OnceCell::new(),
+        }
+    }
+
+    async fn connection(&self) -> Result<&zbus::Connection, DeviceManagerError> {
+        let connection = self
+            .connection
+            .get_or_try_init(zbus::Connection::system)
+            .await?;
+
+        Ok(connection)
+    }
+
+    /// Checks if UPower is running on the system bus, otherwise the batteries are read from sysfs.
+    async fn is_upower_available(&self) -> bool {
+        let available = async {
+            let dbus = zbus::fdo::DBusProxy::new(self.connection().await?).await?;
+            let name = zbus::names::WellKnownName::from_static_str_unchecked(UPOWER_BUS_NAME);
+
+            dbus.name_has_owner(name.into())
+                .await
+                .map_err(DeviceManagerError::from)
+        };
+
+        match available.await {
+            Ok(available) => available,
+            Err(err) => {
+                debug!("couldn't check UPower availability: {err}");
+
+                false
+            }
+        }
+    }
+
+    /// get structured data for `io.edgehog.devicemanager.BatteryStatus` interface
+    ///
+    /// Falls back to the kernel power_supply class when UPower is not available.
+    pub async fn get_battery_status(
+        &self,
+    ) -> Result<HashMap<String, BatteryStatus>, DeviceManagerError> {
+        if self.is_upower_available().await {
+            get_battery_status_from(self.connection().await?).await
+        } else {
+            get_sysfs_battery_status(Path::new(POWER_SUPPLY_PATH))
+        }
+    }
+
+    /// Watches the UPower devices and publishes a battery status as soon as it changes state, it
+    /// gets added or removed, or its level crosses one of the configured thresholds.
+    ///
+    /// The signals are subscribed again if watching them fails, it only returns if UPower is not
+    /// available.
+    pub async fn watch(
+        &self,
+        communication_channel: &Sender<TelemetryMessage>,
+    ) -> Result<(), DeviceManagerError> {
+        if !self.is_upower_available().await {
+            debug!("UPower not available, battery status is only sent periodically");
+
+            return Ok(());
+        }
+
+        let mut last_sent = HashMap::new();
+
+        loop {
+            match self
+                .watch_devices(&mut last_sent, communication_channel)
+                .await
+            {
+                Ok(()) => warn!("UPower signals stream closed, subscribing again"),
+                Err(err) => warn!("couldn't watch the UPower devices, retrying: {err}"),
+            }
+
+            sleep(WATCH_RETRY_DELAY).await;
+        }
+    }
+
+    async fn watch_devices(
+        &self,
+        last_sent: &mut HashMap<String, BatteryStatus>,
+        communication_channel: &Sender<TelemetryMessage>,
+    ) -> Result<(), DeviceManagerError> {
+        let connection = self.connection().await?;
+        let upower = UPowerProxy::new(connection).await?;
+        let mut added = upower.receive_device_added().await?;
+        let mut removed = upower.receive_device_removed().await?;
+
+        loop {
+            let devices = get_battery_devices(connection).await?;
+
+            let mut changes = Vec::new();
+            for (_, device) in &devices {
+                changes.push(device.receive_state_changed().await.map(|_| ()).boxed());
+                changes.push(
+                    device
+                        .receive_percentage_changed()
+                        .await
+                        .map(|_| ())
+                        .boxed(),
+                );
+                changes.push(
+                    device
+                        .receive_is_present_changed()
+                        .await
+                        .map(|_| ())
+                        .boxed(),
+                );
+            }
+            let mut changes = select_all(changes);
+
+            self.send_changes(&devices, last_sent, communication_channel)
+                .await?;
+
+            loop {
+                tokio::select! {
+                    Some(()) = changes.next() => {
+                        self.send_changes(&devices, last_sent, communication_channel)
+                            .await?;
+                    }
+                    Some(_) = added.next() => {
+                        debug!("power device added");
+                        break;
+                    }
+                    Some(_) = removed.next() => {
+                        debug!("power device removed");
+                        break;
+                    }
+                    else => return Ok(()),
+                }
+            }
+        }
+    }
+
+    async fn send_changes(
+        &self,
+        devices: &[(String, DeviceProxy<'static>)],
+        last_sent: &mut HashMap<String, BatteryStatus>,
+        communication_channel: &Sender<TelemetryMessage>,
+    ) -> Result<(), DeviceManagerError> {
+        let mut current = HashMap::new();
+        for (serial, device) in devices {
+            current.insert(serial.clone(), get_device_battery_status(device).await?);
+        }
+
+        for (path, payload) in
+            get_changed_batteries(last_sent, current, &self.options.level_thresholds)
+        {
+            let _ = communication_channel
+                .send(TelemetryMessage {
+                    path,
+                    payload: TelemetryPayload::BatteryStatus(payload),
+                })
+                .await;
+        }
+
+        Ok(())
+    }
+}
 
+async fn get_battery_status_from(
+    connection: &zbus::Connection,
+) -> Result<HashMap<String, BatteryStatus>, DeviceManagerError> {
     let mut result = HashMap::new();
-    for device_path in devices {
-        dbg!(&result);
+    for (serial, device) in get_battery_devices(connection).await? {
+        result.insert(serial, get_device_battery_status(&device).await?);
+    }
+
+    Ok(result)
+}
+
+/// Reads the batteries supplying the system from a power_supply class directory, keyed by serial
+/// number or by name if the serial number is not available.
+fn get_sysfs_battery_status(
+    path: &Path,
+) -> Result<HashMap<String, BatteryStatus>, DeviceManagerError> {
+    let batteries = get_power_supplies(path)?
+        .into_iter()
+        .filter(PowerSupply::is_system_battery)
+        .map(|supply| {
+            // the BatteryStatus interface has no fields for them
+            debug!(
+                "{} voltage {:?}µV current {:?}µA cycles {:?}",
+                supply.name, supply.voltage_now, supply.current_now, supply.cycle_count
+            );
+
+            let status = BatteryStatus::from(&supply);
+            let key = supply.serial_number.unwrap_or(supply.name);
+
+            (key, status)
+        })
+        .collect();
 
-        let device = DeviceProxy::builder(&connection)
+    Ok(batteries)
+}
+
+/// Returns the level percentage and its absolute error, preferring the exact capacity over the
+/// energy or charge ratio and over the coarse capacity level.
+fn get_power_supply_level(supply: &PowerSupply) -> (f64, f64) {
+    let ratio = |now: Option<f64>, full: Option<f64>| match (now, full) {
+        (Some(now), Some(full)) if full > 0.0 => Some((now / full * 100.0).clamp(0.0, 100.0)),
+        _ => None,
+    };
+
+    if let Some(level) = supply
+        .capacity
+        .or_else(|| ratio(supply.energy_now, supply.energy_full))
+        .or_else(|| ratio(supply.charge_now, supply.charge_full))
+    {
+        return (level, 0.0);
+    }
+
+    match supply.capacity_level.as_deref() {
+        Some("Full") => (100.0, 0.0),
+        Some("High") => (85.0, 15.0),
+        Some("Normal") => (55.0, 25.0),
+        Some("Low") => (17.5, 12.5),
+        Some("Critical") => (2.5, 2.5),
+        _ => (0.0, 100.0),
+    }
+}
+
+/// Returns the UPower devices supplying the system that are batteries, keyed by serial number.
+async fn get_battery_devices(
+    connection: &zbus::Connection,
+) -> Result<Vec<(String, DeviceProxy<'static>)>, DeviceManagerError> {
+    let upower = UPowerProxy::new(connection).await?;
+    let devices = upower.enumerate_devices().await?;
+
+    let mut result = Vec::new();
+    for device_path in devices {
+        let device = DeviceProxy::builder(connection)
             .path(device_path)?
             .build()
             .await?;
@@ -64,20 +369,64 @@ pub async fn get_battery_status() -> Result<HashMap<String, BatteryStatus>, Devi
         if device.power_supply().await?
             && device.power_device_type().await? == PowerDeviceType::Battery
         {
-            result.insert(
-                device.serial().await?,
-                BatteryStatus::new(
-                    device.percentage().await?,
-                    device.state().await?,
-                    device.is_present().await?,
-                )
-                .await,
-            );
+            result.push((device.serial().await?, device));
         }
     }
+
     Ok(result)
 }
 
+async fn get_device_battery_status(
+    device: &DeviceProxy<'_>,
+) -> Result<BatteryStatus, DeviceManagerError> {
+    let status = BatteryStatus::new(
+        device.percentage().await?,
+        device.state().await?,
+        device.is_present().await?,
+    )
+    .await;
+
+    Ok(status)
+}
+
+/// Returns the batteries whose status changed from the last one sent, updating it.
+///
+/// A status is considered changed if the battery is new or was removed, if its status string is
+/// different, or if its level crossed one of the thresholds.
+fn get_changed_batteries(
+    last_sent: &mut HashMap<String, BatteryStatus>,
+    current: HashMap<String, BatteryStatus>,
+    level_thresholds: &[f64],
+) -> Vec<(String, BatteryStatus)> {
+    let mut changed = Vec::new();
+
+    for (serial, last) in last_sent.iter_mut() {
+        if !current.contains_key(serial) && last.status != "Removed" {
+            last.status = "Removed".to_string();
+            changed.push((serial.clone(), last.clone()));
+        }
+    }
+
+    for (serial, status) in current {
+        let is_changed = match last_sent.get(&serial) {
+            Some(last) => {
+                last.status != status.status
+                    || level_thresholds.iter().any(|threshold| {
+                        (last.levelPercentage > *threshold) != (status.levelPercentage > *threshold)
+                    })
+            }
+            None => true,
+        };
+
+        if is_changed {
+            last_sent.insert(serial.clone(), status.clone());
+            changed.push((serial, status));
+        }
+    }
+
+    changed
+}
+
 fn get_status(device_state: BatteryState, is_present: bool) -> String {
     match device_state {
         BatteryState::Charging => "Charging".to_string(),
@@ -104,9 +453,13 @@ fn get_error_level(device_state: BatteryState) -> f64 {
 #[cfg(test)]
 mod tests {
     use crate::telemetry::battery_status::{
-        get_battery_status, get_error_level, get_status, BatteryStatus,
+        get_changed_batteries, get_error_level, get_status, get_sysfs_battery_status, Battery,
+        BatteryStatus,
     };
+    use crate::telemetry::power_supply::tests::write_power_supply;
     use crate::telemetry::upower::device::BatteryState;
+    use std::collections::HashMap;
+    use tempdir::TempDir;
 
     #[tokio::test]
     async fn battery_unknown_new_test() {
@@ -200,7 +553,7 @@ mod tests {
 
     #[tokio::test]
     async fn get_battery_status_test() {
-        let battery_status_result = get_battery_status().await;
+        let battery_status_result = Battery::default().get_battery_status().await;
         println!("{:?}", battery_status_result);
         assert!(battery_status_result.is_ok());
     }
@@ -234,4 +587,119 @@ mod tests {
         assert_eq!(get_error_level(BatteryState::Charging), 0_f64);
         assert_eq!(get_error_level(BatteryState::Unknown), 100_f64);
     }
+
+    #[test]
+    fn get_changed_batteries_test() {
+        let thresholds = [20.0, 10.0, 5.0];
+        let battery = |level: f64, status: &str| BatteryStatus {
+            levelPercentage: level,
+            levelAbsoluteError: 0.0,
+            status: status.to_string(),
+        };
+
+        let mut last_sent = HashMap::new();
+
+        let current = HashMap::from([("BAT0".to_string(), battery(50.0, "Discharging"))]);
+        let changed = get_changed_batteries(&mut last_sent, current, &thresholds);
+        assert_eq!(changed.len(), 1, "new battery");
+
+        let current = HashMap::from([("BAT0".to_string(), battery(25.0, "Discharging"))]);
+        let changed = get_changed_batteries(&mut last_sent, current, &thresholds);
+        assert!(changed.is_empty(), "no threshold crossed");
+
+        let current = HashMap::from([("BAT0".to_string(), battery(19.0, "Discharging"))]);
+        let changed = get_changed_batteries(&mut last_sent, current, &thresholds);
+        assert_eq!(
+            changed,
+            vec![("BAT0".to_string(), battery(19.0, "Discharging"))]
+        );
+
+        let current = HashMap::from([("BAT0".to_string(), battery(19.0, "Charging"))]);
+        let changed = get_changed_batteries(&mut last_sent, current, &thresholds);
+        assert_eq!(
+            changed,
+            vec![("BAT0".to_string(), battery(19.0, "Charging"))]
+        );
+
+        let changed = get_changed_batteries(&mut last_sent, HashMap::new(), &thresholds);
+        assert_eq!(
+            changed,
+            vec![("BAT0".to_string(), battery(19.0, "Removed"))]
+        );
+
+        let changed = get_changed_batteries(&mut last_sent, HashMap::new(), &thresholds);
+        assert!(changed.is_empty(), "already removed");
+    }
+
+    #[test]
+    fn get_sysfs_battery_status_test() {
+        let dir = TempDir::new("power_supply").unwrap();
+        write_power_supply(
+            dir.path(),
+            "BAT0",
+            &[
+                ("type", "Battery"),
+                ("present", "1"),
+                ("status", "Not charging"),
+                ("capacity", "97"),
+                ("serial_number", "SN0042"),
+            ],
+        );
+        write_power_supply(
+            dir.path(),
+            "BAT1",
+            &[
+                ("type", "Battery"),
+                ("present", "1"),
+                ("status", "Discharging"),
+                ("energy_now", "30000000"),
+                ("energy_full", "40000000"),
+            ],
+        );
+        write_power_supply(
+            dir.path(),
+            "BAT2",
+            &[
+                ("type", "Battery"),
+                ("present", "1"),
+                ("status", "Charging"),
+                ("health", "Overheat"),
+                ("capacity_level", "Low"),
+            ],
+        );
+        write_power_supply(
+            dir.path(),
+            "BAT3",
+            &[("type", "Battery"), ("present", "0"), ("status", "Unknown")],
+        );
+        write_power_supply(
+            dir.path(),
+            "BAT4",
+            &[("type", "Battery"), ("present", "1"), ("status", "Unknown")],
+        );
+        write_power_supply(
+            dir.path(),
+            "hid-mouse-battery",
+            &[("type", "Battery"), ("scope", "Device"), ("capacity", "40")],
+        );
+        write_power_supply(dir.path(), "AC", &[("type", "Mains"), ("online", "1")]);
+
+        let batteries = get_sysfs_battery_status(dir.path()).unwrap();
+        let battery = |level: f64, error: f64, status: &str| BatteryStatus {
+            levelPercentage: level,
+            levelAbsoluteError: error,
+            status: status.to_string(),
+        };
+
+        assert_eq!(
+            batteries,
+            HashMap::from([
+                ("SN0042".to_string(), battery(97.0, 0.0, "Idle")),
+                ("BAT1".to_string(), battery(75.0, 0.0, "Discharging")),
+                ("BAT2".to_string(), battery(17.5, 12.5, "Failure")),
+                ("BAT3".to_string(), battery(0.0, 100.0, "Removed")),
+                ("BAT4".to_string(), battery(0.0, 100.0, "Unknown")),
+            ])
+        );
+    }
 }
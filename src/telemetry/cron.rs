@@ -0,0 +1,429 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Cron expressions of the telemetry schedules, evaluated in local time.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use chrono::{
+    DateTime, Datelike, Duration, Local, LocalResult, NaiveDate, NaiveDateTime, TimeZone, Timelike,
+};
+use log::warn;
+use tokio::time::sleep;
+
+use crate::error::DeviceManagerError;
+
+/// Maximum sleep between the checks of the wall clock, so that the clock jumps are noticed.
+const MAX_SLEEP: std::time::Duration = std::time::Duration::from_secs(60);
+/// Expressions like `0 0 30 2 *` never match, give up after the 4 years leap cycle.
+const MAX_SEARCH_DAYS: i64 = 4 * 366;
+/// The days of the week of the dates repeat every 28 years.
+const WEEKDAY_CYCLE_DAYS: usize = 28 * 366;
+
+/// Five fields cron expression: minute, hour, day of month, month and day of week.
+///
+/// The fields accept `*`, values, ranges `a-b`, lists `a,b` and steps `*/n` or `a-b/n`. The day of
+/// week goes from 0 (Sunday) to 6, 7 is Sunday too. As in the classic cron, when both the day of
+/// month and the day of week are restricted, a day matching either of them fires.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    day_of_month_restricted: bool,
+    day_of_week_restricted: bool,
+}
+
+fn cron_error(expression: &str, message: &str) -> DeviceManagerError {
+    DeviceManagerError::CronError(format!("{expression}: {message}"))
+}
+
+/// Parses a field into the bitmask of its values, returns whether it was restricted.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<(u64, bool), String> {
+    let mut mask = 0;
+
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step.parse().map_err(|_| format!("invalid step {step}"))?;
+                if step == 0 {
+                    return Err("zero step".to_string());
+                }
+
+                (range, step)
+            }
+            None => (item, 1),
+        };
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => {
+                let parse = |value: &str| {
+                    value
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|value| (min..=max).contains(value))
+                        .ok_or_else(|| format!("invalid value {value}"))
+                };
+
+                match range.split_once('-') {
+                    Some((start, end)) => (parse(start)?, parse(end)?),
+                    // a single value with a step goes up to the maximum
+                    None if step > 1 => (parse(range)?, max),
+                    None => {
+                        let value = parse(range)?;
+                        (value, value)
+                    }
+                }
+            }
+        };
+
+        if start > end {
+            return Err(format!("invalid range {range}"));
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+
+    Ok((mask, field != "*"))
+}
+
+impl FromStr for CronSchedule {
+    type Err = DeviceManagerError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields.as_slice() else {
+            return Err(cron_error(expression, "expected 5 fields"));
+        };
+
+        let parse = |field: &str, min, max| {
+            parse_field(field, min, max).map_err(|err| cron_error(expression, &err))
+        };
+
+        let (minutes, _) = parse(minutes, 0, 59)?;
+        let (hours, _) = parse(hours, 0, 23)?;
+        let (days_of_month, day_of_month_restricted) = parse(days_of_month, 1, 31)?;
+        let (months, _) = parse(months, 1, 12)?;
+        let (mut days_of_week, day_of_week_restricted) = parse(days_of_week, 0, 7)?;
+
+        // Sunday is both 0 and 7
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week | 1) & !(1 << 7);
+        }
+
+        Ok(CronSchedule {
+            expression: fields.join(" "),
+            minutes,
+            hours,
+            days_of_month,
+            months,
+            days_of_week,
+            day_of_month_restricted,
+            day_of_week_restricted,
+        })
+    }
+}
+
+impl Display for CronSchedule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+fn contains(mask: u64, value: u32) -> bool {
+    mask & (1 << value) != 0
+}
+
+impl CronSchedule {
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day_of_month = contains(self.days_of_month, date.day());
+        let day_of_week = contains(self.days_of_week, date.weekday().num_days_from_sunday());
+
+        if self.day_of_month_restricted && self.day_of_week_restricted {
+            day_of_month || day_of_week
+        } else {
+            day_of_month && day_of_week
+        }
+    }
+
+    /// Returns the first matching minute after the given time, in the same time reference.
+    fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut time =
+            after.date().and_hms_opt(after.hour(), after.minute(), 0)? + Duration::minutes(1);
+        let limit = time + Duration::days(MAX_SEARCH_DAYS);
+
+        while time <= limit {
+            let date = time.date();
+
+            if !contains(self.months, date.month()) {
+                let (year, month) = if date.month() == 12 {
+                    (date.year() + 1, 1)
+                } else {
+                    (date.year(), date.month() + 1)
+                };
+                time = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.matches_day(date) {
+                time = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if !contains(self.hours, time.hour()) {
+                time = date.and_hms_opt(time.hour(), 0, 0)? + Duration::hours(1);
+            } else if !contains(self.minutes, time.minute()) {
+                time += Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+
+        None
+    }
+
+    /// Returns the next fire time after the given one, in its timezone.
+    ///
+    /// The local times skipped by a DST change never fire, the ones repeated fire only the first
+    /// time.
+    pub fn next_fire<Tz: TimeZone>(&self, after: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let timezone = after.timezone();
+        let mut local = after.naive_local();
+
+        // a DST change skips or repeats at most a few hours
+        for _ in 0..(4 * 60) {
+            local = self.next_after(local)?;
+
+            match timezone.from_local_datetime(&local) {
+                LocalResult::Single(time) | LocalResult::Ambiguous(time, _) if time > *after => {
+                    return Some(time)
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    /// Returns the shortest interval in seconds between two fire times, ignoring the DST changes,
+    /// `None` if it doesn't fire more than once.
+    pub fn min_interval(&self) -> Option<u64> {
+        let minutes_of_day: Vec<u32> = (0..24)
+            .filter(|hour| contains(self.hours, *hour))
+            .flat_map(|hour| {
+                (0..60)
+                    .filter(|minute| contains(self.minutes, *minute))
+                    .map(move |minute| hour * 60 + minute)
+            })
+            .collect();
+        let first = *minutes_of_day.first()?;
+        let last = *minutes_of_day.last()?;
+
+        let within_day = minutes_of_day
+            .windows(2)
+            .map(|window| window[1] - window[0])
+            .min();
+
+        // the last fire of a day is followed by the first one of the next matching day
+        let mut min_days: Option<u32> = None;
+        let mut previous: Option<NaiveDate> = None;
+        let mut date = NaiveDate::from_ymd_opt(2000, 1, 1)?;
+        for _ in 0..WEEKDAY_CYCLE_DAYS {
+            if contains(self.months, date.month()) && self.matches_day(date) {
+                if let Some(previous) = previous {
+                    let days = (date - previous).num_days() as u32;
+                    min_days = Some(min_days.map_or(days, |min_days| min_days.min(days)));
+                }
+                previous = Some(date);
+            }
+            date = date.succ_opt()?;
+        }
+        let across_days = min_days.map(|days| days * 24 * 60 + first - last);
+
+        within_day
+            .into_iter()
+            .chain(across_days)
+            .min()
+            .map(|minutes| u64::from(minutes) * 60)
+    }
+
+    /// Waits for the next fire time in local time.
+    ///
+    /// The wall clock is checked at least every minute: when it jumps forward past the fire time
+    /// the wait ends once, when it jumps backward the fire time is recomputed.
+    pub async fn wait_next_fire(&self) {
+        let Some(mut fire) = self.next_fire(&Local::now()) else {
+            warn!("cron expression {self} never fires");
+
+            return std::future::pending().await;
+        };
+
+        loop {
+            let now = Local::now();
+            if now >= fire {
+                return;
+            }
+
+            if let Some(next) = self.next_fire(&now) {
+                fire = fire.min(next);
+            }
+
+            let wait = (fire - now).to_std().unwrap_or_default().min(MAX_SLEEP);
+            sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, NaiveDate, TimeZone, Utc};
+
+    use crate::telemetry::cron::CronSchedule;
+
+    fn next(expression: &str, after: &str) -> String {
+        let cron: CronSchedule = expression.parse().unwrap();
+        let after = Utc.from_utc_datetime(&after.parse::<chrono::NaiveDateTime>().unwrap());
+
+        cron.next_fire(&after)
+            .unwrap()
+            .naive_local()
+            .format("%Y-%m-%dT%H:%M")
+            .to_string()
+    }
+
+    #[test]
+    fn parse_test() {
+        let cron: CronSchedule = "0  3 * * *".parse().unwrap();
+        assert_eq!(cron.to_string(), "0 3 * * *");
+        assert_eq!(cron.minutes, 1);
+        assert_eq!(cron.hours, 1 << 3);
+        assert!(!cron.day_of_month_restricted);
+
+        let cron: CronSchedule = "*/15 9-17 * * 1-5".parse().unwrap();
+        assert_eq!(cron.minutes, 1 | 1 << 15 | 1 << 30 | 1 << 45);
+        assert_eq!(cron.hours.count_ones(), 9);
+        assert_eq!(cron.days_of_week, 0b0111110);
+        assert!(cron.day_of_week_restricted);
+
+        let cron: CronSchedule = "0 0 * * 7".parse().unwrap();
+        assert_eq!(cron.days_of_week, 1);
+
+        let cron: CronSchedule = "5/20 0 1,15 * *".parse().unwrap();
+        assert_eq!(cron.minutes, 1 << 5 | 1 << 25 | 1 << 45);
+        assert_eq!(cron.days_of_month, 1 << 1 | 1 << 15);
+
+        for invalid in [
+            "",
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(invalid.parse::<CronSchedule>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn next_fire_test() {
+        // daily at 03:00
+        assert_eq!(next("0 3 * * *", "2023-06-01T02:59:30"), "2023-06-01T03:00");
+        assert_eq!(next("0 3 * * *", "2023-06-01T03:00:00"), "2023-06-02T03:00");
+
+        // business hours, Thursday 1 June 2023
+        let business_hours = "*/15 9-17 * * 1-5";
+        assert_eq!(
+            next(business_hours, "2023-06-01T10:07:00"),
+            "2023-06-01T10:15"
+        );
+        assert_eq!(
+            next(business_hours, "2023-06-01T17:45:00"),
+            "2023-06-02T09:00"
+        );
+        assert_eq!(
+            next(business_hours, "2023-06-02T17:50:00"),
+            "2023-06-05T09:00"
+        );
+
+        // the end of the month and of the year
+        assert_eq!(next("0 0 1 * *", "2023-12-15T00:00:00"), "2024-01-01T00:00");
+        assert_eq!(
+            next("0 12 29 2 *", "2023-03-01T00:00:00"),
+            "2024-02-29T12:00"
+        );
+
+        // either the day of month or the day of week
+        assert_eq!(
+            next("0 0 15 * 0", "2023-06-01T00:00:00"),
+            "2023-06-04T00:00"
+        );
+        assert_eq!(
+            next("0 0 15 * 0", "2023-06-12T00:00:00"),
+            "2023-06-15T00:00"
+        );
+    }
+
+    #[test]
+    fn next_fire_timezone_test() {
+        let cron: CronSchedule = "0 3 * * *".parse().unwrap();
+        let timezone = FixedOffset::east_opt(2 * 3600).unwrap();
+        let after = timezone
+            .from_local_datetime(
+                &NaiveDate::from_ymd_opt(2023, 6, 1)
+                    .unwrap()
+                    .and_hms_opt(4, 0, 0)
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let fire = cron.next_fire(&after).unwrap();
+        assert_eq!(fire.naive_local().to_string(), "2023-06-02 03:00:00");
+        assert_eq!(fire.naive_utc().to_string(), "2023-06-02 01:00:00");
+    }
+
+    #[test]
+    fn min_interval_test() {
+        let min_interval =
+            |expression: &str| expression.parse::<CronSchedule>().unwrap().min_interval();
+
+        assert_eq!(min_interval("* * * * *"), Some(60));
+        assert_eq!(min_interval("*/15 * * * *"), Some(15 * 60));
+        assert_eq!(min_interval("0,50 * * * *"), Some(10 * 60));
+        assert_eq!(min_interval("0 3 * * *"), Some(24 * 3600));
+        assert_eq!(min_interval("0 22,2 * * *"), Some(4 * 3600));
+        assert_eq!(min_interval("0 3 * * 1"), Some(7 * 24 * 3600));
+        assert_eq!(min_interval("0 3 * * 1,5"), Some(3 * 24 * 3600));
+        assert_eq!(min_interval("0 0 1 1 *"), Some(365 * 24 * 3600));
+        assert_eq!(min_interval("0 0 30 2 *"), None);
+    }
+
+    #[test]
+    fn never_fires_test() {
+        let cron: CronSchedule = "0 0 30 2 *".parse().unwrap();
+
+        assert!(cron.next_fire(&Utc::now()).is_none());
+    }
+}
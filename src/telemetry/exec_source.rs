@@ -0,0 +1,529 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Custom telemetry published by running a configured command and parsing its JSON output.
+//!
+//! The command must print a JSON object whose keys are the interface paths. For interfaces with
+//! object aggregation each value is an object with the fields of the path, for individual
+//! interfaces it is the value of the path:
+//!
+//! ```json
+//! { "line1": { "count": 12, "temperature": 31.5 }, "line2": { "count": 3, "temperature": 29.0 } }
+//! ```
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Stdio;
+
+use astarte_device_sdk::types::AstarteType;
+use chrono::{DateTime, Utc};
+use log::{error, warn};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use tokio::time::Duration;
+
+use crate::error::DeviceManagerError;
+
+/// Configuration of a command publishing a custom telemetry interface.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct ExecSourceOptions {
+    /// Name of the interface in `interfaces_directory` the output is published on.
+    pub interface_name: String,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Maximum time to wait for the command to exit, it is killed afterwards.
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+    /// Maximum size of the standard output, longer outputs are discarded.
+    #[serde(default = "default_max_output_bytes")]
+    pub max_output_bytes: usize,
+}
+
+fn default_timeout_seconds() -> u64 {
+    10
+}
+
+fn default_max_output_bytes() -> usize {
+    64 * 1024
+}
+
+/// Value of a path of a custom telemetry interface.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExecValue {
+    Individual(AstarteType),
+    Object(HashMap<String, AstarteType>),
+}
+
+/// The parts of an Astarte interface needed to convert the JSON output.
+#[derive(Debug, Deserialize)]
+struct InterfaceJson {
+    interface_name: String,
+    ownership: String,
+    #[serde(default)]
+    aggregation: Option<String>,
+    mappings: Vec<MappingJson>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MappingJson {
+    endpoint: String,
+    #[serde(rename = "type")]
+    mapping_type: String,
+}
+
+impl InterfaceJson {
+    fn is_object(&self) -> bool {
+        self.aggregation.as_deref() == Some("object")
+    }
+
+    /// Returns the type of the mapping matching the path, parameters match any segment.
+    fn mapping_type(&self, path: &str) -> Option<&str> {
+        let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+
+        self.mappings
+            .iter()
+            .find(|mapping| {
+                let endpoint: Vec<&str> = mapping
+                    .endpoint
+                    .trim_start_matches('/')
+                    .split('/')
+                    .collect();
+
+                endpoint.len() == segments.len()
+                    && endpoint.iter().zip(&segments).all(|(endpoint, segment)| {
+                        !segment.is_empty() && (endpoint.starts_with("%{") || endpoint == segment)
+                    })
+            })
+            .map(|mapping| mapping.mapping_type.as_str())
+    }
+}
+
+#[derive(Debug)]
+struct ExecSource {
+    options: ExecSourceOptions,
+    interface: InterfaceJson,
+}
+
+/// Custom telemetry sources, by interface name.
+#[derive(Debug, Default)]
+pub struct ExecSources {
+    sources: HashMap<String, ExecSource>,
+}
+
+impl ExecSources {
+    /// Creates the sources, reading their interfaces from the interfaces directory.
+    ///
+    /// The sources whose interface is missing or not device owned are skipped, so that a wrong
+    /// configuration doesn't prevent the runtime from starting.
+    pub fn new(options: Vec<ExecSourceOptions>, interfaces_directory: &str) -> Self {
+        if options.is_empty() {
+            return Self::default();
+        }
+
+        let mut interfaces = match read_interfaces(Path::new(interfaces_directory)) {
+            Ok(interfaces) => interfaces,
+            Err(err) => {
+                error!("couldn't read the interfaces of the exec sources: {err}");
+
+                return Self::default();
+            }
+        };
+
+        let mut sources = HashMap::new();
+        for options in options {
+            match source_interface(&mut interfaces, &options, interfaces_directory) {
+                Ok(interface) => {
+                    sources.insert(
+                        options.interface_name.clone(),
+                        ExecSource { options, interface },
+                    );
+                }
+                Err(err) => error!("ignoring the exec source: {err}"),
+            }
+        }
+
+        ExecSources { sources }
+    }
+
+    pub fn contains(&self, interface_name: &str) -> bool {
+        self.sources.contains_key(interface_name)
+    }
+
+    /// Runs the command of the interface, returning the value of each path it printed.
+    pub async fn get_data(
+        &self,
+        interface_name: &str,
+    ) -> Result<Vec<(String, ExecValue)>, DeviceManagerError> {
+        let source = self.sources.get(interface_name).ok_or_else(|| {
+            DeviceManagerError::ExecSourceError(format!("no source for {interface_name}"))
+        })?;
+
+        let output = run(&source.options).await?;
+
+        parse_output(&output, &source.interface)
+    }
+}
+
+fn source_interface(
+    interfaces: &mut HashMap<String, InterfaceJson>,
+    options: &ExecSourceOptions,
+    interfaces_directory: &str,
+) -> Result<InterfaceJson, DeviceManagerError> {
+    let interface = interfaces.remove(&options.interface_name).ok_or_else(|| {
+        DeviceManagerError::ExecSourceError(format!(
+            "interface {} not found in {interfaces_directory}",
+            options.interface_name
+        ))
+    })?;
+
+    if interface.ownership != "device" {
+        return Err(DeviceManagerError::ExecSourceError(format!(
+            "interface {} is not device owned",
+            options.interface_name
+        )));
+    }
+
+    Ok(interface)
+}
+
+/// Reads the interfaces in the directory, skipping the malformed ones.
+fn read_interfaces(dir: &Path) -> Result<HashMap<String, InterfaceJson>, DeviceManagerError> {
+    let mut interfaces = HashMap::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().map_or(true, |ext| ext != "json") {
+            continue;
+        }
+
+        let interface = std::fs::read(&path)
+            .map_err(DeviceManagerError::from)
+            .and_then(|content| {
+                serde_json::from_slice::<InterfaceJson>(&content).map_err(DeviceManagerError::from)
+            });
+        match interface {
+            Ok(interface) => {
+                interfaces.insert(interface.interface_name.clone(), interface);
+            }
+            Err(err) => warn!("couldn't read the interface {}: {err}", path.display()),
+        }
+    }
+
+    Ok(interfaces)
+}
+
+async fn run(options: &ExecSourceOptions) -> Result<Vec<u8>, DeviceManagerError> {
+    let mut child = Command::new(&options.command)
+        .args(&options.args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let max_output_bytes = options.max_output_bytes;
+    let stdout = child.stdout.take();
+    let read_output = async move {
+        let mut output = Vec::new();
+        if let Some(stdout) = stdout {
+            // read one byte more than allowed to detect longer outputs
+            stdout
+                .take(max_output_bytes as u64 + 1)
+                .read_to_end(&mut output)
+                .await?;
+        }
+
+        if output.len() > max_output_bytes {
+            return Err(DeviceManagerError::ExecSourceError(format!(
+                "output longer than {max_output_bytes} bytes"
+            )));
+        }
+
+        let status = child.wait().await?;
+        if !status.success() {
+            return Err(DeviceManagerError::ExecSourceError(format!(
+                "command exited with {status}"
+            )));
+        }
+
+        Ok(output)
+    };
+
+    tokio::time::timeout(Duration::from_secs(options.timeout_seconds), read_output)
+        .await
+        .map_err(|_| {
+            DeviceManagerError::ExecSourceError(format!(
+                "{} timed out after {} seconds",
+                options.command, options.timeout_seconds
+            ))
+        })?
+}
+
+fn parse_output(
+    output: &[u8],
+    interface: &InterfaceJson,
+) -> Result<Vec<(String, ExecValue)>, DeviceManagerError> {
+    let paths: serde_json::Map<String, Value> = serde_json::from_slice(output)?;
+
+    let mut values = Vec::new();
+    for (path, value) in paths {
+        let path = path.trim_matches('/').to_string();
+
+        let value = if interface.is_object() {
+            let fields = value.as_object().ok_or_else(|| {
+                DeviceManagerError::ExecSourceError(format!("{path} is not an object"))
+            })?;
+
+            let mut object = HashMap::new();
+            for (field, value) in fields {
+                let endpoint = format!("{path}/{field}");
+                object.insert(field.clone(), convert(interface, &endpoint, value)?);
+            }
+
+            ExecValue::Object(object)
+        } else {
+            ExecValue::Individual(convert(interface, &path, &value)?)
+        };
+
+        values.push((path, value));
+    }
+
+    Ok(values)
+}
+
+/// Converts a JSON value to the type of the interface mapping of the path.
+fn convert(
+    interface: &InterfaceJson,
+    path: &str,
+    value: &Value,
+) -> Result<AstarteType, DeviceManagerError> {
+    let mapping_type = interface.mapping_type(path).ok_or_else(|| {
+        DeviceManagerError::ExecSourceError(format!(
+            "no mapping for {path} in {}",
+            interface.interface_name
+        ))
+    })?;
+
+    let converted = match mapping_type {
+        "double" => value.as_f64().map(AstarteType::Double),
+        "integer" => as_i32(value).map(AstarteType::Integer),
+        "longinteger" => value.as_i64().map(AstarteType::LongInteger),
+        "boolean" => value.as_bool().map(AstarteType::Boolean),
+        "string" => as_string(value).map(AstarteType::String),
+        "datetime" => as_datetime(value).map(AstarteType::DateTime),
+        "doublearray" => as_array(value, Value::as_f64).map(AstarteType::DoubleArray),
+        "integerarray" => as_array(value, as_i32).map(AstarteType::IntegerArray),
+        "longintegerarray" => as_array(value, Value::as_i64).map(AstarteType::LongIntegerArray),
+        "booleanarray" => as_array(value, Value::as_bool).map(AstarteType::BooleanArray),
+        "stringarray" => as_array(value, as_string).map(AstarteType::StringArray),
+        "datetimearray" => as_array(value, as_datetime).map(AstarteType::DateTimeArray),
+        _ => None,
+    };
+
+    converted.ok_or_else(|| {
+        DeviceManagerError::ExecSourceError(format!("invalid {mapping_type} value for {path}"))
+    })
+}
+
+fn as_i32(value: &Value) -> Option<i32> {
+    value.as_i64().and_then(|value| i32::try_from(value).ok())
+}
+
+fn as_string(value: &Value) -> Option<String> {
+    value.as_str().map(ToString::to_string)
+}
+
+fn as_datetime(value: &Value) -> Option<DateTime<Utc>> {
+    value
+        .as_str()
+        .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+        .map(|value| value.with_timezone(&Utc))
+}
+
+fn as_array<T>(value: &Value, convert: impl Fn(&Value) -> Option<T>) -> Option<Vec<T>> {
+    value.as_array()?.iter().map(convert).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::telemetry::exec_source::{
+        parse_output, run, ExecSourceOptions, ExecSources, ExecValue, InterfaceJson,
+    };
+    use astarte_device_sdk::types::AstarteType;
+    use std::collections::HashMap;
+    use tempdir::TempDir;
+
+    const OBJECT_INTERFACE: &str = r#"{
+        "interface_name": "com.example.PlcCounters",
+        "version_major": 0,
+        "version_minor": 1,
+        "type": "datastream",
+        "ownership": "device",
+        "aggregation": "object",
+        "mappings": [
+            { "endpoint": "/%{line}/count", "type": "longinteger" },
+            { "endpoint": "/%{line}/temperature", "type": "double" },
+            { "endpoint": "/%{line}/alarms", "type": "stringarray" }
+        ]
+    }"#;
+
+    const INDIVIDUAL_INTERFACE: &str = r#"{
+        "interface_name": "com.example.Sensors",
+        "version_major": 0,
+        "version_minor": 1,
+        "type": "datastream",
+        "ownership": "device",
+        "mappings": [
+            { "endpoint": "/humidity", "type": "integer" },
+            { "endpoint": "/lastCalibration", "type": "datetime" }
+        ]
+    }"#;
+
+    fn exec_options(command: &str, args: &[&str]) -> ExecSourceOptions {
+        ExecSourceOptions {
+            interface_name: "com.example.Sensors".to_string(),
+            command: command.to_string(),
+            args: args.iter().map(ToString::to_string).collect(),
+            timeout_seconds: 5,
+            max_output_bytes: 1024,
+        }
+    }
+
+    #[test]
+    fn parse_object_output_test() {
+        let interface: InterfaceJson = serde_json::from_str(OBJECT_INTERFACE).unwrap();
+        let output = br#"{"line1": {"count": 12, "temperature": 31.5, "alarms": ["door"]}}"#;
+
+        let values = parse_output(output, &interface).unwrap();
+
+        assert_eq!(
+            values,
+            vec![(
+                "line1".to_string(),
+                ExecValue::Object(HashMap::from([
+                    ("count".to_string(), AstarteType::LongInteger(12)),
+                    ("temperature".to_string(), AstarteType::Double(31.5)),
+                    (
+                        "alarms".to_string(),
+                        AstarteType::StringArray(vec!["door".to_string()])
+                    ),
+                ]))
+            )]
+        );
+    }
+
+    #[test]
+    fn parse_individual_output_test() {
+        let interface: InterfaceJson = serde_json::from_str(INDIVIDUAL_INTERFACE).unwrap();
+        let output = br#"{"/humidity": 45, "lastCalibration": "2023-10-12T08:30:00Z"}"#;
+
+        let values = parse_output(output, &interface).unwrap();
+
+        assert_eq!(values.len(), 2);
+        assert!(values.contains(&(
+            "humidity".to_string(),
+            ExecValue::Individual(AstarteType::Integer(45))
+        )));
+        assert!(values.iter().any(|(path, value)| path == "lastCalibration"
+            && matches!(value, ExecValue::Individual(AstarteType::DateTime(_)))));
+    }
+
+    #[test]
+    fn parse_invalid_output_test() {
+        let interface: InterfaceJson = serde_json::from_str(INDIVIDUAL_INTERFACE).unwrap();
+
+        // unknown path
+        assert!(parse_output(br#"{"pressure": 1}"#, &interface).is_err());
+        // integer overflow
+        assert!(parse_output(br#"{"humidity": 4294967296}"#, &interface).is_err());
+        // wrong type
+        assert!(parse_output(br#"{"humidity": "high"}"#, &interface).is_err());
+        // not an object
+        assert!(parse_output(b"[1, 2]", &interface).is_err());
+
+        let interface: InterfaceJson = serde_json::from_str(OBJECT_INTERFACE).unwrap();
+        assert!(parse_output(br#"{"line1": 12}"#, &interface).is_err());
+        assert!(parse_output(br#"{"line1/extra": {"count": 12}}"#, &interface).is_err());
+    }
+
+    #[tokio::test]
+    async fn run_test() {
+        let output = run(&exec_options("sh", &["-c", "echo '{\"humidity\": 45}'"]))
+            .await
+            .unwrap();
+
+        assert_eq!(output, b"{\"humidity\": 45}\n");
+    }
+
+    #[tokio::test]
+    async fn run_failures_test() {
+        // non zero exit status
+        assert!(run(&exec_options("sh", &["-c", "exit 1"])).await.is_err());
+        // output too long
+        assert!(run(&exec_options("sh", &["-c", "head -c 2048 /dev/zero"]))
+            .await
+            .is_err());
+        // missing command
+        assert!(run(&exec_options("/nonexistent/command", &[]))
+            .await
+            .is_err());
+
+        let mut options = exec_options("sleep", &["10"]);
+        options.timeout_seconds = 1;
+        assert!(run(&options).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn exec_sources_test() {
+        let dir = TempDir::new("interfaces").unwrap();
+        std::fs::write(
+            dir.path().join("com.example.Sensors.json"),
+            INDIVIDUAL_INTERFACE,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("README.md"), "not an interface").unwrap();
+
+        let interfaces_directory = dir.path().to_str().unwrap();
+        let options = exec_options("sh", &["-c", "echo '{\"humidity\": 45}'"]);
+
+        std::fs::write(dir.path().join("com.example.Broken.json"), "{").unwrap();
+        let missing = ExecSourceOptions {
+            interface_name: "com.example.Missing".to_string(),
+            ..options.clone()
+        };
+
+        // the wrong sources are skipped, the others are still available
+        let sources = ExecSources::new(vec![options, missing], interfaces_directory);
+        assert!(!sources.contains("com.example.Missing"));
+        assert!(sources.contains("com.example.Sensors"));
+
+        let values = sources.get_data("com.example.Sensors").await.unwrap();
+        assert_eq!(
+            values,
+            vec![(
+                "humidity".to_string(),
+                ExecValue::Individual(AstarteType::Integer(45))
+            )]
+        );
+    }
+}
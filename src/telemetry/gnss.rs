@@ -0,0 +1,355 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Position telemetry read from a local [gpsd](https://gpsd.io/) daemon.
+
+use astarte_device_sdk::AstarteAggregate;
+use chrono::{DateTime, Utc};
+use log::debug;
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+
+use crate::error::DeviceManagerError;
+
+const GPSD_WATCH_COMMAND: &[u8] = b"?WATCH={\"enable\":true,\"json\":true};\n";
+const EARTH_RADIUS_METERS: f64 = 6_371_008.8;
+
+/// Configuration of the gpsd telemetry source.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct GnssOptions {
+    /// Address of the gpsd socket, `localhost:2947` by default.
+    #[serde(default = "default_gpsd_address")]
+    pub gpsd_address: String,
+    /// Positions closer than this distance to the last published one are not sent.
+    #[serde(default)]
+    pub min_distance_meters: f64,
+    /// Maximum time to wait for a fix from gpsd.
+    #[serde(default = "default_fix_timeout_seconds")]
+    pub fix_timeout_seconds: u64,
+}
+
+fn default_gpsd_address() -> String {
+    "localhost:2947".to_string()
+}
+
+fn default_fix_timeout_seconds() -> u64 {
+    5
+}
+
+impl Default for GnssOptions {
+    fn default() -> Self {
+        GnssOptions {
+            gpsd_address: default_gpsd_address(),
+            min_distance_meters: 0.0,
+            fix_timeout_seconds: default_fix_timeout_seconds(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, AstarteAggregate, PartialEq)]
+#[allow(non_snake_case)]
+pub struct GnssPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Altitude in meters, 0 if the fix is not 3D.
+    pub altitude: f64,
+    /// Horizontal accuracy in meters.
+    pub accuracy: f64,
+    /// Speed over ground in meters per second.
+    pub speed: f64,
+    /// "Fix mode string, any of: 2D, 3D"
+    pub fixMode: String,
+}
+
+/// A position fix reported by gpsd.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GnssFix {
+    /// Name of the receiver, used as the interface path.
+    pub device: String,
+    pub position: GnssPosition,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Time-Position-Velocity report of the gpsd JSON protocol.
+#[derive(Debug, Deserialize)]
+struct Tpv {
+    device: Option<String>,
+    mode: u8,
+    time: Option<String>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    #[serde(rename = "altHAE")]
+    alt_hae: Option<f64>,
+    alt: Option<f64>,
+    eph: Option<f64>,
+    epx: Option<f64>,
+    epy: Option<f64>,
+    speed: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "class")]
+enum GpsdMessage {
+    #[serde(rename = "TPV")]
+    Tpv(Tpv),
+    #[serde(other)]
+    Other,
+}
+
+/// gpsd telemetry source, keeps the last published position to apply the distance deadband.
+#[derive(Debug, Default)]
+pub struct Gnss {
+    options: GnssOptions,
+    last_sent: Mutex<Option<GnssFix>>,
+}
+
+impl Gnss {
+    pub fn new(options: GnssOptions) -> Self {
+        Gnss {
+            options,
+            last_sent: Mutex::new(None),
+        }
+    }
+
+    /// get structured data for `io.edgehog.devicemanager.GnssPosition` interface
+    ///
+    /// Returns `None` if the new fix is within the configured distance from the last one sent.
+    pub async fn get_position(&self) -> Result<Option<GnssFix>, DeviceManagerError> {
+        let timeout = Duration::from_secs(self.options.fix_timeout_seconds);
+        let fix = tokio::time::timeout(timeout, read_fix(&self.options.gpsd_address))
+            .await
+            .map_err(|_| DeviceManagerError::GpsdError("timed out waiting for a fix".into()))??;
+
+        let mut last_sent = self.last_sent.lock().await;
+        if let Some(last) = last_sent.as_ref() {
+            if !is_outside_deadband(last, &fix, self.options.min_distance_meters) {
+                debug!("position inside the deadband, skipping");
+
+                return Ok(None);
+            }
+        }
+
+        *last_sent = Some(fix.clone());
+
+        Ok(Some(fix))
+    }
+}
+
+async fn read_fix(address: &str) -> Result<GnssFix, DeviceManagerError> {
+    let mut stream = TcpStream::connect(address).await?;
+    stream.write_all(GPSD_WATCH_COMMAND).await?;
+
+    let mut lines = BufReader::new(stream).lines();
+    while let Some(line) = lines.next_line().await? {
+        if let Some(fix) = parse_fix(&line)? {
+            return Ok(fix);
+        }
+    }
+
+    Err(DeviceManagerError::GpsdError(
+        "connection closed before a fix".into(),
+    ))
+}
+
+/// Parses a gpsd JSON line, returning a fix only for TPV reports with at least a 2D fix.
+fn parse_fix(line: &str) -> Result<Option<GnssFix>, DeviceManagerError> {
+    let tpv = match serde_json::from_str(line)? {
+        GpsdMessage::Tpv(tpv) => tpv,
+        GpsdMessage::Other => return Ok(None),
+    };
+
+    let fix_mode = match tpv.mode {
+        2 => "2D",
+        3 => "3D",
+        _ => return Ok(None),
+    };
+
+    let (latitude, longitude) = match (tpv.lat, tpv.lon) {
+        (Some(lat), Some(lon)) => (lat, lon),
+        _ => return Ok(None),
+    };
+
+    let altitude = if tpv.mode == 3 {
+        tpv.alt_hae.or(tpv.alt).unwrap_or_default()
+    } else {
+        0.0
+    };
+
+    let accuracy = match (tpv.eph, tpv.epx, tpv.epy) {
+        (Some(eph), _, _) => eph,
+        (None, Some(epx), Some(epy)) => epx.max(epy),
+        _ => 0.0,
+    };
+
+    let timestamp = match tpv.time {
+        Some(time) => DateTime::parse_from_rfc3339(&time)
+            .map_err(|err| DeviceManagerError::GpsdError(err.to_string()))?
+            .with_timezone(&Utc),
+        None => Utc::now(),
+    };
+
+    let device = tpv
+        .device
+        .map(|device| device.replace("/dev/", ""))
+        .unwrap_or_else(|| "gpsd".to_string());
+
+    Ok(Some(GnssFix {
+        device,
+        position: GnssPosition {
+            latitude,
+            longitude,
+            altitude,
+            accuracy,
+            speed: tpv.speed.unwrap_or_default(),
+            fixMode: fix_mode.to_string(),
+        },
+        timestamp,
+    }))
+}
+
+fn is_outside_deadband(last: &GnssFix, fix: &GnssFix, min_distance_meters: f64) -> bool {
+    last.device != fix.device
+        || last.position.fixMode != fix.position.fixMode
+        || distance_meters(&last.position, &fix.position) >= min_distance_meters
+}
+
+/// Great-circle distance between two positions, using the haversine formula.
+fn distance_meters(a: &GnssPosition, b: &GnssPosition) -> f64 {
+    let lat_a = a.latitude.to_radians();
+    let lat_b = b.latitude.to_radians();
+    let delta_lat = (b.latitude - a.latitude).to_radians();
+    let delta_lon = (b.longitude - a.longitude).to_radians();
+
+    let h = (delta_lat / 2.0).sin().powi(2)
+        + lat_a.cos() * lat_b.cos() * (delta_lon / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_METERS * h.sqrt().asin()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::telemetry::gnss::{distance_meters, parse_fix, Gnss, GnssOptions, GnssPosition};
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    const TPV_3D: &str = r#"{"class":"TPV","device":"/dev/ttyUSB0","mode":3,"time":"2023-10-12T08:30:00.000Z","lat":45.4642,"lon":9.19,"altHAE":122.5,"eph":4.2,"speed":12.5}"#;
+    const TPV_2D: &str = r#"{"class":"TPV","device":"/dev/ttyUSB0","mode":2,"time":"2023-10-12T08:30:01.000Z","lat":45.4642,"lon":9.19,"alt":50.0,"epx":3.0,"epy":5.0}"#;
+
+    fn position(latitude: f64, longitude: f64) -> GnssPosition {
+        GnssPosition {
+            latitude,
+            longitude,
+            altitude: 0.0,
+            accuracy: 0.0,
+            speed: 0.0,
+            fixMode: "3D".to_string(),
+        }
+    }
+
+    async fn fake_gpsd(lines: &'static [&'static str]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                for line in lines {
+                    let _ = socket.write_all(format!("{line}\n").as_bytes()).await;
+                }
+            }
+        });
+
+        address
+    }
+
+    #[test]
+    fn parse_fix_3d_test() {
+        let fix = parse_fix(TPV_3D).unwrap().unwrap();
+
+        assert_eq!(fix.device, "ttyUSB0");
+        assert_eq!(fix.timestamp.to_rfc3339(), "2023-10-12T08:30:00+00:00");
+        assert_eq!(
+            fix.position,
+            GnssPosition {
+                latitude: 45.4642,
+                longitude: 9.19,
+                altitude: 122.5,
+                accuracy: 4.2,
+                speed: 12.5,
+                fixMode: "3D".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_fix_2d_test() {
+        let fix = parse_fix(TPV_2D).unwrap().unwrap();
+
+        assert_eq!(fix.position.altitude, 0.0);
+        assert_eq!(fix.position.accuracy, 5.0);
+        assert_eq!(fix.position.speed, 0.0);
+        assert_eq!(fix.position.fixMode, "2D");
+    }
+
+    #[test]
+    fn parse_fix_skipped_test() {
+        let no_fix = r#"{"class":"TPV","device":"/dev/ttyUSB0","mode":1}"#;
+        let version =
+            r#"{"class":"VERSION","release":"3.25","rev":"3.25","proto_major":3,"proto_minor":15}"#;
+
+        assert!(parse_fix(no_fix).unwrap().is_none());
+        assert!(parse_fix(version).unwrap().is_none());
+        assert!(parse_fix("not json").is_err());
+    }
+
+    #[test]
+    fn distance_meters_test() {
+        let milan = position(45.4642, 9.19);
+        let turin = position(45.0703, 7.6869);
+
+        let distance = distance_meters(&milan, &turin);
+
+        assert!((distance - 125_700.0).abs() < 1_000.0, "{distance}");
+        assert_eq!(distance_meters(&milan, &milan), 0.0);
+    }
+
+    #[tokio::test]
+    async fn get_position_deadband_test() {
+        const LINES: &[&str] = &[
+            r#"{"class":"VERSION","release":"3.25","rev":"3.25","proto_major":3,"proto_minor":15}"#,
+            TPV_3D,
+        ];
+
+        let gnss = Gnss::new(GnssOptions {
+            gpsd_address: fake_gpsd(LINES).await,
+            min_distance_meters: 10.0,
+            ..Default::default()
+        });
+
+        let fix = gnss.get_position().await.unwrap();
+        assert_eq!(fix.unwrap().position.latitude, 45.4642);
+
+        // same position, inside the deadband
+        assert!(gnss.get_position().await.unwrap().is_none());
+    }
+}
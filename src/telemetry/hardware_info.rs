@@ -22,6 +22,10 @@ use crate::error::DeviceManagerError;
 use astarte_device_sdk::types::AstarteType;
 use procfs::{CpuInfo, Meminfo, ProcResult};
 use std::collections::HashMap;
+use std::path::Path;
+
+const DEVICE_TREE_PATH: &str = "/proc/device-tree";
+const SOC_PATH: &str = "/sys/devices/soc0";
 
 /// get structured data for `io.edgehog.devicemanager.HardwareInfo` interface
 pub fn get_hardware_info() -> Result<HashMap<String, AstarteType>, DeviceManagerError> {
@@ -31,6 +35,35 @@ pub fn get_hardware_info() -> Result<HashMap<String, AstarteType>, DeviceManager
     ret.insert("/cpu/architecture".to_owned(), architecture.into());
 
     let cpuinfo = get_cpu_info()?;
+    ret.extend(cpu_info_to_astarte(&cpuinfo));
+
+    let meminfo = get_meminfo()?;
+    ret.insert(
+        "/mem/totalBytes".to_owned(),
+        (meminfo.mem_total as i64).into(),
+    );
+
+    ret.extend(get_platform_info(
+        Path::new(DEVICE_TREE_PATH),
+        Path::new(SOC_PATH),
+    ));
+
+    let uname = nix::sys::utsname::uname();
+    ret.insert(
+        "/kernel/release".to_owned(),
+        uname.release().to_owned().into(),
+    );
+    ret.insert(
+        "/kernel/version".to_owned(),
+        uname.version().to_owned().into(),
+    );
+
+    Ok(ret)
+}
+
+fn cpu_info_to_astarte(cpuinfo: &CpuInfo) -> HashMap<String, AstarteType> {
+    let mut ret: HashMap<String, AstarteType> = HashMap::new();
+
     if let Some(f) = cpuinfo.fields.get("model") {
         ret.insert("/cpu/model".to_owned(), f.clone().into());
     }
@@ -43,13 +76,53 @@ pub fn get_hardware_info() -> Result<HashMap<String, AstarteType>, DeviceManager
         ret.insert("/cpu/vendor".to_owned(), f.clone().into());
     }
 
-    let meminfo = get_meminfo()?;
-    ret.insert(
-        "/mem/totalBytes".to_owned(),
-        (meminfo.mem_total as i64).into(),
-    );
+    ret.insert("/cpu/cores".to_owned(), (cpuinfo.num_cores() as i32).into());
 
-    Ok(ret)
+    ret
+}
+
+/// Reads the board identity from the device tree and the SoC information, usually available only
+/// on ARM and RISC-V boards.
+fn get_platform_info(device_tree: &Path, soc: &Path) -> HashMap<String, AstarteType> {
+    let mut ret: HashMap<String, AstarteType> = HashMap::new();
+
+    if let Some(model) = read_device_tree_strings(&device_tree.join("model")) {
+        if let Some(model) = model.into_iter().next() {
+            ret.insert("/platform/model".to_owned(), model.into());
+        }
+    }
+
+    if let Some(compatible) = read_device_tree_strings(&device_tree.join("compatible")) {
+        ret.insert(
+            "/platform/compatible".to_owned(),
+            AstarteType::StringArray(compatible),
+        );
+    }
+
+    for (file, path) in [("family", "/soc/family"), ("revision", "/soc/revision")] {
+        if let Ok(value) = std::fs::read_to_string(soc.join(file)) {
+            ret.insert(path.to_owned(), value.trim().to_owned().into());
+        }
+    }
+
+    ret
+}
+
+/// Device tree string properties are lists of NUL terminated strings.
+fn read_device_tree_strings(path: &Path) -> Option<Vec<String>> {
+    let data = std::fs::read(path).ok()?;
+
+    let strings: Vec<String> = data
+        .split(|byte| *byte == 0)
+        .filter(|value| !value.is_empty())
+        .map(|value| String::from_utf8_lossy(value).into_owned())
+        .collect();
+
+    if strings.is_empty() {
+        None
+    } else {
+        Some(strings)
+    }
 }
 
 #[cfg(not(test))]
@@ -148,8 +221,152 @@ CmaFree:          194196 kB
 
 #[cfg(test)]
 mod tests {
-    use crate::telemetry::hardware_info::get_hardware_info;
+    use crate::telemetry::hardware_info::{
+        cpu_info_to_astarte, get_hardware_info, get_platform_info,
+    };
     use astarte_device_sdk::types::AstarteType;
+    use procfs::CpuInfo;
+    use tempdir::TempDir;
+
+    const X86_CPUINFO: &str = r#"processor	: 0
+vendor_id	: GenuineIntel
+cpu family	: 6
+model		: 142
+model name	: Intel(R) Core(TM) i7-8650U CPU @ 1.90GHz
+stepping	: 10
+cpu cores	: 2
+
+processor	: 1
+vendor_id	: GenuineIntel
+cpu family	: 6
+model		: 142
+model name	: Intel(R) Core(TM) i7-8650U CPU @ 1.90GHz
+stepping	: 10
+cpu cores	: 2
+"#;
+
+    const ARM_CPUINFO: &str = r#"processor	: 0
+BogoMIPS	: 48.00
+Features	: fp asimd evtstrm aes pmull sha1 sha2 crc32 cpuid
+CPU implementer	: 0x41
+CPU architecture: 8
+CPU variant	: 0x0
+CPU part	: 0xd03
+CPU revision	: 4
+
+processor	: 1
+BogoMIPS	: 48.00
+Features	: fp asimd evtstrm aes pmull sha1 sha2 crc32 cpuid
+CPU implementer	: 0x41
+CPU architecture: 8
+CPU variant	: 0x0
+CPU part	: 0xd03
+CPU revision	: 4
+
+processor	: 2
+BogoMIPS	: 48.00
+Features	: fp asimd evtstrm aes pmull sha1 sha2 crc32 cpuid
+CPU implementer	: 0x41
+CPU architecture: 8
+CPU variant	: 0x0
+CPU part	: 0xd03
+CPU revision	: 4
+
+processor	: 3
+BogoMIPS	: 48.00
+Features	: fp asimd evtstrm aes pmull sha1 sha2 crc32 cpuid
+CPU implementer	: 0x41
+CPU architecture: 8
+CPU variant	: 0x0
+CPU part	: 0xd03
+CPU revision	: 4
+"#;
+
+    fn cpu_info(data: &str) -> CpuInfo {
+        CpuInfo::from_reader(std::io::Cursor::new(data.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn x86_cpu_info_test() {
+        let cpu = cpu_info_to_astarte(&cpu_info(X86_CPUINFO));
+
+        assert_eq!(cpu.get("/cpu/cores"), Some(&AstarteType::Integer(2)));
+        assert_eq!(
+            cpu.get("/cpu/modelName"),
+            Some(&AstarteType::String(
+                "Intel(R) Core(TM) i7-8650U CPU @ 1.90GHz".to_string()
+            ))
+        );
+        assert_eq!(
+            cpu.get("/cpu/vendor"),
+            Some(&AstarteType::String("GenuineIntel".to_string()))
+        );
+    }
+
+    #[test]
+    fn arm_cpu_info_test() {
+        let cpu = cpu_info_to_astarte(&cpu_info(ARM_CPUINFO));
+
+        assert_eq!(cpu.get("/cpu/cores"), Some(&AstarteType::Integer(4)));
+        assert!(!cpu.contains_key("/cpu/modelName"));
+        assert!(!cpu.contains_key("/cpu/vendor"));
+    }
+
+    #[test]
+    fn arm_platform_info_test() {
+        let dir = TempDir::new("edgehog").unwrap();
+        let device_tree = dir.path().join("device-tree");
+        let soc = dir.path().join("soc0");
+        std::fs::create_dir(&device_tree).unwrap();
+        std::fs::create_dir(&soc).unwrap();
+
+        std::fs::write(
+            device_tree.join("model"),
+            b"Raspberry Pi 4 Model B Rev 1.4\0",
+        )
+        .unwrap();
+        std::fs::write(
+            device_tree.join("compatible"),
+            b"raspberrypi,4-model-b\0brcm,bcm2711\0",
+        )
+        .unwrap();
+        std::fs::write(soc.join("family"), "Freescale i.MX\n").unwrap();
+        std::fs::write(soc.join("revision"), "1.1\n").unwrap();
+
+        let platform = get_platform_info(&device_tree, &soc);
+
+        assert_eq!(
+            platform.get("/platform/model"),
+            Some(&AstarteType::String(
+                "Raspberry Pi 4 Model B Rev 1.4".to_string()
+            ))
+        );
+        assert_eq!(
+            platform.get("/platform/compatible"),
+            Some(&AstarteType::StringArray(vec![
+                "raspberrypi,4-model-b".to_string(),
+                "brcm,bcm2711".to_string()
+            ]))
+        );
+        assert_eq!(
+            platform.get("/soc/family"),
+            Some(&AstarteType::String("Freescale i.MX".to_string()))
+        );
+        assert_eq!(
+            platform.get("/soc/revision"),
+            Some(&AstarteType::String("1.1".to_string()))
+        );
+    }
+
+    #[test]
+    fn x86_platform_info_test() {
+        let dir = TempDir::new("edgehog").unwrap();
+
+        // no device tree nor SoC information
+        let platform = get_platform_info(&dir.path().join("device-tree"), &dir.path().join("soc0"));
+
+        assert!(platform.is_empty());
+    }
 
     #[test]
     fn hardware_info_test() {
@@ -183,5 +400,11 @@ mod tests {
                 .to_owned(),
             AstarteType::LongInteger(1043820544)
         );
+        assert_eq!(
+            astarte_hardware_info.get("/cpu/cores").unwrap().to_owned(),
+            AstarteType::Integer(1)
+        );
+        assert!(astarte_hardware_info.contains_key("/kernel/release"));
+        assert!(astarte_hardware_info.contains_key("/kernel/version"));
     }
 }
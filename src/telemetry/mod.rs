@@ -21,9 +21,15 @@
 use crate::error::DeviceManagerError;
 use crate::repository::file_state_repository::FileStateRepository;
 use crate::repository::StateRepository;
+use crate::telemetry::aggregation::Window;
+use crate::telemetry::cron::CronSchedule;
+use crate::telemetry::schedule::{Schedule, Ticker};
+use crate::telemetry::send_policy::{ChangeFilter, Deadband, SendPolicy};
 use astarte_device_sdk::types::AstarteType;
+use astarte_device_sdk::AstarteAggregate;
 use log::{debug, error, warn};
 use serde::{Deserialize, Serialize};
+use std::path::Path;
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::broadcast::{channel, Receiver, Sender};
 use tokio::sync::mpsc::Sender as MpscSender;
@@ -31,26 +37,118 @@ use tokio::sync::RwLock;
 use tokio::task::spawn;
 use tokio::time::interval;
 use tokio::time::Duration;
+use tokio::time::Instant;
 
+pub(crate) mod aggregation;
 pub(crate) mod base_image;
 pub(crate) mod battery_status;
+pub(crate) mod cron;
+pub(crate) mod exec_source;
+pub(crate) mod gnss;
 pub(crate) mod hardware_info;
 pub(crate) mod net_if_properties;
+pub(crate) mod oom_kill;
 pub(crate) mod os_info;
+pub(crate) mod package_inventory;
+pub(crate) mod peripherals;
+pub(crate) mod power_supply;
+pub(crate) mod pressure;
+pub(crate) mod process_watch;
 pub(crate) mod runtime_info;
+pub(crate) mod schedule;
+pub(crate) mod send_policy;
+pub(crate) mod storage_health;
 pub(crate) mod storage_usage;
 pub(crate) mod system_info;
 pub(crate) mod system_status;
+pub(crate) mod systemd;
+pub(crate) mod systemd_units;
+pub(crate) mod time_sync;
+pub(crate) mod timedate;
 pub(crate) mod upower;
 pub(crate) mod wifi_scan;
+pub(crate) mod wpa_supplicant;
 
 const TELEMETRY_PATH: &str = "telemetry.json";
+pub const EFFECTIVE_TELEMETRY_CONFIG_INTERFACE: &str =
+    "io.edgehog.devicemanager.EffectiveTelemetryConfig";
+
+/// Periodic interfaces that can be configured, besides the custom exec sources.
+const TELEMETRY_INTERFACES: &[&str] = &[
+    "io.edgehog.devicemanager.SystemStatus",
+    "io.edgehog.devicemanager.StorageUsage",
+    "io.edgehog.devicemanager.MountPointUsage",
+    "io.edgehog.devicemanager.BatteryStatus",
+    "io.edgehog.devicemanager.StorageHealth",
+    "io.edgehog.devicemanager.PressureStall",
+    "io.edgehog.devicemanager.SystemdUnitStatus",
+    "io.edgehog.devicemanager.ProcessStatus",
+    "io.edgehog.devicemanager.WiFiScanResults",
+    "io.edgehog.devicemanager.TimeSyncStatus",
+    "io.edgehog.devicemanager.GnssPosition",
+];
+
+/// Bounds of the periods of the telemetry interfaces, from the local or the remote configuration.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct PeriodLimits {
+    pub min_period: Option<u64>,
+    pub max_period: Option<u64>,
+}
+
+impl PeriodLimits {
+    /// Clamps a period to the limits, a zero period disables the interface and is kept.
+    fn clamp(&self, period: u64) -> u64 {
+        if period == 0 {
+            return 0;
+        }
+
+        let period = self.max_period.map_or(period, |max| period.min(max));
+
+        self.min_period.map_or(period, |min| period.max(min))
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+    /// Checks that the cron expression doesn't fire more often than the minimum period.
+    fn check_cron(&self, cron: &CronSchedule) -> Result<(), String> {
+        match (cron.min_interval(), self.min_period) {
+            (Some(interval), Some(min)) if interval < min => Err(format!(
+                "{cron} fires every {interval} seconds, below the minimum period of {min} seconds"
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TelemetryInterfaceConfig {
     pub interface_name: String,
     pub enabled: Option<bool>,
     pub period: Option<u64>,
+    /// Cron expression in local time, used instead of `period`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    /// Send a path only when its data changed from the last sent one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_change: Option<bool>,
+    /// Ignore numeric changes smaller than this, implies `on_change`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadband: Option<Deadband>,
+    /// Maximum seconds without sending a path that didn't change.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_silence: Option<u64>,
+    /// Seconds between samples, if shorter than `period` the numeric fields are aggregated over
+    /// each period and published on `io.edgehog.devicemanager.TelemetryAggregate`, alongside the
+    /// last sample on the interface itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_period: Option<u64>,
+    /// Maximum random delay in seconds of the first send, to spread the devices starting together.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial_offset: Option<u64>,
+    /// Maximum random delay in seconds of each send from its nominal time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jitter: Option<u64>,
+    /// Send on the multiples of the period since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub align: Option<bool>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -59,6 +157,92 @@ struct TelemetryTaskConfig {
     default_period: Option<u64>,
     override_enabled: Option<bool>,
     override_period: Option<u64>,
+    default_cron: Option<CronSchedule>,
+    override_cron: Option<CronSchedule>,
+    policy: SendPolicy,
+    sample_period: Option<u64>,
+    schedule: Schedule,
+}
+
+impl TelemetryTaskConfig {
+    /// Returns whether the interface is enabled and its period, merging the overrides with the
+    /// defaults.
+    fn effective(&self, limits: &PeriodLimits) -> (bool, u64) {
+        let enabled = self
+            .override_enabled
+            .unwrap_or_else(|| self.default_enabled.unwrap_or(false));
+
+        let period = self
+            .override_period
+            .unwrap_or_else(|| self.default_period.unwrap_or(0));
+
+        (enabled, limits.clamp(period))
+    }
+
+    /// Returns the cron expression in use, it takes precedence over the period of the same
+    /// configuration, local or remote.
+    fn effective_cron(&self) -> Option<&CronSchedule> {
+        match (&self.override_cron, self.override_period) {
+            (Some(cron), _) => Some(cron),
+            (None, Some(_)) => None,
+            (None, None) => self.default_cron.as_ref(),
+        }
+    }
+}
+
+/// Parses the cron expression of a configuration, ignoring it if invalid or out of the limits.
+fn parse_cron(
+    interface_name: &str,
+    cron: Option<&str>,
+    limits: &PeriodLimits,
+) -> Option<CronSchedule> {
+    let res = cron?
+        .parse::<CronSchedule>()
+        .map_err(|err| err.to_string())
+        .and_then(|cron| limits.check_cron(&cron).map(|()| cron));
+
+    match res {
+        Ok(cron) => Some(cron),
+        Err(err) => {
+            warn!("ignoring the {interface_name} schedule: {err}");
+
+            None
+        }
+    }
+}
+
+/// Effective scheduling of a running telemetry task.
+#[derive(Debug, Clone)]
+struct TaskOptions {
+    period: u64,
+    sample_period: Option<u64>,
+    policy: SendPolicy,
+    schedule: Schedule,
+    cron: Option<CronSchedule>,
+}
+
+impl TaskOptions {
+    /// Returns the sampling period if the task has to aggregate its samples.
+    fn aggregation_period(&self) -> Option<u64> {
+        if self.cron.is_some() {
+            return None;
+        }
+
+        self.sample_period
+            .filter(|sample_period| *sample_period > 0 && *sample_period < self.period)
+    }
+}
+
+/// Telemetry sources that need their own configuration or keep state between samples.
+#[derive(Debug, Default)]
+pub struct TelemetrySources {
+    pub battery: battery_status::Battery,
+    pub gnss: gnss::Gnss,
+    pub exec: exec_source::ExecSources,
+    pub storage: storage_usage::StorageUsageOptions,
+    pub block_devices: storage_health::BlockDevices,
+    pub systemd: systemd_units::SystemdUnits,
+    pub processes: process_watch::ProcessWatch,
 }
 
 #[derive(Debug)]
@@ -67,12 +251,108 @@ pub struct Telemetry {
     kill_switches: HashMap<String, Sender<()>>,
     communication_channel: MpscSender<TelemetryMessage>,
     store_directory: String,
+    sources: Arc<TelemetrySources>,
+    limits: PeriodLimits,
+}
+
+/// Takes the samples of the periodic interfaces, the sources measuring a usage between two samples
+/// keep a baseline for each sampler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Sampler {
+    /// The telemetry tasks publishing the interfaces.
+    Telemetry,
+    /// The evaluation of the alert rules.
+    Alerts,
 }
 
 pub enum TelemetryPayload {
     SystemStatus(crate::telemetry::system_status::SystemStatus),
     StorageUsage(crate::telemetry::storage_usage::DiskUsage),
+    MountPointUsage(crate::telemetry::storage_usage::MountPointUsage),
     BatteryStatus(crate::telemetry::battery_status::BatteryStatus),
+    GnssPosition(
+        crate::telemetry::gnss::GnssPosition,
+        chrono::DateTime<chrono::Utc>,
+    ),
+    StorageHealth(crate::telemetry::storage_health::StorageHealth),
+    PressureStall(crate::telemetry::pressure::PressureStall),
+    SystemdUnitStatus(crate::telemetry::systemd_units::SystemdUnitStatus),
+    SystemdUnitFailed(crate::telemetry::systemd_units::SystemdUnitFailedEvent),
+    OomKill(
+        crate::telemetry::oom_kill::OomKillEvent,
+        chrono::DateTime<chrono::Utc>,
+    ),
+    ProcessStatus(crate::telemetry::process_watch::ProcessStatus),
+    ProcessDisappeared(crate::telemetry::process_watch::ProcessDisappearedEvent),
+    WifiScanResult(crate::telemetry::wifi_scan::WifiScanResult),
+    TimeSyncStatus(crate::telemetry::time_sync::TimeSyncStatus),
+    Peripheral(crate::telemetry::peripherals::PeripheralEvent),
+    /// Properties to set or unset on the interface, by path.
+    Properties(&'static str, HashMap<String, AstarteType>),
+    TelemetryAggregate(crate::telemetry::aggregation::AggregatedValue),
+    /// Output of a custom telemetry command, with the name of its interface.
+    Exec(String, crate::telemetry::exec_source::ExecValue),
+}
+
+impl TelemetryPayload {
+    /// Returns the name of the interface the payload is published on.
+    pub(crate) fn interface_name(&self) -> &str {
+        match self {
+            TelemetryPayload::SystemStatus(_) => "io.edgehog.devicemanager.SystemStatus",
+            TelemetryPayload::StorageUsage(_) => "io.edgehog.devicemanager.StorageUsage",
+            TelemetryPayload::MountPointUsage(_) => "io.edgehog.devicemanager.MountPointUsage",
+            TelemetryPayload::BatteryStatus(_) => "io.edgehog.devicemanager.BatteryStatus",
+            TelemetryPayload::GnssPosition(_, _) => "io.edgehog.devicemanager.GnssPosition",
+            TelemetryPayload::StorageHealth(_) => "io.edgehog.devicemanager.StorageHealth",
+            TelemetryPayload::PressureStall(_) => "io.edgehog.devicemanager.PressureStall",
+            TelemetryPayload::SystemdUnitStatus(_) => "io.edgehog.devicemanager.SystemdUnitStatus",
+            TelemetryPayload::SystemdUnitFailed(_) => {
+                "io.edgehog.devicemanager.SystemdUnitFailedEvent"
+            }
+            TelemetryPayload::OomKill(_, _) => "io.edgehog.devicemanager.OomKillEvent",
+            TelemetryPayload::ProcessStatus(_) => "io.edgehog.devicemanager.ProcessStatus",
+            TelemetryPayload::ProcessDisappeared(_) => {
+                "io.edgehog.devicemanager.ProcessDisappearedEvent"
+            }
+            TelemetryPayload::WifiScanResult(_) => "io.edgehog.devicemanager.WiFiScanResults",
+            TelemetryPayload::TimeSyncStatus(_) => "io.edgehog.devicemanager.TimeSyncStatus",
+            TelemetryPayload::Peripheral(_) => "io.edgehog.devicemanager.PeripheralEvent",
+            TelemetryPayload::Properties(interface_name, _) => interface_name,
+            TelemetryPayload::TelemetryAggregate(_) => {
+                "io.edgehog.devicemanager.TelemetryAggregate"
+            }
+            TelemetryPayload::Exec(interface_name, _) => interface_name,
+        }
+    }
+
+    /// Returns the fields of the payload, to compare it with the previously sent one.
+    pub(crate) fn values(
+        &self,
+    ) -> Result<HashMap<String, AstarteType>, astarte_device_sdk::AstarteError> {
+        match self {
+            TelemetryPayload::SystemStatus(data) => data.clone().astarte_aggregate(),
+            TelemetryPayload::StorageUsage(data) => data.clone().astarte_aggregate(),
+            TelemetryPayload::MountPointUsage(data) => data.clone().astarte_aggregate(),
+            TelemetryPayload::BatteryStatus(data) => data.clone().astarte_aggregate(),
+            TelemetryPayload::GnssPosition(data, _) => data.clone().astarte_aggregate(),
+            TelemetryPayload::StorageHealth(data) => data.clone().astarte_aggregate(),
+            TelemetryPayload::PressureStall(data) => data.clone().astarte_aggregate(),
+            TelemetryPayload::SystemdUnitStatus(data) => data.clone().astarte_aggregate(),
+            TelemetryPayload::SystemdUnitFailed(data) => data.clone().astarte_aggregate(),
+            TelemetryPayload::OomKill(data, _) => data.clone().astarte_aggregate(),
+            TelemetryPayload::ProcessStatus(data) => data.clone().astarte_aggregate(),
+            TelemetryPayload::ProcessDisappeared(data) => data.clone().astarte_aggregate(),
+            TelemetryPayload::WifiScanResult(data) => data.clone().astarte_aggregate(),
+            TelemetryPayload::TimeSyncStatus(data) => data.clone().astarte_aggregate(),
+            TelemetryPayload::Peripheral(data) => data.clone().astarte_aggregate(),
+            TelemetryPayload::Properties(_, data) => Ok(data.clone()),
+            TelemetryPayload::TelemetryAggregate(data) => data.clone().astarte_aggregate(),
+            TelemetryPayload::Exec(_, exec_source::ExecValue::Object(data)) => Ok(data.clone()),
+            TelemetryPayload::Exec(_, exec_source::ExecValue::Individual(data)) => {
+                Ok(HashMap::from([("value".to_string(), data.clone())]))
+            }
+        }
+    }
 }
 
 pub struct TelemetryMessage {
@@ -80,12 +360,30 @@ pub struct TelemetryMessage {
     pub payload: TelemetryPayload,
 }
 
+impl TelemetryMessage {
+    /// Identifies the series of the message when filtering, aggregating and evaluating it.
+    ///
+    /// It's the path, except for the access points: they are all published on `ap` and are told
+    /// apart by their BSSID.
+    pub(crate) fn key(&self) -> String {
+        match &self.payload {
+            TelemetryPayload::WifiScanResult(access_point) => {
+                format!("{}/{}", self.path, access_point.bssid())
+            }
+            _ => self.path.clone(),
+        }
+    }
+}
+
 impl Telemetry {
     pub async fn from_default_config(
         cfg: Option<Vec<TelemetryInterfaceConfig>>,
+        sources: TelemetrySources,
+        limits: PeriodLimits,
         communication_channel: MpscSender<TelemetryMessage>,
         store_directory: String,
     ) -> Self {
+        let sources = Arc::new(sources);
         let cfg = match cfg {
             None => {
                 return Telemetry {
@@ -93,12 +391,23 @@ impl Telemetry {
                     kill_switches: Default::default(),
                     communication_channel,
                     store_directory,
+                    sources,
+                    limits,
                 }
             }
             Some(conf) => conf,
         };
         let mut telemetry_task_configs = HashMap::new();
         for c in cfg {
+            if !is_supported(&sources, &c.interface_name) {
+                warn!(
+                    "ignoring the configuration of unknown interface {}",
+                    c.interface_name
+                );
+
+                continue;
+            }
+
             telemetry_task_configs.insert(
                 c.interface_name.clone(),
                 TelemetryTaskConfig {
@@ -106,6 +415,11 @@ impl Telemetry {
                     default_period: c.period,
                     override_enabled: None,
                     override_period: None,
+                    default_cron: parse_cron(&c.interface_name, c.cron.as_deref(), &limits),
+                    override_cron: None,
+                    policy: SendPolicy::new(c.on_change, c.deadband, c.max_silence),
+                    sample_period: c.sample_period,
+                    schedule: Schedule::new(c.initial_offset, c.jitter, c.align),
                 },
             );
         }
@@ -116,10 +430,21 @@ impl Telemetry {
         if telemetry_repo.exists().await {
             let saved_config: Vec<TelemetryInterfaceConfig> = telemetry_repo.read().await.unwrap();
             for c in saved_config {
+                if !is_supported(&sources, &c.interface_name) {
+                    warn!(
+                        "dropping the saved configuration of unknown interface {}",
+                        c.interface_name
+                    );
+
+                    continue;
+                }
+
                 if let Some(rwlock_default_task) = telemetry_task_configs.get_mut(&c.interface_name)
                 {
                     rwlock_default_task.override_enabled = c.enabled;
                     rwlock_default_task.override_period = c.period;
+                    rwlock_default_task.override_cron =
+                        parse_cron(&c.interface_name, c.cron.as_deref(), &limits);
                 } else {
                     telemetry_task_configs.insert(
                         c.interface_name.clone(),
@@ -128,6 +453,15 @@ impl Telemetry {
                             default_period: None,
                             override_enabled: c.enabled,
                             override_period: c.period,
+                            default_cron: None,
+                            override_cron: parse_cron(
+                                &c.interface_name,
+                                c.cron.as_deref(),
+                                &limits,
+                            ),
+                            policy: SendPolicy::default(),
+                            sample_period: None,
+                            schedule: Schedule::default(),
                         },
                     );
                 };
@@ -139,13 +473,56 @@ impl Telemetry {
             kill_switches: HashMap::new(),
             communication_channel,
             store_directory,
+            sources,
+            limits,
         }
     }
 
+    pub fn sources(&self) -> Arc<TelemetrySources> {
+        self.sources.clone()
+    }
+
+    /// Starts watching the event interfaces, which are published independently of the periodic
+    /// interfaces being enabled.
+    pub fn spawn_event_watchers(&self) {
+        let communication_channel = self.communication_channel.clone();
+        tokio::spawn(async move {
+            if let Err(err) = oom_kill::watch(&communication_channel).await {
+                error!("couldn't watch the OOM kills: {err}");
+            }
+        });
+
+        let communication_channel = self.communication_channel.clone();
+        let sources = self.sources.clone();
+        tokio::spawn(async move {
+            if let Err(err) = sources.systemd.watch(&communication_channel).await {
+                error!("couldn't watch the failed systemd units: {err}");
+            }
+        });
+
+        let communication_channel = self.communication_channel.clone();
+        let sources = self.sources.clone();
+        tokio::spawn(async move {
+            if let Err(err) = sources.processes.watch(&communication_channel).await {
+                error!("couldn't watch the processes: {err}");
+            }
+        });
+    }
+
     pub async fn run_telemetry(&mut self) {
-        for interface_name in self.telemetry_task_configs.clone().read().await.keys() {
+        let interface_names: Vec<String> = self
+            .telemetry_task_configs
+            .read()
+            .await
+            .keys()
+            .cloned()
+            .collect();
+
+        for interface_name in &interface_names {
             self.schedule_task(interface_name.clone()).await;
         }
+
+        self.publish_effective_config(&interface_names).await;
     }
 
     async fn schedule_task(&mut self, interface_name: String) {
@@ -153,27 +530,30 @@ impl Telemetry {
         let telemetry_task_configs = telemetry_task_configs_clone.read().await;
         let telemetry_task_config = telemetry_task_configs.get(&interface_name.clone()).unwrap();
 
-        let period = telemetry_task_config
-            .override_period
-            .unwrap_or_else(|| telemetry_task_config.default_period.unwrap_or(0));
-
-        let enabled = telemetry_task_config
-            .override_enabled
-            .unwrap_or_else(|| telemetry_task_config.default_enabled.unwrap_or(false));
+        let (enabled, period) = telemetry_task_config.effective(&self.limits);
 
         if let Some(kill_switch) = self.kill_switches.get(&interface_name.clone()) {
             let _ = kill_switch.send(());
         }
 
         let comm = self.communication_channel.clone();
+        let sources = self.sources.clone();
+        let options = TaskOptions {
+            period,
+            sample_period: telemetry_task_config.sample_period,
+            policy: telemetry_task_config.policy.clone(),
+            schedule: telemetry_task_config.schedule,
+            cron: telemetry_task_config.effective_cron().cloned(),
+        };
 
-        if period > 0 && enabled {
+        if enabled && (period > 0 || options.cron.is_some()) {
             let (tx, rx) = channel(1);
             spawn(Telemetry::start_task(
                 rx,
                 interface_name.clone(),
-                period,
+                options,
                 comm,
+                sources,
             ));
 
             self.kill_switches.insert(interface_name, tx);
@@ -183,31 +563,104 @@ impl Telemetry {
     async fn start_task(
         mut kill_switch: Receiver<()>,
         interface_name: String,
-        period: u64,
+        options: TaskOptions,
         communication_channel: MpscSender<TelemetryMessage>,
+        sources: Arc<TelemetrySources>,
     ) {
         tokio::select! {
-            _output = Telemetry::data_send_loop(interface_name, period, communication_channel) => {debug!("data_send_loop ended")},
+            _output = Telemetry::data_send_loop(interface_name.clone(), options, communication_channel.clone(), sources.clone()) => {debug!("data_send_loop ended")},
+            _output = watch_events(&communication_channel, &interface_name, &sources) => {debug!("watch_events ended")},
             _ = kill_switch.recv() => {debug!("Kill switch triggered")},
         }
     }
 
     async fn data_send_loop(
         interface_name: String,
-        period: u64,
+        options: TaskOptions,
         communication_channel: MpscSender<TelemetryMessage>,
+        sources: Arc<TelemetrySources>,
     ) {
-        let mut interval = interval(Duration::from_secs(period));
+        if let Some(sample_period) = options.aggregation_period() {
+            return Telemetry::aggregate_send_loop(
+                interface_name,
+                sample_period,
+                options.period,
+                options.schedule,
+                communication_channel,
+                sources,
+            )
+            .await;
+        }
+
+        let mut filter = ChangeFilter::new(options.policy);
+        let mut ticker = match options.cron {
+            Some(cron) => Ticker::with_cron(cron, options.schedule),
+            None => Ticker::new(Duration::from_secs(options.period), options.schedule),
+        };
         loop {
-            interval.tick().await;
+            ticker.tick().await;
 
             // TODO: the error should be bubbled up
-            if let Err(err) = send_data(&communication_channel, &interface_name).await {
+            if let Err(err) = send_data(
+                &communication_channel,
+                &interface_name,
+                &sources,
+                &mut filter,
+            )
+            .await
+            {
                 error!("coulnd't send telemetry data: {:#?}", err)
             }
         }
     }
 
+    /// Samples the interface every `sample_period` and publishes the aggregated values every
+    /// `period` following the schedule, together with the last sample on the interface itself.
+    /// The send policy is not applied.
+    async fn aggregate_send_loop(
+        interface_name: String,
+        sample_period: u64,
+        period: u64,
+        schedule: Schedule,
+        communication_channel: MpscSender<TelemetryMessage>,
+        sources: Arc<TelemetrySources>,
+    ) {
+        let period = Duration::from_secs(period);
+        let mut publish_ticker = Ticker::new(period, schedule);
+        // the first tick is at the start, after the initial offset or on the first boundary, the
+        // windows are published from the following one
+        publish_ticker.tick().await;
+        let mut sample_interval = interval(Duration::from_secs(sample_period));
+        let mut window = Window::new(&interface_name, chrono::Utc::now());
+        let mut last_samples = Vec::new();
+
+        loop {
+            tokio::select! {
+                _ = sample_interval.tick() => {
+                    match sample_data(&interface_name, &sources, &mut window).await {
+                        Ok(samples) => last_samples = samples,
+                        Err(err) => error!("couldn't sample telemetry data: {:#?}", err),
+                    }
+                }
+                _ = publish_ticker.tick() => {
+                    for (path, value) in window.take(chrono::Utc::now()) {
+                        let msg = TelemetryMessage {
+                            path,
+                            payload: TelemetryPayload::TelemetryAggregate(value),
+                        };
+
+                        let _ = communication_channel.send(msg).await;
+                    }
+
+                    // the source interface keeps receiving its data, once per period
+                    for msg in std::mem::take(&mut last_samples) {
+                        let _ = communication_channel.send(msg).await;
+                    }
+                }
+            }
+        }
+    }
+
     async fn set_enabled(&self, interface_name: &str, enabled: bool) {
         debug!("set {interface_name} to enabled {enabled}");
 
@@ -236,6 +689,12 @@ impl Telemetry {
 
     async fn set_period(&self, interface_name: &str, period: u64) {
         debug!("set {interface_name} to period {period}");
+
+        let clamped = self.limits.clamp(period);
+        if clamped != period {
+            warn!("{interface_name} period {period} out of the limits, using {clamped}");
+        }
+
         self.telemetry_task_configs
             .clone()
             .write()
@@ -245,6 +704,30 @@ impl Telemetry {
             .override_period = Some(period);
     }
 
+    async fn set_cron(&self, interface_name: &str, cron: CronSchedule) {
+        debug!("set {interface_name} to cron {cron}");
+
+        self.telemetry_task_configs
+            .write()
+            .await
+            .entry(interface_name.to_string())
+            .or_insert_with(Default::default)
+            .override_cron = Some(cron);
+    }
+
+    async fn unset_cron(&self, interface_name: &str) {
+        debug!("unset {interface_name} cron");
+
+        if let Some(telemetry_task_config) = self
+            .telemetry_task_configs
+            .write()
+            .await
+            .get_mut(interface_name)
+        {
+            telemetry_task_config.override_cron = None;
+        }
+    }
+
     async fn unset_period(&self, interface_name: &str) {
         debug!("unset {interface_name} period");
 
@@ -265,6 +748,12 @@ impl Telemetry {
         endpoint: &str,
         data: &AstarteType,
     ) {
+        if !is_supported(&self.sources, interface_name) {
+            warn!("ignoring the configuration of unknown interface {interface_name}");
+
+            return;
+        }
+
         match (endpoint, data) {
             ("enable", AstarteType::Boolean(enabled)) => {
                 self.set_enabled(interface_name, *enabled).await;
@@ -286,6 +775,16 @@ impl Telemetry {
                 self.unset_period(interface_name).await;
             }
 
+            ("cron", AstarteType::String(cron)) => {
+                if let Some(cron) = parse_cron(interface_name, Some(cron), &self.limits) {
+                    self.set_cron(interface_name, cron).await;
+                }
+            }
+
+            ("cron", AstarteType::Unset) => {
+                self.unset_cron(interface_name).await;
+            }
+
             _ => {
                 warn!("Received malformed data from io.edgehog.devicemanager.config.Telemetry: {endpoint} {data:?}");
             }
@@ -293,6 +792,49 @@ impl Telemetry {
 
         self.schedule_task(interface_name.to_string()).await;
         self.save_telemetry_config().await;
+        self.publish_effective_config(&[interface_name.to_string()])
+            .await;
+    }
+
+    /// Publishes the enabled state, period and cron expression in use for the interfaces.
+    async fn publish_effective_config(&self, interface_names: &[String]) {
+        let telemetry_task_configs = self.telemetry_task_configs.read().await;
+
+        let mut properties = HashMap::new();
+        for interface_name in interface_names {
+            let Some(telemetry_task_config) = telemetry_task_configs.get(interface_name) else {
+                continue;
+            };
+
+            let (enabled, period) = telemetry_task_config.effective(&self.limits);
+            properties.insert(
+                format!("/{interface_name}/enable"),
+                AstarteType::Boolean(enabled),
+            );
+            properties.insert(
+                format!("/{interface_name}/periodSeconds"),
+                AstarteType::LongInteger(i64::try_from(period).unwrap_or(i64::MAX)),
+            );
+            properties.insert(
+                format!("/{interface_name}/cron"),
+                telemetry_task_config
+                    .effective_cron()
+                    .map_or(AstarteType::Unset, |cron| {
+                        AstarteType::String(cron.to_string())
+                    }),
+            );
+        }
+
+        if properties.is_empty() {
+            return;
+        }
+
+        let msg = TelemetryMessage {
+            path: String::new(),
+            payload: TelemetryPayload::Properties(EFFECTIVE_TELEMETRY_CONFIG_INTERFACE, properties),
+        };
+
+        let _ = self.communication_channel.send(msg).await;
     }
 
     async fn save_telemetry_config(&self) {
@@ -304,6 +846,11 @@ impl Telemetry {
                 interface_name: interface_name.to_string(),
                 enabled: telemetry_task_config.override_enabled,
                 period: telemetry_task_config.override_period,
+                cron: telemetry_task_config
+                    .override_cron
+                    .as_ref()
+                    .map(CronSchedule::to_string),
+                ..Default::default()
             };
 
             telemetry_config.push(interface_config);
@@ -315,42 +862,164 @@ impl Telemetry {
     }
 }
 
+/// Returns whether the interface is published by one of the periodic telemetry sources.
+pub(crate) fn is_supported(sources: &TelemetrySources, interface_name: &str) -> bool {
+    TELEMETRY_INTERFACES.contains(&interface_name) || sources.exec.contains(interface_name)
+}
+
 async fn send_data(
     communication_channel: &MpscSender<TelemetryMessage>,
     interface_name: &str,
+    sources: &TelemetrySources,
+    filter: &mut ChangeFilter,
 ) -> Result<(), DeviceManagerError> {
     debug!("sending {interface_name}");
 
+    let now = Instant::now();
+    for msg in get_data(interface_name, sources, Sampler::Telemetry).await? {
+        let send = match msg.payload.values() {
+            Ok(values) => filter.should_send(&msg.key(), values, now),
+            Err(err) => {
+                warn!("couldn't compare {interface_name} data, sending it: {err}");
+
+                true
+            }
+        };
+
+        if send {
+            let _ = communication_channel.send(msg).await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Adds a sample of the interface to the window, returning it.
+async fn sample_data(
+    interface_name: &str,
+    sources: &TelemetrySources,
+    window: &mut Window,
+) -> Result<Vec<TelemetryMessage>, DeviceManagerError> {
+    debug!("sampling {interface_name}");
+
+    let messages = get_data(interface_name, sources, Sampler::Telemetry).await?;
+    for msg in &messages {
+        match msg.payload.values() {
+            Ok(values) => window.add(&msg.key(), &values),
+            Err(err) => warn!("couldn't aggregate {interface_name} data: {err}"),
+        }
+    }
+
+    Ok(messages)
+}
+
+/// Collects the data of a periodic telemetry interface.
+pub(crate) async fn get_data(
+    interface_name: &str,
+    sources: &TelemetrySources,
+    sampler: Sampler,
+) -> Result<Vec<TelemetryMessage>, DeviceManagerError> {
+    let mut messages = Vec::new();
+
     match interface_name {
         "io.edgehog.devicemanager.SystemStatus" => {
             let sysstatus = system_status::get_system_status()?;
-            let _ = communication_channel
-                .send(TelemetryMessage {
-                    path: "".to_string(),
-                    payload: TelemetryPayload::SystemStatus(sysstatus),
-                })
-                .await;
+            messages.push(TelemetryMessage {
+                path: "".to_string(),
+                payload: TelemetryPayload::SystemStatus(sysstatus),
+            });
         }
         "io.edgehog.devicemanager.StorageUsage" => {
-            let storage_usage = storage_usage::get_storage_usage()?;
+            let storage_usage = storage_usage::get_storage_usage(&sources.storage)?;
             for (path, payload) in storage_usage {
-                let _ = communication_channel
-                    .send(TelemetryMessage {
-                        path,
-                        payload: TelemetryPayload::StorageUsage(payload),
-                    })
-                    .await;
+                messages.push(TelemetryMessage {
+                    path,
+                    payload: TelemetryPayload::StorageUsage(payload),
+                });
+            }
+        }
+        "io.edgehog.devicemanager.MountPointUsage" => {
+            let mount_point_usage = storage_usage::get_mount_point_usage(&sources.storage)?;
+            for (path, payload) in mount_point_usage {
+                messages.push(TelemetryMessage {
+                    path,
+                    payload: TelemetryPayload::MountPointUsage(payload),
+                });
             }
         }
         "io.edgehog.devicemanager.BatteryStatus" => {
-            let battery_status = battery_status::get_battery_status().await?;
+            let battery_status = sources.battery.get_battery_status().await?;
             for (path, payload) in battery_status {
-                let _ = communication_channel
-                    .send(TelemetryMessage {
-                        path,
-                        payload: TelemetryPayload::BatteryStatus(payload),
-                    })
-                    .await;
+                messages.push(TelemetryMessage {
+                    path,
+                    payload: TelemetryPayload::BatteryStatus(payload),
+                });
+            }
+        }
+        "io.edgehog.devicemanager.StorageHealth" => {
+            let storage_health = sources.block_devices.get_storage_health().await?;
+            for (path, payload) in storage_health {
+                messages.push(TelemetryMessage {
+                    path,
+                    payload: TelemetryPayload::StorageHealth(payload),
+                });
+            }
+        }
+        "io.edgehog.devicemanager.PressureStall" => {
+            let pressure = pressure::get_pressure_stall(Path::new(pressure::PRESSURE_PATH))?;
+            for (path, payload) in pressure {
+                messages.push(TelemetryMessage {
+                    path,
+                    payload: TelemetryPayload::PressureStall(payload),
+                });
+            }
+        }
+        "io.edgehog.devicemanager.SystemdUnitStatus" => {
+            let unit_status = sources.systemd.get_unit_status().await?;
+            for (path, payload) in unit_status {
+                messages.push(TelemetryMessage {
+                    path,
+                    payload: TelemetryPayload::SystemdUnitStatus(payload),
+                });
+            }
+        }
+        "io.edgehog.devicemanager.ProcessStatus" => {
+            let process_status = sources.processes.get_process_status(sampler).await?;
+            for (path, payload) in process_status {
+                messages.push(TelemetryMessage {
+                    path,
+                    payload: TelemetryPayload::ProcessStatus(payload),
+                });
+            }
+        }
+        "io.edgehog.devicemanager.WiFiScanResults" => {
+            for access_point in wifi_scan::get_wifi_scan_results().await? {
+                messages.push(TelemetryMessage {
+                    path: "ap".to_string(),
+                    payload: TelemetryPayload::WifiScanResult(access_point),
+                });
+            }
+        }
+        "io.edgehog.devicemanager.TimeSyncStatus" => {
+            messages.push(TelemetryMessage {
+                path: "".to_string(),
+                payload: TelemetryPayload::TimeSyncStatus(time_sync::get_time_sync_status().await?),
+            });
+        }
+        "io.edgehog.devicemanager.GnssPosition" => {
+            if let Some(fix) = sources.gnss.get_position().await? {
+                messages.push(TelemetryMessage {
+                    path: fix.device,
+                    payload: TelemetryPayload::GnssPosition(fix.position, fix.timestamp),
+                });
+            }
+        }
+        interface if sources.exec.contains(interface) => {
+            for (path, value) in sources.exec.get_data(interface).await? {
+                messages.push(TelemetryMessage {
+                    path,
+                    payload: TelemetryPayload::Exec(interface.to_string(), value),
+                });
             }
         }
         interface => {
@@ -358,14 +1027,43 @@ async fn send_data(
         }
     }
 
-    Ok(())
+    Ok(messages)
+}
+
+/// Publishes the data of the interfaces that can notify their changes as soon as they happen,
+/// alongside the periodic send of the same interface.
+///
+/// It never returns, so it doesn't stop the periodic task even if watching is not supported.
+async fn watch_events(
+    communication_channel: &MpscSender<TelemetryMessage>,
+    interface_name: &str,
+    sources: &TelemetrySources,
+) {
+    let res = match interface_name {
+        "io.edgehog.devicemanager.BatteryStatus" => {
+            sources.battery.watch(communication_channel).await
+        }
+        _ => Ok(()),
+    };
+
+    if let Err(err) = res {
+        error!("couldn't watch {interface_name} events: {err}");
+    }
+
+    std::future::pending::<()>().await
 }
 
 #[cfg(test)]
 mod tests {
     use crate::repository::file_state_repository::FileStateRepository;
     use crate::repository::StateRepository;
-    use crate::telemetry::{send_data, Telemetry, TelemetryInterfaceConfig};
+    use crate::telemetry::aggregation::Window;
+    use crate::telemetry::schedule::Schedule;
+    use crate::telemetry::send_policy::{Deadband, SendPolicy};
+    use crate::telemetry::{
+        parse_cron, sample_data, send_data, PeriodLimits, Telemetry, TelemetryInterfaceConfig,
+        TelemetryPayload, EFFECTIVE_TELEMETRY_CONFIG_INTERFACE,
+    };
 
     use astarte_device_sdk::types::AstarteType;
     use tempdir::TempDir;
@@ -388,12 +1086,20 @@ mod tests {
             interface_name: interface_name.to_string(),
             enabled: Some(true),
             period: Some(10),
+            ..Default::default()
         });
 
         let (_dir, t_dir) = temp_dir();
 
         let (tx, _) = tokio::sync::mpsc::channel(32);
-        let tel = Telemetry::from_default_config(Some(config), tx, t_dir).await;
+        let tel = Telemetry::from_default_config(
+            Some(config),
+            Default::default(),
+            Default::default(),
+            tx,
+            t_dir,
+        )
+        .await;
         let telemetry_config = tel.telemetry_task_configs.clone();
         let interface_configs = telemetry_config.read().await;
         let system_status_config = interface_configs.get(interface_name).unwrap();
@@ -410,12 +1116,20 @@ mod tests {
             interface_name: interface_name.to_string(),
             enabled: Some(true),
             period: Some(10),
+            ..Default::default()
         });
 
         let (_dir, t_dir) = temp_dir();
 
         let (tx, _) = tokio::sync::mpsc::channel(32);
-        let mut tel = Telemetry::from_default_config(Some(config), tx, t_dir.clone()).await;
+        let mut tel = Telemetry::from_default_config(
+            Some(config),
+            Default::default(),
+            Default::default(),
+            tx,
+            t_dir.clone(),
+        )
+        .await;
 
         tel.telemetry_config_event(interface_name, "enable", &AstarteType::Boolean(false))
             .await;
@@ -457,12 +1171,20 @@ mod tests {
             interface_name: interface_name.to_string(),
             enabled: Some(true),
             period: Some(10),
+            ..Default::default()
         });
 
         let (_dir, t_dir) = temp_dir();
 
         let (tx, _) = tokio::sync::mpsc::channel(32);
-        let mut tel = Telemetry::from_default_config(Some(config), tx, t_dir.clone()).await;
+        let mut tel = Telemetry::from_default_config(
+            Some(config),
+            Default::default(),
+            Default::default(),
+            tx,
+            t_dir.clone(),
+        )
+        .await;
 
         tel.telemetry_config_event(interface_name, "enable", &AstarteType::Unset)
             .await;
@@ -501,12 +1223,20 @@ mod tests {
             interface_name: interface_name.to_string(),
             enabled: Some(true),
             period: Some(10),
+            ..Default::default()
         });
 
         let (_dir, t_dir) = temp_dir();
 
         let (tx, mut rx) = tokio::sync::mpsc::channel(32);
-        let mut tel = Telemetry::from_default_config(Some(config), tx, t_dir).await;
+        let mut tel = Telemetry::from_default_config(
+            Some(config),
+            Default::default(),
+            Default::default(),
+            tx,
+            t_dir,
+        )
+        .await;
         tel.telemetry_config_event(interface_name, "enable", &AstarteType::Boolean(true))
             .await;
         tel.telemetry_config_event(
@@ -524,10 +1254,114 @@ mod tests {
         let (_dir, t_dir) = temp_dir();
 
         let (tx, _) = tokio::sync::mpsc::channel(32);
-        let tel = Telemetry::from_default_config(None, tx, t_dir).await;
+        let tel =
+            Telemetry::from_default_config(None, Default::default(), Default::default(), tx, t_dir)
+                .await;
         assert!(tel.telemetry_task_configs.clone().read().await.is_empty());
     }
 
+    #[tokio::test]
+    async fn unknown_interface_test() {
+        let config = vec![TelemetryInterfaceConfig {
+            interface_name: "io.edgehog.devicemanager.SystemStatuss".to_string(),
+            enabled: Some(true),
+            period: Some(10),
+            ..Default::default()
+        }];
+
+        let (_dir, t_dir) = temp_dir();
+
+        let (tx, _) = tokio::sync::mpsc::channel(32);
+        let mut tel = Telemetry::from_default_config(
+            Some(config),
+            Default::default(),
+            Default::default(),
+            tx,
+            t_dir,
+        )
+        .await;
+        assert!(tel.telemetry_task_configs.read().await.is_empty());
+
+        tel.telemetry_config_event(
+            "io.edgehog.devicemanager.Typo",
+            "enable",
+            &AstarteType::Boolean(true),
+        )
+        .await;
+        assert!(tel.telemetry_task_configs.read().await.is_empty());
+        assert!(tel.kill_switches.is_empty());
+    }
+
+    #[test]
+    fn period_limits_test() {
+        let limits = PeriodLimits {
+            min_period: Some(10),
+            max_period: Some(3600),
+        };
+
+        assert_eq!(limits.clamp(0), 0);
+        assert_eq!(limits.clamp(1), 10);
+        assert_eq!(limits.clamp(60), 60);
+        assert_eq!(limits.clamp(86400), 3600);
+        assert_eq!(PeriodLimits::default().clamp(1), 1);
+    }
+
+    #[test]
+    fn cron_limits_test() {
+        let interface_name = "io.edgehog.devicemanager.SystemStatus";
+        let limits = PeriodLimits {
+            min_period: Some(600),
+            max_period: None,
+        };
+
+        assert!(parse_cron(interface_name, Some("* * * * *"), &limits).is_none());
+        assert!(parse_cron(interface_name, Some("*/5 * * * *"), &limits).is_none());
+        assert!(parse_cron(interface_name, Some("*/10 * * * *"), &limits).is_some());
+        assert!(parse_cron(interface_name, Some("0 3 * * *"), &limits).is_some());
+        assert!(parse_cron(interface_name, Some("* * * * *"), &Default::default()).is_some());
+        assert!(parse_cron(interface_name, Some("every day"), &Default::default()).is_none());
+        assert!(parse_cron(interface_name, None, &limits).is_none());
+    }
+
+    #[tokio::test]
+    async fn effective_config_test() {
+        let interface_name = "io.edgehog.devicemanager.SystemStatus";
+        let config = vec![TelemetryInterfaceConfig {
+            interface_name: interface_name.to_string(),
+            enabled: Some(false),
+            period: Some(60),
+            ..Default::default()
+        }];
+        let limits = PeriodLimits {
+            min_period: Some(10),
+            max_period: None,
+        };
+
+        let (_dir, t_dir) = temp_dir();
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        let mut tel =
+            Telemetry::from_default_config(Some(config), Default::default(), limits, tx, t_dir)
+                .await;
+
+        tel.telemetry_config_event(interface_name, "periodSeconds", &AstarteType::Integer(1))
+            .await;
+
+        let msg = rx.recv().await.unwrap();
+        let TelemetryPayload::Properties(interface, properties) = msg.payload else {
+            panic!("expected the effective configuration");
+        };
+        assert_eq!(interface, EFFECTIVE_TELEMETRY_CONFIG_INTERFACE);
+        assert_eq!(
+            properties.get("/io.edgehog.devicemanager.SystemStatus/enable"),
+            Some(&AstarteType::Boolean(false))
+        );
+        assert_eq!(
+            properties.get("/io.edgehog.devicemanager.SystemStatus/periodSeconds"),
+            Some(&AstarteType::LongInteger(10))
+        );
+    }
+
     #[tokio::test]
     async fn send_data_test() {
         let (tx, mut rx) = tokio::sync::mpsc::channel(32);
@@ -538,7 +1372,7 @@ mod tests {
         ];
 
         for interface in interfaces {
-            let res = send_data(&tx, interface).await;
+            let res = send_data(&tx, interface, &Default::default(), &mut Default::default()).await;
 
             assert!(
                 res.is_ok(),
@@ -550,4 +1384,207 @@ mod tests {
             assert!(rx.recv().await.is_some());
         }
     }
+
+    #[tokio::test]
+    async fn telemetry_send_policy_test() {
+        let config: TelemetryInterfaceConfig = toml::from_str(
+            r#"
+            interface_name = "io.edgehog.devicemanager.StorageUsage"
+            enabled = true
+            period = 60
+            deadband = { percent = 5.0 }
+            max_silence = 3600
+            "#,
+        )
+        .unwrap();
+
+        let (_dir, t_dir) = temp_dir();
+
+        let (tx, _) = tokio::sync::mpsc::channel(32);
+        let tel = Telemetry::from_default_config(
+            Some(vec![config]),
+            Default::default(),
+            Default::default(),
+            tx,
+            t_dir,
+        )
+        .await;
+        let telemetry_config = tel.telemetry_task_configs.clone();
+        let interface_configs = telemetry_config.read().await;
+        let storage_usage_config = interface_configs
+            .get("io.edgehog.devicemanager.StorageUsage")
+            .unwrap();
+
+        assert_eq!(
+            storage_usage_config.policy,
+            SendPolicy::new(None, Some(Deadband::Percent(5.0)), Some(3600))
+        );
+        assert!(storage_usage_config.policy.on_change);
+    }
+
+    #[tokio::test]
+    async fn telemetry_schedule_test() {
+        let config: TelemetryInterfaceConfig = toml::from_str(
+            r#"
+            interface_name = "io.edgehog.devicemanager.SystemStatus"
+            enabled = true
+            period = 300
+            initial_offset = 120
+            jitter = 10
+            align = true
+            "#,
+        )
+        .unwrap();
+
+        let (_dir, t_dir) = temp_dir();
+
+        let (tx, _) = tokio::sync::mpsc::channel(32);
+        let tel = Telemetry::from_default_config(
+            Some(vec![config]),
+            Default::default(),
+            Default::default(),
+            tx,
+            t_dir,
+        )
+        .await;
+        let telemetry_config = tel.telemetry_task_configs.clone();
+        let interface_configs = telemetry_config.read().await;
+        let system_status_config = interface_configs
+            .get("io.edgehog.devicemanager.SystemStatus")
+            .unwrap();
+
+        assert_eq!(
+            system_status_config.schedule,
+            Schedule::new(Some(120), Some(10), Some(true))
+        );
+    }
+
+    #[tokio::test]
+    async fn telemetry_cron_test() {
+        let interface_name = "io.edgehog.devicemanager.SystemStatus";
+        let config: TelemetryInterfaceConfig = toml::from_str(
+            r#"
+            interface_name = "io.edgehog.devicemanager.SystemStatus"
+            enabled = true
+            cron = "0 3 * * *"
+            "#,
+        )
+        .unwrap();
+
+        let (_dir, t_dir) = temp_dir();
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(32);
+        let mut tel = Telemetry::from_default_config(
+            Some(vec![config]),
+            Default::default(),
+            Default::default(),
+            tx,
+            t_dir.clone(),
+        )
+        .await;
+
+        let effective_cron = |tel: &Telemetry| {
+            let telemetry_task_configs = tel.telemetry_task_configs.try_read().unwrap();
+
+            telemetry_task_configs
+                .get(interface_name)
+                .unwrap()
+                .effective_cron()
+                .map(|cron| cron.to_string())
+        };
+        assert_eq!(effective_cron(&tel).as_deref(), Some("0 3 * * *"));
+
+        // the remote period takes precedence over the local cron expression
+        tel.telemetry_config_event(interface_name, "periodSeconds", &AstarteType::Integer(60))
+            .await;
+        assert_eq!(effective_cron(&tel), None);
+
+        tel.telemetry_config_event(
+            interface_name,
+            "cron",
+            &AstarteType::String("*/5 9-17 * * 1-5".to_string()),
+        )
+        .await;
+        assert_eq!(effective_cron(&tel).as_deref(), Some("*/5 9-17 * * 1-5"));
+
+        tel.telemetry_config_event(
+            interface_name,
+            "cron",
+            &AstarteType::String("every day".to_string()),
+        )
+        .await;
+        assert_eq!(effective_cron(&tel).as_deref(), Some("*/5 9-17 * * 1-5"));
+
+        let telemetry_repo = FileStateRepository::new(t_dir, TELEMETRY_PATH.to_string());
+        let saved_config: Vec<TelemetryInterfaceConfig> = telemetry_repo.read().await.unwrap();
+        assert_eq!(saved_config[0].cron.as_deref(), Some("*/5 9-17 * * 1-5"));
+        assert_eq!(saved_config[0].period, Some(60));
+    }
+
+    #[tokio::test]
+    async fn telemetry_sample_period_test() {
+        let config: TelemetryInterfaceConfig = toml::from_str(
+            r#"
+            interface_name = "io.edgehog.devicemanager.SystemStatus"
+            enabled = true
+            period = 1
+            sample_period = 1
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.sample_period, Some(1));
+
+        let (_dir, t_dir) = temp_dir();
+
+        // a sample period not shorter than the period disables the aggregation
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        let mut tel = Telemetry::from_default_config(
+            Some(vec![config]),
+            Default::default(),
+            Default::default(),
+            tx,
+            t_dir,
+        )
+        .await;
+        tel.run_telemetry().await;
+
+        let msg = loop {
+            let msg = rx.recv().await.unwrap();
+            if !matches!(msg.payload, TelemetryPayload::Properties(..)) {
+                break msg;
+            }
+        };
+        assert!(matches!(msg.payload, TelemetryPayload::SystemStatus(_)));
+    }
+
+    #[tokio::test]
+    async fn sample_data_test() {
+        let mut window = Window::new("io.edgehog.devicemanager.SystemStatus", chrono::Utc::now());
+
+        for _ in 0..2 {
+            let samples = sample_data(
+                "io.edgehog.devicemanager.SystemStatus",
+                &Default::default(),
+                &mut window,
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(samples.len(), 1);
+            assert!(matches!(
+                samples[0].payload,
+                TelemetryPayload::SystemStatus(_)
+            ));
+        }
+
+        let aggregated = window.take(chrono::Utc::now());
+        let (path, value) = aggregated
+            .iter()
+            .find(|(path, _)| path == "SystemStatus/availMemoryBytes")
+            .unwrap();
+
+        assert_eq!(path, "SystemStatus/availMemoryBytes");
+        assert_eq!(value.samples, 2);
+        assert!(value.min <= value.avg && value.avg <= value.max);
+    }
 }
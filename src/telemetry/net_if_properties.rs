@@ -19,9 +19,35 @@
  */
 
 use crate::error::DeviceManagerError;
+use crate::repository::file_state_repository::FileStateRepository;
+use crate::repository::StateRepository;
+use crate::telemetry::{TelemetryMessage, TelemetryPayload};
 use astarte_device_sdk::types::AstarteType;
-use log::warn;
-use std::collections::HashMap;
+use log::{debug, error, warn};
+use nix::sys::socket::{
+    bind, recv, socket, AddressFamily, MsgFlags, NetlinkAddr, SockAddr, SockFlag, SockProtocol,
+    SockType,
+};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::os::unix::io::RawFd;
+use tokio::sync::mpsc::{self, Sender};
+use tokio::time::{sleep, Duration};
+
+pub const NET_IF_PROPERTIES_INTERFACE: &str = "io.edgehog.devicemanager.NetworkInterfaceProperties";
+
+const PUBLISHED_PROPERTIES_PATH: &str = "net_if_properties.json";
+
+const PROC_NET_ROUTE: &str = "/proc/net/route";
+const PROC_NET_IPV6_ROUTE: &str = "/proc/net/ipv6_route";
+
+// rtnetlink multicast groups of the links, addresses and routes changes
+const RTMGRP_LINK: u32 = 0x1;
+const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+const RTMGRP_IPV4_ROUTE: u32 = 0x40;
+const RTMGRP_IPV6_IFADDR: u32 = 0x100;
+const RTMGRP_IPV6_ROUTE: u32 = 0x400;
+// a change usually comes with a burst of messages, e.g. link up, addresses and routes
+const CHANGES_DEBOUNCE: Duration = Duration::from_secs(1);
 
 #[derive(Debug)]
 enum TechnologyType {
@@ -46,6 +72,14 @@ struct NetworkInterfaceProperties {
     interface: String,
     mac_address: String,
     technology_type: TechnologyType,
+    oper_state: String,
+    /// Link speed in Mbit/s, not available when the link is down or for wireless interfaces.
+    speed_mbps: Option<i32>,
+    duplex: Option<String>,
+    mtu: Option<i32>,
+    ipv4_addresses: Vec<String>,
+    ipv6_addresses: Vec<String>,
+    default_route: bool,
 }
 
 fn get_supported_network_interfaces() -> Result<Vec<NetworkInterfaceProperties>, DeviceManagerError>
@@ -55,6 +89,9 @@ fn get_supported_network_interfaces() -> Result<Vec<NetworkInterfaceProperties>,
 
     let mut results = Vec::new();
 
+    let mut addresses = get_addresses();
+    let default_route_interfaces = get_default_route_interfaces();
+
     let mut enumerator = udev::Enumerator::new()?;
 
     enumerator.match_subsystem("net")?;
@@ -97,16 +134,122 @@ fn get_supported_network_interfaces() -> Result<Vec<NetworkInterfaceProperties>,
             }
         };
 
+        let attribute = |name: &str| {
+            device
+                .attribute_value(name)
+                .map(|value| value.to_string_lossy().trim().to_string())
+        };
+
+        let interface = device.sysname().to_string_lossy().into_owned();
+        let (ipv4_addresses, ipv6_addresses) = addresses.remove(&interface).unwrap_or_default();
+
         results.push(NetworkInterfaceProperties {
-            interface: device.sysname().to_string_lossy().into_owned(),
             mac_address: address.to_string_lossy().into_owned(),
             technology_type,
+            oper_state: attribute("operstate").unwrap_or_else(|| "unknown".to_string()),
+            // -1 when the speed is unknown
+            speed_mbps: attribute("speed")
+                .and_then(|speed| speed.parse().ok())
+                .filter(|speed: &i32| *speed >= 0),
+            duplex: attribute("duplex").filter(|duplex| duplex != "unknown"),
+            mtu: attribute("mtu").and_then(|mtu| mtu.parse().ok()),
+            ipv4_addresses,
+            ipv6_addresses,
+            default_route: default_route_interfaces.contains(&interface),
+            interface,
         });
     }
 
     Ok(results)
 }
 
+/// Returns the IPv4 and IPv6 addresses of each interface.
+fn get_addresses() -> HashMap<String, (Vec<String>, Vec<String>)> {
+    let mut addresses: HashMap<String, (Vec<String>, Vec<String>)> = HashMap::new();
+
+    let ifaddrs = match nix::ifaddrs::getifaddrs() {
+        Ok(ifaddrs) => ifaddrs,
+        Err(err) => {
+            warn!("couldn't read the interfaces addresses: {err}");
+
+            return addresses;
+        }
+    };
+
+    for ifaddr in ifaddrs {
+        let ip = match ifaddr.address {
+            Some(SockAddr::Inet(inet)) => inet.to_std().ip(),
+            _ => continue,
+        };
+
+        let (ipv4, ipv6) = addresses.entry(ifaddr.interface_name).or_default();
+        if ip.is_ipv4() {
+            ipv4.push(ip.to_string());
+        } else {
+            ipv6.push(ip.to_string());
+        }
+    }
+
+    addresses
+}
+
+/// Returns the interfaces with an IPv4 or IPv6 default route.
+pub(crate) fn get_default_route_interfaces() -> HashSet<String> {
+    let mut interfaces = HashSet::new();
+    for (path, parse) in [
+        (
+            PROC_NET_ROUTE,
+            parse_ipv4_default_routes as fn(&str) -> Vec<String>,
+        ),
+        (PROC_NET_IPV6_ROUTE, parse_ipv6_default_routes),
+    ] {
+        match std::fs::read_to_string(path) {
+            Ok(routes) => interfaces.extend(parse(&routes)),
+            Err(err) => debug!("couldn't read {path}: {err}"),
+        }
+    }
+
+    interfaces
+}
+
+/// Returns the interfaces with a default route in `/proc/net/route`.
+fn parse_ipv4_default_routes(routes: &str) -> Vec<String> {
+    routes
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            // Iface Destination Gateway Flags RefCnt Use Metric Mask ...
+            match columns.as_slice() {
+                [iface, "00000000", _, _, _, _, _, "00000000", ..] => Some(iface.to_string()),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Returns the interfaces with a default route in `/proc/net/ipv6_route`.
+fn parse_ipv6_default_routes(routes: &str) -> Vec<String> {
+    const ANY: &str = "00000000000000000000000000000000";
+
+    routes
+        .lines()
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            // destination, prefix length, source, source prefix length, next hop, metric,
+            // reference count, use count, flags, interface
+            match columns.as_slice() {
+                [destination, "00", _, _, _, _, _, _, _, iface]
+                    if *destination == ANY && *iface != "lo" =>
+                {
+                    Some(iface.to_string())
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
 /// get structured data for `io.edgehog.devicemanager.NetworkInterfaceProperties` interface
 pub async fn get_network_interface_properties(
 ) -> Result<HashMap<String, AstarteType>, DeviceManagerError> {
@@ -127,26 +270,233 @@ fn network_interface_to_astarte(
     let mut ret: HashMap<String, AstarteType> = HashMap::new();
 
     for iff in eth_wifi {
+        let path = |field: &str| format!("/{}/{field}", iff.interface);
+
         ret.insert(
-            format!("/{}/macAddress", iff.interface),
+            path("macAddress"),
             AstarteType::String(iff.mac_address.to_ascii_lowercase()),
         );
         ret.insert(
-            format!("/{}/technologyType", iff.interface),
+            path("technologyType"),
             AstarteType::String(iff.technology_type.to_string()),
         );
+        ret.insert(
+            path("operState"),
+            AstarteType::String(iff.oper_state.clone()),
+        );
+        if let Some(speed) = iff.speed_mbps {
+            ret.insert(path("speedMbps"), AstarteType::Integer(speed));
+        }
+        if let Some(duplex) = &iff.duplex {
+            ret.insert(path("duplex"), AstarteType::String(duplex.clone()));
+        }
+        if let Some(mtu) = iff.mtu {
+            ret.insert(path("mtu"), AstarteType::Integer(mtu));
+        }
+        ret.insert(
+            path("ipv4Addresses"),
+            AstarteType::StringArray(iff.ipv4_addresses.clone()),
+        );
+        ret.insert(
+            path("ipv6Addresses"),
+            AstarteType::StringArray(iff.ipv6_addresses.clone()),
+        );
+        ret.insert(
+            path("defaultRoute"),
+            AstarteType::Boolean(iff.default_route),
+        );
     }
 
     ret
 }
 
+/// Returns the properties to set because they changed, and the ones to unset because their
+/// interface was removed or the value is not available anymore.
+fn properties_diff(
+    published: &HashMap<String, AstarteType>,
+    current: &HashMap<String, AstarteType>,
+) -> HashMap<String, AstarteType> {
+    let changed = current
+        .iter()
+        .filter(|(path, value)| published.get(*path) != Some(value))
+        .map(|(path, value)| (path.clone(), value.clone()));
+
+    let removed = published
+        .keys()
+        .filter(|path| !current.contains_key(*path))
+        .map(|path| (path.clone(), AstarteType::Unset));
+
+    changed.chain(removed).collect()
+}
+
+/// Waits on a rtnetlink socket for the links, addresses and routes changes, the udev handles
+/// can't be moved between threads so the properties are read on the async side.
+fn monitor_netlink(changes: mpsc::Sender<()>) -> Result<(), DeviceManagerError> {
+    let fd: RawFd = socket(
+        AddressFamily::Netlink,
+        SockType::Raw,
+        SockFlag::SOCK_CLOEXEC,
+        SockProtocol::NetlinkRoute,
+    )
+    .map_err(std::io::Error::from)?;
+
+    let groups = RTMGRP_LINK
+        | RTMGRP_IPV4_IFADDR
+        | RTMGRP_IPV4_ROUTE
+        | RTMGRP_IPV6_IFADDR
+        | RTMGRP_IPV6_ROUTE;
+    bind(fd, &SockAddr::Netlink(NetlinkAddr::new(0, groups))).map_err(std::io::Error::from)?;
+
+    let mut buf = [0u8; 8192];
+    loop {
+        match recv(fd, &mut buf, MsgFlags::empty()) {
+            Ok(_) | Err(nix::errno::Errno::EINTR) | Err(nix::errno::Errno::ENOBUFS) => {}
+            Err(err) => {
+                let _ = nix::unistd::close(fd);
+
+                return Err(std::io::Error::from(err).into());
+            }
+        }
+
+        // a pending notification already covers this change
+        if let Err(mpsc::error::TrySendError::Closed(_)) = changes.try_send(()) {
+            let _ = nix::unistd::close(fd);
+
+            return Ok(());
+        }
+    }
+}
+
+/// Paths of the published properties, saved to unset the ones of the interfaces removed while
+/// the runtime was not running.
+struct PublishedPaths {
+    repository: FileStateRepository,
+}
+
+impl PublishedPaths {
+    fn new(store_directory: &str) -> Self {
+        PublishedPaths {
+            repository: FileStateRepository::new(
+                store_directory.to_string(),
+                PUBLISHED_PROPERTIES_PATH.to_string(),
+            ),
+        }
+    }
+
+    /// Returns the properties published before that are not available anymore, to unset.
+    async fn removed(
+        &self,
+        current: &HashMap<String, AstarteType>,
+    ) -> HashMap<String, AstarteType> {
+        if !StateRepository::<BTreeSet<String>>::exists(&self.repository).await {
+            return HashMap::new();
+        }
+
+        let published: BTreeSet<String> = match self.repository.read().await {
+            Ok(published) => published,
+            Err(err) => {
+                warn!("couldn't read the published network interfaces: {err}");
+                return HashMap::new();
+            }
+        };
+
+        published
+            .into_iter()
+            .filter(|path| !current.contains_key(path))
+            .map(|path| (path, AstarteType::Unset))
+            .collect()
+    }
+
+    async fn save(&self, published: &HashMap<String, AstarteType>) {
+        let paths: BTreeSet<String> = published.keys().cloned().collect();
+        if let Err(err) = self.repository.write(&paths).await {
+            warn!("couldn't save the published network interfaces: {err}");
+        }
+    }
+}
+
+/// Republishes the properties of the network interfaces when they appear, disappear or change.
+///
+/// At start it unsets the properties of the interfaces removed while the runtime was not running.
+pub async fn watch(communication_channel: Sender<TelemetryMessage>, store_directory: String) {
+    // listen before reading the interfaces, to not miss the changes in between
+    let (changes_tx, mut changes_rx) = mpsc::channel(1);
+    let monitor = tokio::task::spawn_blocking(move || monitor_netlink(changes_tx));
+
+    let published_paths = PublishedPaths::new(&store_directory);
+    let mut published = match get_network_interface_properties().await {
+        Ok(current) => {
+            let removed = published_paths.removed(&current).await;
+            if !removed.is_empty() {
+                debug!("network interfaces removed while not running: {removed:?}");
+
+                let _ = communication_channel
+                    .send(TelemetryMessage {
+                        path: "".to_string(),
+                        payload: TelemetryPayload::Properties(NET_IF_PROPERTIES_INTERFACE, removed),
+                    })
+                    .await;
+            }
+            published_paths.save(&current).await;
+
+            current
+        }
+        Err(err) => {
+            warn!("couldn't read the network interfaces: {err}");
+
+            HashMap::new()
+        }
+    };
+
+    while changes_rx.recv().await.is_some() {
+        sleep(CHANGES_DEBOUNCE).await;
+        // drop the notification of the changes received while waiting
+        let _ = changes_rx.try_recv();
+
+        let current = match get_network_interface_properties().await {
+            Ok(current) => current,
+            Err(err) => {
+                warn!("couldn't read the network interfaces: {err}");
+                continue;
+            }
+        };
+
+        let diff = properties_diff(&published, &current);
+        published = current;
+
+        if diff.is_empty() {
+            continue;
+        }
+
+        debug!("network interfaces changed: {diff:?}");
+
+        let _ = communication_channel
+            .send(TelemetryMessage {
+                path: "".to_string(),
+                payload: TelemetryPayload::Properties(NET_IF_PROPERTIES_INTERFACE, diff),
+            })
+            .await;
+
+        published_paths.save(&published).await;
+    }
+
+    match monitor.await {
+        Ok(Err(err)) => error!("couldn't monitor the network interfaces: {err}"),
+        Err(err) => error!("network interfaces monitor failed: {err}"),
+        Ok(Ok(())) => {}
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::telemetry::net_if_properties::{
-        get_supported_network_interfaces, network_interface_to_astarte, NetworkInterfaceProperties,
+        get_supported_network_interfaces, network_interface_to_astarte, parse_ipv4_default_routes,
+        parse_ipv6_default_routes, properties_diff, NetworkInterfaceProperties, PublishedPaths,
         TechnologyType,
     };
     use astarte_device_sdk::types::AstarteType;
+    use std::collections::HashMap;
+    use tempdir::TempDir;
 
     #[test]
     fn technology_type_to_string_test() {
@@ -162,16 +512,37 @@ mod tests {
                 interface: "wifi_test".to_string(),
                 mac_address: "00:11:22:33:44:55".to_string(),
                 technology_type: TechnologyType::WiFi,
+                oper_state: "up".to_string(),
+                speed_mbps: None,
+                duplex: None,
+                mtu: Some(1500),
+                ipv4_addresses: vec![],
+                ipv6_addresses: vec![],
+                default_route: false,
             },
             NetworkInterfaceProperties {
                 interface: "eth_test".to_string(),
                 mac_address: "11:22:33:44:55:66".to_string(),
                 technology_type: TechnologyType::Ethernet,
+                oper_state: "up".to_string(),
+                speed_mbps: None,
+                duplex: None,
+                mtu: Some(1500),
+                ipv4_addresses: vec![],
+                ipv6_addresses: vec![],
+                default_route: false,
             },
             NetworkInterfaceProperties {
                 interface: "cellular_test".to_string(),
                 mac_address: "22:33:44:55:66:77".to_string(),
                 technology_type: TechnologyType::Cellular,
+                oper_state: "up".to_string(),
+                speed_mbps: None,
+                duplex: None,
+                mtu: Some(1500),
+                ipv4_addresses: vec![],
+                ipv6_addresses: vec![],
+                default_route: false,
             },
         ];
 
@@ -209,4 +580,147 @@ mod tests {
     fn get_supported_network_interfaces_run_test() {
         assert!(get_supported_network_interfaces().is_ok());
     }
+
+    #[test]
+    fn network_interface_link_to_astarte_test() {
+        let eth = NetworkInterfaceProperties {
+            interface: "eth0".to_string(),
+            mac_address: "AA:BB:CC:DD:EE:FF".to_string(),
+            technology_type: TechnologyType::Ethernet,
+            oper_state: "up".to_string(),
+            speed_mbps: Some(1000),
+            duplex: Some("full".to_string()),
+            mtu: Some(1500),
+            ipv4_addresses: vec!["192.168.1.10".to_string()],
+            ipv6_addresses: vec!["fe80::a8bb:ccff:fedd:eeff".to_string()],
+            default_route: true,
+        };
+
+        let astarte_payload = network_interface_to_astarte(vec![eth]);
+
+        assert_eq!(
+            astarte_payload.get("/eth0/macAddress"),
+            Some(&AstarteType::String("aa:bb:cc:dd:ee:ff".to_string()))
+        );
+        assert_eq!(
+            astarte_payload.get("/eth0/operState"),
+            Some(&AstarteType::String("up".to_string()))
+        );
+        assert_eq!(
+            astarte_payload.get("/eth0/speedMbps"),
+            Some(&AstarteType::Integer(1000))
+        );
+        assert_eq!(
+            astarte_payload.get("/eth0/duplex"),
+            Some(&AstarteType::String("full".to_string()))
+        );
+        assert_eq!(
+            astarte_payload.get("/eth0/mtu"),
+            Some(&AstarteType::Integer(1500))
+        );
+        assert_eq!(
+            astarte_payload.get("/eth0/ipv4Addresses"),
+            Some(&AstarteType::StringArray(vec!["192.168.1.10".to_string()]))
+        );
+        assert_eq!(
+            astarte_payload.get("/eth0/ipv6Addresses"),
+            Some(&AstarteType::StringArray(vec![
+                "fe80::a8bb:ccff:fedd:eeff".to_string()
+            ]))
+        );
+        assert_eq!(
+            astarte_payload.get("/eth0/defaultRoute"),
+            Some(&AstarteType::Boolean(true))
+        );
+    }
+
+    #[test]
+    fn parse_default_routes_test() {
+        let ipv4_routes =
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+wlan0\t0001A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0
+";
+        assert_eq!(parse_ipv4_default_routes(ipv4_routes), ["wlan0"]);
+
+        let ipv6_routes = "fe800000000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001 eth0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000400 00000001 00000000 00000003 usb0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 00000000000000000000000000000000 ffffffff 00000001 00000000 00200200 lo
+";
+        assert_eq!(parse_ipv6_default_routes(ipv6_routes), ["usb0"]);
+    }
+
+    #[test]
+    fn properties_diff_test() {
+        let published = HashMap::from([
+            (
+                "/eth0/operState".to_string(),
+                AstarteType::String("up".to_string()),
+            ),
+            ("/eth0/speedMbps".to_string(), AstarteType::Integer(1000)),
+            (
+                "/usb0/operState".to_string(),
+                AstarteType::String("up".to_string()),
+            ),
+        ]);
+        let current = HashMap::from([
+            (
+                "/eth0/operState".to_string(),
+                AstarteType::String("down".to_string()),
+            ),
+            (
+                "/wwan0/operState".to_string(),
+                AstarteType::String("up".to_string()),
+            ),
+        ]);
+
+        let diff = properties_diff(&published, &current);
+
+        assert_eq!(
+            diff,
+            HashMap::from([
+                (
+                    "/eth0/operState".to_string(),
+                    AstarteType::String("down".to_string())
+                ),
+                ("/eth0/speedMbps".to_string(), AstarteType::Unset),
+                ("/usb0/operState".to_string(), AstarteType::Unset),
+                (
+                    "/wwan0/operState".to_string(),
+                    AstarteType::String("up".to_string())
+                ),
+            ])
+        );
+        assert!(properties_diff(&current, &current).is_empty());
+    }
+
+    #[tokio::test]
+    async fn published_paths_test() {
+        let store = TempDir::new("store").unwrap();
+        let published_paths = PublishedPaths::new(store.path().to_str().unwrap());
+        let properties = |paths: &[&str]| -> HashMap<String, AstarteType> {
+            paths
+                .iter()
+                .map(|path| (path.to_string(), AstarteType::String("up".to_string())))
+                .collect()
+        };
+
+        // nothing was published before the first start
+        assert!(published_paths
+            .removed(&properties(&["/eth0/operState"]))
+            .await
+            .is_empty());
+
+        published_paths
+            .save(&properties(&["/eth0/operState", "/wwan0/operState"]))
+            .await;
+        // restarted after the modem was unplugged
+        let published_paths = PublishedPaths::new(store.path().to_str().unwrap());
+        assert_eq!(
+            published_paths
+                .removed(&properties(&["/eth0/operState"]))
+                .await,
+            HashMap::from([("/wwan0/operState".to_string(), AstarteType::Unset)])
+        );
+    }
 }
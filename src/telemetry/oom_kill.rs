@@ -0,0 +1,227 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Out of memory kill events, read from the kernel log or counted from `/proc/vmstat`.
+
+use std::io::SeekFrom;
+use std::path::Path;
+
+use astarte_device_sdk::AstarteAggregate;
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use nix::errno::Errno;
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncSeekExt, BufReader};
+use tokio::sync::mpsc::Sender;
+use tokio::time::{interval, Duration};
+
+use crate::error::DeviceManagerError;
+use crate::telemetry::{TelemetryMessage, TelemetryPayload};
+
+const KMSG_PATH: &str = "/dev/kmsg";
+const VMSTAT_PATH: &str = "/proc/vmstat";
+const VMSTAT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, AstarteAggregate, PartialEq)]
+#[allow(non_snake_case)]
+pub struct OomKillEvent {
+    /// Name of the killed process, empty if unknown.
+    pub processName: String,
+    /// Pid of the killed process, -1 if unknown.
+    pub pid: i32,
+}
+
+/// Watches the kernel log and publishes an event for each process killed by the OOM killer.
+///
+/// When the kernel log can't be read, e.g. without `CAP_SYSLOG`, the kills are counted from
+/// `/proc/vmstat` and the events have no process information.
+pub async fn watch(
+    communication_channel: &Sender<TelemetryMessage>,
+) -> Result<(), DeviceManagerError> {
+    match File::open(KMSG_PATH).await {
+        Ok(kmsg) => watch_kmsg(kmsg, communication_channel).await,
+        Err(err) => {
+            debug!("couldn't open {KMSG_PATH}, counting the OOM kills from vmstat: {err}");
+
+            watch_vmstat(Path::new(VMSTAT_PATH), communication_channel).await
+        }
+    }
+}
+
+async fn watch_kmsg(
+    mut kmsg: File,
+    communication_channel: &Sender<TelemetryMessage>,
+) -> Result<(), DeviceManagerError> {
+    // only the kills happening from now on
+    kmsg.seek(SeekFrom::End(0)).await?;
+
+    let mut reader = BufReader::new(kmsg);
+    let mut record = String::new();
+    loop {
+        record.clear();
+        match reader.read_line(&mut record).await {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            // the reader was too slow and the kernel overwrote the next records
+            Err(err) if err.raw_os_error() == Some(Errno::EPIPE as i32) => {
+                warn!("kernel log records lost: {err}");
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        if let Some(event) = parse_kmsg_record(&record) {
+            send_event(communication_channel, event, Utc::now()).await;
+        }
+    }
+}
+
+async fn watch_vmstat(
+    vmstat_path: &Path,
+    communication_channel: &Sender<TelemetryMessage>,
+) -> Result<(), DeviceManagerError> {
+    let mut last_count = read_oom_kill_count(vmstat_path).await?;
+    let mut interval = interval(VMSTAT_POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let count = read_oom_kill_count(vmstat_path).await?;
+        for _ in last_count..count {
+            let event = OomKillEvent {
+                processName: String::new(),
+                pid: -1,
+            };
+
+            send_event(communication_channel, event, Utc::now()).await;
+        }
+
+        last_count = count;
+    }
+}
+
+async fn send_event(
+    communication_channel: &Sender<TelemetryMessage>,
+    event: OomKillEvent,
+    timestamp: DateTime<Utc>,
+) {
+    debug!("process killed for out of memory: {event:?}");
+
+    let _ = communication_channel
+        .send(TelemetryMessage {
+            path: "".to_string(),
+            payload: TelemetryPayload::OomKill(event, timestamp),
+        })
+        .await;
+}
+
+async fn read_oom_kill_count(vmstat_path: &Path) -> Result<u64, DeviceManagerError> {
+    let vmstat = tokio::fs::read_to_string(vmstat_path).await?;
+
+    Ok(parse_oom_kill_count(&vmstat))
+}
+
+/// Returns the `oom_kill` counter, 0 on kernels older than 4.13 that don't have it.
+fn parse_oom_kill_count(vmstat: &str) -> u64 {
+    vmstat
+        .lines()
+        .find_map(|line| line.strip_prefix("oom_kill "))
+        .and_then(|count| count.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Parses a `/dev/kmsg` record, e.g.
+/// `3,1021,2453901,-;Out of memory: Killed process 1234 (stress) total-vm:1024kB, ...`
+fn parse_kmsg_record(record: &str) -> Option<OomKillEvent> {
+    let (_, message) = record.split_once(';')?;
+    let (_, killed) = message.split_once("Killed process ")?;
+
+    let (pid, rest) = killed.split_once(' ')?;
+    let process_name = rest.strip_prefix('(')?.split_once(')')?.0;
+
+    Some(OomKillEvent {
+        processName: process_name.to_string(),
+        pid: pid.parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::telemetry::oom_kill::{
+        parse_kmsg_record, parse_oom_kill_count, watch_vmstat, OomKillEvent,
+    };
+    use crate::telemetry::TelemetryPayload;
+    use tempdir::TempDir;
+
+    #[test]
+    fn parse_kmsg_record_test() {
+        let record = "3,1021,2453901,-;Out of memory: Killed process 1234 (stress ng) total-vm:1024kB, anon-rss:512kB\n";
+        assert_eq!(
+            parse_kmsg_record(record),
+            Some(OomKillEvent {
+                processName: "stress ng".to_string(),
+                pid: 1234,
+            })
+        );
+
+        let cgroup =
+            "3,1022,2453902,-;Memory cgroup out of memory: Killed process 42 (node) total-vm:1kB\n";
+        assert_eq!(parse_kmsg_record(cgroup).unwrap().pid, 42);
+
+        assert!(
+            parse_kmsg_record("6,1023,2453903,-;usb 1-1: new high-speed USB device\n").is_none()
+        );
+        assert!(parse_kmsg_record(" SUBSYSTEM=usb\n").is_none());
+    }
+
+    #[test]
+    fn parse_oom_kill_count_test() {
+        assert_eq!(
+            parse_oom_kill_count("pgfault 1000\noom_kill 3\npgmajfault 2\n"),
+            3
+        );
+        assert_eq!(parse_oom_kill_count("pgfault 1000\n"), 0);
+    }
+
+    #[tokio::test]
+    async fn watch_vmstat_test() {
+        let dir = TempDir::new("vmstat").unwrap();
+        let vmstat = dir.path().join("vmstat");
+        std::fs::write(&vmstat, "oom_kill 1\n").unwrap();
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        let watch_path = vmstat.clone();
+        let watcher = tokio::spawn(async move { watch_vmstat(&watch_path, &tx).await });
+
+        // let the watcher read the initial count
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        std::fs::write(&vmstat, "oom_kill 3\n").unwrap();
+
+        for _ in 0..2 {
+            let msg = rx.recv().await.unwrap();
+            assert!(matches!(
+                msg.payload,
+                TelemetryPayload::OomKill(OomKillEvent { pid: -1, .. }, _)
+            ));
+        }
+
+        watcher.abort();
+    }
+}
@@ -0,0 +1,350 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Installed software packages, read from the dpkg or opkg database or from rpm.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::process::Command;
+
+use crate::error::DeviceManagerError;
+use crate::repository::file_state_repository::FileStateRepository;
+use crate::repository::StateRepository;
+
+const DPKG_STATUS_PATH: &str = "/var/lib/dpkg/status";
+const OPKG_STATUS_PATHS: [&str; 2] = ["/var/lib/opkg/status", "/usr/lib/opkg/status"];
+const PUBLISHED_INVENTORY_PATH: &str = "package_inventory.json";
+
+/// Versions of the installed packages, by name.
+pub type Inventory = BTreeMap<String, String>;
+
+/// Changes of the inventory since the last published one.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InventoryDiff {
+    /// New packages or packages with a different version.
+    pub changed: Vec<(String, String)>,
+    pub removed: Vec<String>,
+    /// Hash of the full inventory, `None` if it didn't change.
+    pub hash: Option<String>,
+}
+
+impl InventoryDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.removed.is_empty() && self.hash.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+struct PublishedInventory {
+    hash: String,
+    packages: Inventory,
+}
+
+/// Reads the package database of the first supported package manager.
+pub async fn read_inventory() -> Result<Inventory, DeviceManagerError> {
+    let status_paths = std::iter::once(DPKG_STATUS_PATH).chain(OPKG_STATUS_PATHS);
+    for path in status_paths {
+        if Path::new(path).exists() {
+            debug!("reading the package inventory from {path}");
+
+            let status = tokio::fs::read_to_string(path).await?;
+
+            return Ok(parse_status(&status));
+        }
+    }
+
+    debug!("reading the package inventory from rpm");
+
+    let output = Command::new("rpm")
+        .args(["-qa", "--queryformat", "%{NAME} %{VERSION}-%{RELEASE}\\n"])
+        .output()
+        .await
+        .map_err(|err| {
+            DeviceManagerError::PackageInventoryError(format!("no package database found: {err}"))
+        })?;
+
+    if !output.status.success() {
+        return Err(DeviceManagerError::PackageInventoryError(format!(
+            "rpm exited with {}",
+            output.status
+        )));
+    }
+
+    Ok(parse_rpm_output(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parses a dpkg or opkg status file, keeping only the installed packages.
+fn parse_status(status: &str) -> Inventory {
+    let mut inventory = Inventory::new();
+
+    for paragraph in status.split("\n\n") {
+        let mut name = None;
+        let mut version = None;
+        let mut installed = false;
+
+        for line in paragraph.lines() {
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field, value.trim()),
+                None => continue,
+            };
+
+            match field {
+                "Package" => name = Some(value),
+                "Version" => version = Some(value),
+                // e.g. "install ok installed", the last word is the state
+                "Status" => installed = value.split_whitespace().last() == Some("installed"),
+                _ => {}
+            }
+        }
+
+        if let (Some(name), Some(version), true) = (name, version, installed) {
+            inventory.insert(name.to_string(), version.to_string());
+        }
+    }
+
+    inventory
+}
+
+fn parse_rpm_output(output: &str) -> Inventory {
+    output
+        .lines()
+        .filter_map(|line| line.trim().split_once(' '))
+        .map(|(name, version)| (name.to_string(), version.to_string()))
+        .collect()
+}
+
+/// Hex SHA-256 of the `<name> <version>\n` lines of the inventory sorted by name.
+pub fn inventory_hash(inventory: &Inventory) -> String {
+    let mut hasher = Sha256::new();
+    for (name, version) in inventory {
+        hasher.update(format!("{name} {version}\n"));
+    }
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Path of the version property of a package.
+///
+/// The characters of the name other than letters, digits and `-`, like the `+` of `libstdc++6`
+/// which isn't allowed in a topic, are escaped as `_` and their hex value, e.g.
+/// `/packages/libstdc_2b_2b6`.
+pub fn package_path(name: &str) -> String {
+    let name: String = name
+        .bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' => char::from(byte).to_string(),
+            _ => format!("_{byte:02x}"),
+        })
+        .collect();
+
+    format!("/packages/{name}")
+}
+
+fn diff(published: &PublishedInventory, inventory: &Inventory, hash: String) -> InventoryDiff {
+    if published.hash == hash {
+        return InventoryDiff::default();
+    }
+
+    let changed = inventory
+        .iter()
+        .filter(|(name, version)| published.packages.get(*name) != Some(version))
+        .map(|(name, version)| (name.clone(), version.clone()))
+        .collect();
+
+    let removed = published
+        .packages
+        .keys()
+        .filter(|name| !inventory.contains_key(*name))
+        .cloned()
+        .collect();
+
+    InventoryDiff {
+        changed,
+        removed,
+        hash: Some(hash),
+    }
+}
+
+/// Last inventory published on `io.edgehog.devicemanager.PackageInventory`.
+pub struct PackageInventory {
+    repository: Box<dyn StateRepository<PublishedInventory>>,
+}
+
+impl PackageInventory {
+    pub fn new(store_directory: &str) -> Self {
+        PackageInventory {
+            repository: Box::new(FileStateRepository::new(
+                store_directory.to_string(),
+                PUBLISHED_INVENTORY_PATH.to_string(),
+            )),
+        }
+    }
+
+    /// Returns the changes of the inventory since the last published one.
+    pub async fn diff(&self, inventory: &Inventory) -> InventoryDiff {
+        let published = if self.repository.exists().await {
+            self.repository.read().await.unwrap_or_default()
+        } else {
+            PublishedInventory::default()
+        };
+
+        let diff = diff(&published, inventory, inventory_hash(inventory));
+
+        info!(
+            "package inventory: {} changed, {} removed",
+            diff.changed.len(),
+            diff.removed.len()
+        );
+
+        diff
+    }
+
+    /// Saves the inventory after it has been published.
+    pub async fn save(&self, inventory: Inventory) -> Result<(), DeviceManagerError> {
+        let published = PublishedInventory {
+            hash: inventory_hash(&inventory),
+            packages: inventory,
+        };
+
+        self.repository.write(&published).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::telemetry::package_inventory::{
+        inventory_hash, package_path, parse_rpm_output, parse_status, Inventory, PackageInventory,
+    };
+    use tempdir::TempDir;
+
+    const DPKG_STATUS: &str = "Package: bash
+Status: install ok installed
+Priority: required
+Version: 5.1-6ubuntu1
+Description: GNU Bourne Again SHell
+ Bash is an sh-compatible command language interpreter.
+
+Package: vim
+Status: deinstall ok config-files
+Version: 2:8.2.3995-1ubuntu2
+
+Package: libstdc++6
+Status: install ok installed
+Architecture: amd64
+Version: 12.3.0-1ubuntu1~22.04
+";
+
+    const OPKG_STATUS: &str = "Package: busybox
+Version: 1.35.0-r0
+Depends: libc6 (>= 2.35)
+Status: install user installed
+Architecture: cortexa53
+
+Package: dropbear
+Version: 2020.81-r0
+Status: install ok not-installed
+";
+
+    fn inventory(packages: &[(&str, &str)]) -> Inventory {
+        packages
+            .iter()
+            .map(|(name, version)| (name.to_string(), version.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_dpkg_status_test() {
+        assert_eq!(
+            parse_status(DPKG_STATUS),
+            inventory(&[
+                ("bash", "5.1-6ubuntu1"),
+                ("libstdc++6", "12.3.0-1ubuntu1~22.04")
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_opkg_status_test() {
+        assert_eq!(
+            parse_status(OPKG_STATUS),
+            inventory(&[("busybox", "1.35.0-r0")])
+        );
+    }
+
+    #[test]
+    fn parse_rpm_output_test() {
+        assert_eq!(
+            parse_rpm_output("bash 5.1.8-6.el9\nopenssl 3.0.7-24.el9\n\n"),
+            inventory(&[("bash", "5.1.8-6.el9"), ("openssl", "3.0.7-24.el9")])
+        );
+    }
+
+    #[test]
+    fn inventory_hash_test() {
+        let hash = inventory_hash(&inventory(&[("bash", "5.1")]));
+
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, inventory_hash(&inventory(&[("bash", "5.1")])));
+        assert_ne!(hash, inventory_hash(&inventory(&[("bash", "5.2")])));
+    }
+
+    #[test]
+    fn package_path_test() {
+        assert_eq!(package_path("bash"), "/packages/bash");
+        assert_eq!(package_path("libstdc++6"), "/packages/libstdc_2b_2b6");
+        assert_eq!(package_path("python3.10"), "/packages/python3_2e10");
+        assert_ne!(package_path("g++"), package_path("g_2b+"));
+    }
+
+    #[tokio::test]
+    async fn inventory_diff_test() {
+        let dir = TempDir::new("edgehog").unwrap();
+        let packages = PackageInventory::new(dir.path().to_str().unwrap());
+
+        let first = inventory(&[("bash", "5.1"), ("vim", "8.2")]);
+        let diff = packages.diff(&first).await;
+        assert_eq!(diff.changed.len(), 2);
+        assert!(diff.removed.is_empty());
+        assert_eq!(diff.hash, Some(inventory_hash(&first)));
+
+        packages.save(first.clone()).await.unwrap();
+        assert!(packages.diff(&first).await.is_empty());
+
+        let second = inventory(&[("bash", "5.2"), ("curl", "7.81")]);
+        let diff = packages.diff(&second).await;
+        assert_eq!(
+            diff.changed,
+            [
+                ("bash".to_string(), "5.2".to_string()),
+                ("curl".to_string(), "7.81".to_string())
+            ]
+        );
+        assert_eq!(diff.removed, ["vim"]);
+        assert_eq!(diff.hash, Some(inventory_hash(&second)));
+    }
+}
@@ -0,0 +1,646 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! USB and PCI peripherals, enumerated and monitored through udev.
+
+use std::collections::{BTreeSet, HashMap};
+use std::os::unix::io::AsRawFd;
+
+use astarte_device_sdk::types::AstarteType;
+use astarte_device_sdk::AstarteAggregate;
+use log::{debug, error, warn};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use tokio::sync::mpsc::{self, Sender};
+
+use crate::error::DeviceManagerError;
+use crate::repository::file_state_repository::FileStateRepository;
+use crate::repository::StateRepository;
+use crate::telemetry::{TelemetryMessage, TelemetryPayload};
+
+pub const PERIPHERALS_INTERFACE: &str = "io.edgehog.devicemanager.Peripherals";
+const PUBLISHED_PERIPHERALS_PATH: &str = "peripherals.json";
+
+const PROPERTY_FIELDS: [&str; 6] = [
+    "bus",
+    "vendorId",
+    "productId",
+    "vendorName",
+    "productName",
+    "serial",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Bus {
+    Usb,
+    Pci,
+}
+
+impl Bus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Bus::Usb => "usb",
+            Bus::Pci => "pci",
+        }
+    }
+}
+
+/// The udev properties and sysfs attributes of a device needed to identify it.
+#[derive(Debug, Clone, Default)]
+struct DeviceFields {
+    subsystem: String,
+    devtype: String,
+    sysname: String,
+    properties: HashMap<String, String>,
+    attributes: HashMap<String, String>,
+}
+
+impl DeviceFields {
+    const PROPERTIES: [&'static str; 6] = [
+        "ID_VENDOR_FROM_DATABASE",
+        "ID_MODEL_FROM_DATABASE",
+        "ID_VENDOR_ID",
+        "ID_MODEL_ID",
+        "ID_SERIAL_SHORT",
+        "PCI_ID",
+    ];
+    const ATTRIBUTES: [&'static str; 7] = [
+        "idVendor",
+        "idProduct",
+        "manufacturer",
+        "product",
+        "serial",
+        "vendor",
+        "device",
+    ];
+
+    fn from_udev(device: &udev::Device) -> Self {
+        let to_string = |value: Option<&std::ffi::OsStr>| {
+            value.map(|value| value.to_string_lossy().trim().to_string())
+        };
+
+        let properties = Self::PROPERTIES
+            .iter()
+            .filter_map(|key| Some((key.to_string(), to_string(device.property_value(key))?)))
+            .collect();
+        let attributes = Self::ATTRIBUTES
+            .iter()
+            .filter_map(|key| Some((key.to_string(), to_string(device.attribute_value(key))?)))
+            .collect();
+
+        DeviceFields {
+            subsystem: to_string(device.subsystem()).unwrap_or_default(),
+            devtype: to_string(device.devtype()).unwrap_or_default(),
+            sysname: device.sysname().to_string_lossy().into_owned(),
+            properties,
+            attributes,
+        }
+    }
+
+    fn property(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(String::as_str)
+    }
+
+    fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes.get(key).map(String::as_str)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Peripheral {
+    /// Bus and sysfs name of the device, escaped to be used as an interface path.
+    id: String,
+    bus: Bus,
+    vendor_id: String,
+    product_id: String,
+    vendor_name: String,
+    product_name: String,
+    serial: String,
+}
+
+impl Peripheral {
+    /// Returns `None` if the device is not a USB device or a PCI function.
+    fn from_fields(fields: &DeviceFields) -> Option<Self> {
+        let bus = match (fields.subsystem.as_str(), fields.devtype.as_str()) {
+            ("usb", "usb_device") => Bus::Usb,
+            ("pci", _) => Bus::Pci,
+            _ => return None,
+        };
+
+        let (vendor_id, product_id) = match bus {
+            Bus::Usb => (
+                fields
+                    .attribute("idVendor")
+                    .or_else(|| fields.property("ID_VENDOR_ID")),
+                fields
+                    .attribute("idProduct")
+                    .or_else(|| fields.property("ID_MODEL_ID")),
+            ),
+            Bus::Pci => match fields.property("PCI_ID").and_then(|id| id.split_once(':')) {
+                Some((vendor, product)) => (Some(vendor), Some(product)),
+                None => (fields.attribute("vendor"), fields.attribute("device")),
+            },
+        };
+
+        let (vendor_name, product_name) = match bus {
+            Bus::Usb => (
+                fields
+                    .property("ID_VENDOR_FROM_DATABASE")
+                    .or_else(|| fields.attribute("manufacturer")),
+                fields
+                    .property("ID_MODEL_FROM_DATABASE")
+                    .or_else(|| fields.attribute("product")),
+            ),
+            Bus::Pci => (
+                fields.property("ID_VENDOR_FROM_DATABASE"),
+                fields.property("ID_MODEL_FROM_DATABASE"),
+            ),
+        };
+
+        let serial = match bus {
+            Bus::Usb => fields
+                .attribute("serial")
+                .or_else(|| fields.property("ID_SERIAL_SHORT")),
+            Bus::Pci => None,
+        };
+
+        Some(Peripheral {
+            id: peripheral_id(bus, &fields.sysname),
+            bus,
+            vendor_id: normalize_id(vendor_id.unwrap_or_default()),
+            product_id: normalize_id(product_id.unwrap_or_default()),
+            vendor_name: vendor_name.unwrap_or_default().to_string(),
+            product_name: product_name.unwrap_or_default().to_string(),
+            serial: serial.unwrap_or_default().to_string(),
+        })
+    }
+
+    fn to_astarte(&self) -> HashMap<String, AstarteType> {
+        let values = [
+            self.bus.as_str(),
+            self.vendor_id.as_str(),
+            self.product_id.as_str(),
+            self.vendor_name.as_str(),
+            self.product_name.as_str(),
+            self.serial.as_str(),
+        ];
+
+        PROPERTY_FIELDS
+            .iter()
+            .zip(values)
+            .map(|(field, value)| {
+                (
+                    format!("/{}/{field}", self.id),
+                    AstarteType::String(value.to_string()),
+                )
+            })
+            .collect()
+    }
+
+    fn to_event(&self, action: &str) -> PeripheralEvent {
+        PeripheralEvent {
+            action: action.to_string(),
+            id: self.id.clone(),
+            bus: self.bus.as_str().to_string(),
+            vendorId: self.vendor_id.clone(),
+            productId: self.product_id.clone(),
+            vendorName: self.vendor_name.clone(),
+            productName: self.product_name.clone(),
+            serial: self.serial.clone(),
+        }
+    }
+}
+
+/// Unsets the properties of a removed peripheral.
+fn unset_astarte(id: &str) -> HashMap<String, AstarteType> {
+    PROPERTY_FIELDS
+        .iter()
+        .map(|field| (format!("/{id}/{field}"), AstarteType::Unset))
+        .collect()
+}
+
+/// Escapes the sysfs name, e.g. `1-1.2` or `0000:00:1f.2`, to a valid path segment.
+fn peripheral_id(bus: Bus, sysname: &str) -> String {
+    let sysname: String = sysname
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    format!("{}-{sysname}", bus.as_str())
+}
+
+/// Lowercase hex id without prefix, the PCI sysfs attributes are prefixed by `0x`.
+fn normalize_id(id: &str) -> String {
+    id.trim_start_matches("0x").to_ascii_lowercase()
+}
+
+#[derive(Debug, Clone, AstarteAggregate, PartialEq)]
+#[allow(non_snake_case)]
+pub struct PeripheralEvent {
+    /// "Any of: added, removed"
+    pub action: String,
+    pub id: String,
+    pub bus: String,
+    pub vendorId: String,
+    pub productId: String,
+    pub vendorName: String,
+    pub productName: String,
+    pub serial: String,
+}
+
+fn enumerate_peripherals() -> Result<Vec<Peripheral>, DeviceManagerError> {
+    let mut peripherals = Vec::new();
+
+    for subsystem in ["usb", "pci"] {
+        let mut enumerator = udev::Enumerator::new()?;
+        enumerator.match_subsystem(subsystem)?;
+
+        peripherals.extend(
+            enumerator
+                .scan_devices()?
+                .filter_map(|device| Peripheral::from_fields(&DeviceFields::from_udev(&device))),
+        );
+    }
+
+    Ok(peripherals)
+}
+
+/// get structured data for `io.edgehog.devicemanager.Peripherals` interface
+pub fn get_peripherals() -> Result<HashMap<String, AstarteType>, DeviceManagerError> {
+    let peripherals = enumerate_peripherals().map_or_else(
+        |err| {
+            warn!("couldn't enumerate the peripherals: {err}");
+            Default::default()
+        },
+        |peripherals| peripherals,
+    );
+
+    Ok(peripherals
+        .iter()
+        .flat_map(Peripheral::to_astarte)
+        .collect())
+}
+
+#[derive(Debug)]
+enum Hotplug {
+    Added(DeviceFields),
+    Removed(DeviceFields),
+}
+
+/// Listens to the udev events on a blocking thread, the udev handles can't be moved between
+/// threads.
+fn monitor_udev(events: mpsc::Sender<Hotplug>) -> Result<(), DeviceManagerError> {
+    let socket = udev::MonitorBuilder::new()?
+        .match_subsystem_devtype("usb", "usb_device")?
+        .match_subsystem("pci")?
+        .listen()?;
+
+    let mut fds = [PollFd::new(socket.as_raw_fd(), PollFlags::POLLIN)];
+    loop {
+        match poll(&mut fds, -1) {
+            Ok(_) | Err(Errno::EINTR) => {}
+            Err(err) => return Err(std::io::Error::from(err).into()),
+        }
+
+        for event in socket.iter() {
+            let fields = DeviceFields::from_udev(&event.device());
+            let hotplug = match event.event_type() {
+                udev::EventType::Add => Hotplug::Added(fields),
+                udev::EventType::Remove => Hotplug::Removed(fields),
+                _ => continue,
+            };
+
+            if events.blocking_send(hotplug).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Ids of the peripherals whose properties have been published, saved to unset the ones removed
+/// while the runtime was not running.
+struct PublishedPeripherals {
+    repository: FileStateRepository,
+}
+
+impl PublishedPeripherals {
+    fn new(store_directory: &str) -> Self {
+        PublishedPeripherals {
+            repository: FileStateRepository::new(
+                store_directory.to_string(),
+                PUBLISHED_PERIPHERALS_PATH.to_string(),
+            ),
+        }
+    }
+
+    /// Returns the ids published before that are not connected anymore.
+    async fn removed(&self, known: &HashMap<String, Peripheral>) -> Vec<String> {
+        if !StateRepository::<BTreeSet<String>>::exists(&self.repository).await {
+            return Vec::new();
+        }
+
+        let published: BTreeSet<String> = match self.repository.read().await {
+            Ok(published) => published,
+            Err(err) => {
+                warn!("couldn't read the published peripherals: {err}");
+                return Vec::new();
+            }
+        };
+
+        published
+            .into_iter()
+            .filter(|id| !known.contains_key(id))
+            .collect()
+    }
+
+    async fn save(&self, known: &HashMap<String, Peripheral>) {
+        let ids: BTreeSet<String> = known.keys().cloned().collect();
+        if let Err(err) = self.repository.write(&ids).await {
+            warn!("couldn't save the published peripherals: {err}");
+        }
+    }
+}
+
+/// Publishes the added and removed peripherals, updating their properties.
+///
+/// At start it unsets the properties of the peripherals removed while the runtime was not
+/// running.
+pub async fn watch(communication_channel: Sender<TelemetryMessage>, store_directory: String) {
+    // listen before enumerating, to not miss the peripherals added in between
+    let (events_tx, mut events_rx) = mpsc::channel(32);
+    let monitor = tokio::task::spawn_blocking(move || monitor_udev(events_tx));
+
+    let mut known: HashMap<String, Peripheral> = match enumerate_peripherals() {
+        Ok(peripherals) => peripherals
+            .into_iter()
+            .map(|peripheral| (peripheral.id.clone(), peripheral))
+            .collect(),
+        Err(err) => {
+            error!("couldn't enumerate the peripherals: {err}");
+            return;
+        }
+    };
+
+    let published = PublishedPeripherals::new(&store_directory);
+    for id in published.removed(&known).await {
+        debug!("peripheral {id} removed while not running");
+
+        let msg = TelemetryMessage {
+            path: "".to_string(),
+            payload: TelemetryPayload::Properties(PERIPHERALS_INTERFACE, unset_astarte(&id)),
+        };
+        let _ = communication_channel.send(msg).await;
+    }
+    published.save(&known).await;
+
+    while let Some(hotplug) = events_rx.recv().await {
+        let (action, peripheral, properties) = match hotplug {
+            Hotplug::Added(fields) => {
+                let peripheral = match Peripheral::from_fields(&fields) {
+                    Some(peripheral) => peripheral,
+                    None => continue,
+                };
+                // already enumerated if added while enumerating
+                if known.get(&peripheral.id) == Some(&peripheral) {
+                    continue;
+                }
+                known.insert(peripheral.id.clone(), peripheral.clone());
+                let properties = peripheral.to_astarte();
+
+                ("added", peripheral, properties)
+            }
+            Hotplug::Removed(fields) => {
+                // the attributes of a removed device can't be read anymore
+                let removed = Peripheral::from_fields(&fields)
+                    .and_then(|removed| known.remove(&removed.id).or(Some(removed)));
+                let peripheral = match removed {
+                    Some(peripheral) => peripheral,
+                    None => continue,
+                };
+                let properties = unset_astarte(&peripheral.id);
+
+                ("removed", peripheral, properties)
+            }
+        };
+
+        debug!("peripheral {action}: {peripheral:?}");
+
+        let messages = [
+            TelemetryMessage {
+                path: "".to_string(),
+                payload: TelemetryPayload::Peripheral(peripheral.to_event(action)),
+            },
+            TelemetryMessage {
+                path: "".to_string(),
+                payload: TelemetryPayload::Properties(PERIPHERALS_INTERFACE, properties),
+            },
+        ];
+
+        for msg in messages {
+            let _ = communication_channel.send(msg).await;
+        }
+
+        published.save(&known).await;
+    }
+
+    match monitor.await {
+        Ok(Err(err)) => error!("couldn't monitor the peripherals: {err}"),
+        Err(err) => error!("peripherals monitor failed: {err}"),
+        Ok(Ok(())) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use astarte_device_sdk::types::AstarteType;
+
+    use tempdir::TempDir;
+
+    use crate::telemetry::peripherals::{
+        peripheral_id, unset_astarte, Bus, DeviceFields, Peripheral, PublishedPeripherals,
+    };
+
+    fn fields(
+        subsystem: &str,
+        devtype: &str,
+        sysname: &str,
+        properties: &[(&str, &str)],
+        attributes: &[(&str, &str)],
+    ) -> DeviceFields {
+        let to_map = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+
+        DeviceFields {
+            subsystem: subsystem.to_string(),
+            devtype: devtype.to_string(),
+            sysname: sysname.to_string(),
+            properties: to_map(properties),
+            attributes: to_map(attributes),
+        }
+    }
+
+    #[test]
+    fn usb_peripheral_test() {
+        let device = fields(
+            "usb",
+            "usb_device",
+            "1-1.2",
+            &[("ID_VENDOR_FROM_DATABASE", "Future Technology Devices")],
+            &[
+                ("idVendor", "0403"),
+                ("idProduct", "6001"),
+                ("manufacturer", "FTDI"),
+                ("product", "FT232R USB UART"),
+                ("serial", "A50285BI"),
+            ],
+        );
+
+        assert_eq!(
+            Peripheral::from_fields(&device).unwrap(),
+            Peripheral {
+                id: "usb-1-1_2".to_string(),
+                bus: Bus::Usb,
+                vendor_id: "0403".to_string(),
+                product_id: "6001".to_string(),
+                vendor_name: "Future Technology Devices".to_string(),
+                product_name: "FT232R USB UART".to_string(),
+                serial: "A50285BI".to_string(),
+            }
+        );
+
+        // interfaces of the device are not peripherals
+        let interface = fields("usb", "usb_interface", "1-1.2:1.0", &[], &[]);
+        assert!(Peripheral::from_fields(&interface).is_none());
+    }
+
+    #[test]
+    fn pci_peripheral_test() {
+        let device = fields(
+            "pci",
+            "",
+            "0000:00:1f.2",
+            &[
+                ("PCI_ID", "8086:A352"),
+                ("ID_VENDOR_FROM_DATABASE", "Intel Corporation"),
+                (
+                    "ID_MODEL_FROM_DATABASE",
+                    "Cannon Lake PCH SATA AHCI Controller",
+                ),
+            ],
+            &[("vendor", "0x8086"), ("device", "0xa352")],
+        );
+
+        let peripheral = Peripheral::from_fields(&device).unwrap();
+        assert_eq!(peripheral.id, "pci-0000_00_1f_2");
+        assert_eq!(peripheral.vendor_id, "8086");
+        assert_eq!(peripheral.product_id, "a352");
+        assert_eq!(peripheral.vendor_name, "Intel Corporation");
+        assert!(peripheral.serial.is_empty());
+
+        // without the hwdb properties
+        let device = fields(
+            "pci",
+            "",
+            "0000:01:00.0",
+            &[],
+            &[("vendor", "0x10EC"), ("device", "0x8168")],
+        );
+        let peripheral = Peripheral::from_fields(&device).unwrap();
+        assert_eq!(peripheral.vendor_id, "10ec");
+        assert_eq!(peripheral.product_id, "8168");
+        assert!(peripheral.vendor_name.is_empty());
+    }
+
+    #[test]
+    fn peripheral_to_astarte_test() {
+        let device = fields(
+            "usb",
+            "usb_device",
+            "2-1",
+            &[],
+            &[("idVendor", "1d6b"), ("idProduct", "0003")],
+        );
+        let astarte = Peripheral::from_fields(&device).unwrap().to_astarte();
+
+        assert_eq!(astarte.len(), 6);
+        assert_eq!(
+            astarte.get("/usb-2-1/bus"),
+            Some(&AstarteType::String("usb".to_string()))
+        );
+        assert_eq!(
+            astarte.get("/usb-2-1/vendorId"),
+            Some(&AstarteType::String("1d6b".to_string()))
+        );
+
+        let unset = unset_astarte("usb-2-1");
+        assert_eq!(unset.len(), 6);
+        assert!(unset.values().all(|value| *value == AstarteType::Unset));
+        assert!(unset.contains_key("/usb-2-1/serial"));
+    }
+
+    #[test]
+    fn peripheral_id_test() {
+        assert_eq!(peripheral_id(Bus::Usb, "usb1"), "usb-usb1");
+        assert_eq!(peripheral_id(Bus::Pci, "0000:00:02.0"), "pci-0000_00_02_0");
+    }
+
+    #[tokio::test]
+    async fn published_peripherals_test() {
+        let store = TempDir::new("store").unwrap();
+        let published = PublishedPeripherals::new(store.path().to_str().unwrap());
+        let peripheral = |id: &str| Peripheral {
+            id: id.to_string(),
+            bus: Bus::Usb,
+            vendor_id: "0403".to_string(),
+            product_id: "6001".to_string(),
+            vendor_name: String::new(),
+            product_name: String::new(),
+            serial: String::new(),
+        };
+        let known = |ids: &[&str]| -> HashMap<String, Peripheral> {
+            ids.iter()
+                .map(|id| (id.to_string(), peripheral(id)))
+                .collect()
+        };
+
+        // nothing was published before the first start
+        assert!(published.removed(&known(&["usb-1-1"])).await.is_empty());
+
+        published.save(&known(&["usb-1-1", "usb-1-2"])).await;
+        // restarted after unplugging usb-1-2 and plugging usb-1-3
+        let published = PublishedPeripherals::new(store.path().to_str().unwrap());
+        assert_eq!(
+            published.removed(&known(&["usb-1-1", "usb-1-3"])).await,
+            ["usb-1-2"]
+        );
+    }
+}
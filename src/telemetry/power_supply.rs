@@ -0,0 +1,198 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Reads the power supplies exposed by the kernel in `/sys/class/power_supply`.
+
+use std::io;
+use std::path::Path;
+
+use nix::errno::Errno;
+
+pub const POWER_SUPPLY_PATH: &str = "/sys/class/power_supply";
+
+/// Attributes of a power supply class device, missing attributes are `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PowerSupply {
+    /// Name of the sysfs entry, e.g. `BAT0`.
+    pub name: String,
+    pub supply_type: Option<String>,
+    /// `System` for the supplies powering the device, `Device` for the ones of peripherals.
+    pub scope: Option<String>,
+    pub serial_number: Option<String>,
+    pub present: Option<bool>,
+    pub status: Option<String>,
+    pub health: Option<String>,
+    /// Level in percentage.
+    pub capacity: Option<f64>,
+    pub capacity_level: Option<String>,
+    /// Energy in µWh.
+    pub energy_now: Option<f64>,
+    pub energy_full: Option<f64>,
+    /// Charge in µAh.
+    pub charge_now: Option<f64>,
+    pub charge_full: Option<f64>,
+    /// Voltage in µV.
+    pub voltage_now: Option<i64>,
+    /// Current in µA.
+    pub current_now: Option<i64>,
+    pub cycle_count: Option<i64>,
+}
+
+impl PowerSupply {
+    pub fn is_system_battery(&self) -> bool {
+        self.supply_type.as_deref() == Some("Battery") && self.scope.as_deref() != Some("Device")
+    }
+}
+
+/// Reads all the power supplies in the given power_supply class directory, none if the directory
+/// doesn't exist.
+pub fn get_power_supplies(path: &Path) -> io::Result<Vec<PowerSupply>> {
+    let mut supplies = Vec::new();
+
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(supplies),
+        Err(err) => return Err(err),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        supplies.push(get_power_supply(&entry.path())?);
+    }
+
+    supplies.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(supplies)
+}
+
+fn get_power_supply(path: &Path) -> io::Result<PowerSupply> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    Ok(PowerSupply {
+        name,
+        supply_type: read_attribute(path, "type")?,
+        scope: read_attribute(path, "scope")?,
+        serial_number: read_attribute(path, "serial_number")?.filter(|s| !s.is_empty()),
+        present: read_parsed::<u8>(path, "present")?.map(|present| present != 0),
+        status: read_attribute(path, "status")?,
+        health: read_attribute(path, "health")?,
+        capacity: read_parsed(path, "capacity")?,
+        capacity_level: read_attribute(path, "capacity_level")?,
+        energy_now: read_parsed(path, "energy_now")?,
+        energy_full: read_parsed(path, "energy_full")?,
+        charge_now: read_parsed(path, "charge_now")?,
+        charge_full: read_parsed(path, "charge_full")?,
+        voltage_now: read_parsed(path, "voltage_now")?,
+        current_now: read_parsed(path, "current_now")?,
+        cycle_count: read_parsed(path, "cycle_count")?,
+    })
+}
+
+fn read_attribute(path: &Path, attribute: &str) -> io::Result<Option<String>> {
+    match std::fs::read_to_string(path.join(attribute)) {
+        Ok(value) => Ok(Some(value.trim().to_string())),
+        // some drivers return ENODATA or EINVAL for attributes they don't support at the moment
+        Err(err)
+            if err.kind() == io::ErrorKind::NotFound
+                || err.raw_os_error() == Some(Errno::ENODATA as i32)
+                || err.kind() == io::ErrorKind::InvalidInput =>
+        {
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn read_parsed<T: std::str::FromStr>(path: &Path, attribute: &str) -> io::Result<Option<T>> {
+    Ok(read_attribute(path, attribute)?.and_then(|value| value.parse().ok()))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::telemetry::power_supply::{get_power_supplies, PowerSupply};
+    use std::path::Path;
+    use tempdir::TempDir;
+
+    /// Adds a device with the given attributes to a power_supply class directory.
+    pub(crate) fn write_power_supply(dir: &Path, name: &str, attributes: &[(&str, &str)]) {
+        let device = dir.join(name);
+        std::fs::create_dir(&device).unwrap();
+
+        for (attribute, value) in attributes {
+            std::fs::write(device.join(attribute), format!("{value}\n")).unwrap();
+        }
+    }
+
+    #[test]
+    fn get_power_supplies_test() {
+        let dir = TempDir::new("power_supply").unwrap();
+        write_power_supply(
+            dir.path(),
+            "BAT0",
+            &[
+                ("type", "Battery"),
+                ("scope", "System"),
+                ("present", "1"),
+                ("status", "Discharging"),
+                ("health", "Good"),
+                ("capacity", "87"),
+                ("voltage_now", "12400000"),
+                ("current_now", "1250000"),
+                ("cycle_count", "112"),
+                ("serial_number", ""),
+            ],
+        );
+        write_power_supply(dir.path(), "AC", &[("type", "Mains"), ("online", "1")]);
+
+        let supplies = get_power_supplies(dir.path()).unwrap();
+
+        assert_eq!(supplies.len(), 2);
+        assert_eq!(supplies[0].name, "AC");
+        assert!(!supplies[0].is_system_battery());
+        assert_eq!(
+            supplies[1],
+            PowerSupply {
+                name: "BAT0".to_string(),
+                supply_type: Some("Battery".to_string()),
+                scope: Some("System".to_string()),
+                serial_number: None,
+                present: Some(true),
+                status: Some("Discharging".to_string()),
+                health: Some("Good".to_string()),
+                capacity: Some(87.0),
+                voltage_now: Some(12_400_000),
+                current_now: Some(1_250_000),
+                cycle_count: Some(112),
+                ..Default::default()
+            }
+        );
+        assert!(supplies[1].is_system_battery());
+    }
+
+    #[test]
+    fn get_power_supplies_missing_test() {
+        assert!(get_power_supplies(Path::new("/nonexistent/power_supply"))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,166 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Pressure Stall Information of the kernel, read from `/proc/pressure`.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use astarte_device_sdk::AstarteAggregate;
+
+use crate::error::DeviceManagerError;
+
+pub const PRESSURE_PATH: &str = "/proc/pressure";
+const RESOURCES: [&str; 3] = ["cpu", "memory", "io"];
+
+#[derive(Debug, Clone, Default, AstarteAggregate, PartialEq)]
+#[allow(non_snake_case)]
+pub struct PressureStall {
+    /// Percentage of time some tasks were stalled, averaged over 10, 60 and 300 seconds.
+    pub someAvg10: f64,
+    pub someAvg60: f64,
+    pub someAvg300: f64,
+    /// Total stall time in microseconds.
+    pub someTotal: i64,
+    /// Same as the `some` fields, for the time all non-idle tasks were stalled.
+    pub fullAvg10: f64,
+    pub fullAvg60: f64,
+    pub fullAvg300: f64,
+    pub fullTotal: i64,
+}
+
+/// get structured data for `io.edgehog.devicemanager.PressureStall` interface
+///
+/// The path is the name of the resource: `cpu`, `memory` or `io`.
+pub fn get_pressure_stall(
+    pressure_path: &Path,
+) -> Result<HashMap<String, PressureStall>, DeviceManagerError> {
+    let mut ret = HashMap::new();
+
+    for resource in RESOURCES {
+        let content = std::fs::read_to_string(pressure_path.join(resource))?;
+        let pressure = parse_pressure(&content).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid pressure file for {resource}"),
+            )
+        })?;
+
+        ret.insert(resource.to_string(), pressure);
+    }
+
+    Ok(ret)
+}
+
+/// Parses a pressure file, the `full` line is missing for the cpu on older kernels.
+fn parse_pressure(content: &str) -> Option<PressureStall> {
+    let mut pressure = PressureStall::default();
+    let mut has_some = false;
+
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        let kind = fields.next()?;
+
+        let mut avg10 = None;
+        let mut avg60 = None;
+        let mut avg300 = None;
+        let mut total = None;
+        for field in fields {
+            match field.split_once('=')? {
+                ("avg10", value) => avg10 = value.parse().ok(),
+                ("avg60", value) => avg60 = value.parse().ok(),
+                ("avg300", value) => avg300 = value.parse().ok(),
+                ("total", value) => total = value.parse().ok(),
+                _ => {}
+            }
+        }
+
+        match kind {
+            "some" => {
+                pressure.someAvg10 = avg10?;
+                pressure.someAvg60 = avg60?;
+                pressure.someAvg300 = avg300?;
+                pressure.someTotal = total?;
+                has_some = true;
+            }
+            "full" => {
+                pressure.fullAvg10 = avg10?;
+                pressure.fullAvg60 = avg60?;
+                pressure.fullAvg300 = avg300?;
+                pressure.fullTotal = total?;
+            }
+            _ => {}
+        }
+    }
+
+    has_some.then_some(pressure)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::telemetry::pressure::{get_pressure_stall, parse_pressure, PressureStall};
+    use tempdir::TempDir;
+
+    const MEMORY: &str = "\
+some avg10=1.53 avg60=0.87 avg300=0.21 total=5423311
+full avg10=0.40 avg60=0.22 avg300=0.05 total=1203456
+";
+
+    #[test]
+    fn parse_pressure_test() {
+        assert_eq!(
+            parse_pressure(MEMORY),
+            Some(PressureStall {
+                someAvg10: 1.53,
+                someAvg60: 0.87,
+                someAvg300: 0.21,
+                someTotal: 5_423_311,
+                fullAvg10: 0.4,
+                fullAvg60: 0.22,
+                fullAvg300: 0.05,
+                fullTotal: 1_203_456,
+            })
+        );
+
+        let cpu = parse_pressure("some avg10=2.00 avg60=1.00 avg300=0.50 total=100\n").unwrap();
+        assert_eq!(cpu.someAvg10, 2.0);
+        assert_eq!(cpu.fullTotal, 0);
+
+        assert!(parse_pressure("").is_none());
+        assert!(parse_pressure("some avg10=abc avg60=1.00 avg300=0.50 total=100\n").is_none());
+    }
+
+    #[test]
+    fn get_pressure_stall_test() {
+        let dir = TempDir::new("pressure").unwrap();
+        for resource in ["cpu", "memory", "io"] {
+            std::fs::write(dir.path().join(resource), MEMORY).unwrap();
+        }
+
+        let pressure = get_pressure_stall(dir.path()).unwrap();
+
+        assert_eq!(pressure.len(), 3);
+        assert_eq!(pressure["io"].someTotal, 5_423_311);
+
+        std::fs::remove_file(dir.path().join("io")).unwrap();
+        assert!(get_pressure_stall(dir.path()).is_err());
+    }
+}
@@ -0,0 +1,452 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Status of a list of watched processes, matched by name or command line.
+
+use std::collections::HashMap;
+
+use astarte_device_sdk::AstarteAggregate;
+use log::{debug, warn};
+use serde::Deserialize;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::Mutex;
+use tokio::time::{interval, Duration};
+
+use crate::error::DeviceManagerError;
+use crate::telemetry::{Sampler, TelemetryMessage, TelemetryPayload};
+
+const PROCESS_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A process to watch, by default it matches the processes whose executable name is `name`.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct WatchedProcess {
+    /// Name used as the interface path.
+    pub name: String,
+    /// Matches the processes whose executable name is equal to it.
+    #[serde(default)]
+    pub process_name: Option<String>,
+    /// Matches the processes whose command line contains it.
+    #[serde(default)]
+    pub cmdline: Option<String>,
+}
+
+impl WatchedProcess {
+    fn matches(&self, process: &ProcessSample) -> bool {
+        match (&self.process_name, &self.cmdline) {
+            (None, None) => comm_matches(&self.name, &process.comm),
+            (process_name, cmdline) => {
+                process_name
+                    .as_ref()
+                    .map_or(true, |name| comm_matches(name, &process.comm))
+                    && cmdline
+                        .as_ref()
+                        .map_or(true, |cmdline| process.cmdline.contains(cmdline.as_str()))
+            }
+        }
+    }
+}
+
+/// The kernel truncates the executable name to 15 characters.
+fn comm_matches(name: &str, comm: &str) -> bool {
+    name == comm || (comm.len() == 15 && name.starts_with(comm))
+}
+
+/// Configuration of the process watchlist telemetry source.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct ProcessWatchOptions {
+    #[serde(default)]
+    pub processes: Vec<WatchedProcess>,
+}
+
+#[derive(Debug, Clone, AstarteAggregate, PartialEq)]
+#[allow(non_snake_case)]
+pub struct ProcessStatus {
+    pub running: bool,
+    pub pidCount: i32,
+    /// Resident memory of all the matching processes.
+    pub rssBytes: i64,
+    /// CPU usage of all the matching processes since the last read, 100 is a full core.
+    pub cpuPercent: f64,
+    /// Unix time in milliseconds of the start of the oldest matching process, 0 if not running.
+    pub startTimeMillis: i64,
+}
+
+#[derive(Debug, Clone, AstarteAggregate, PartialEq)]
+#[allow(non_snake_case)]
+pub struct ProcessDisappearedEvent {
+    pub name: String,
+}
+
+/// A process read from `/proc`, times are in clock ticks since boot.
+#[derive(Debug, Clone, PartialEq)]
+struct ProcessSample {
+    pid: i32,
+    comm: String,
+    cmdline: String,
+    cpu_ticks: u64,
+    rss_bytes: u64,
+    start_ticks: u64,
+}
+
+fn read_processes() -> Result<Vec<ProcessSample>, DeviceManagerError> {
+    let page_size = procfs::page_size();
+
+    let mut processes = Vec::new();
+    for process in procfs::process::all_processes()? {
+        // the process could exit while reading it
+        let process = match process {
+            Ok(process) => process,
+            Err(_) => continue,
+        };
+        let stat = match process.stat() {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+
+        processes.push(ProcessSample {
+            pid: stat.pid,
+            comm: stat.comm,
+            cmdline: process.cmdline().unwrap_or_default().join(" "),
+            cpu_ticks: stat.utime + stat.stime,
+            rss_bytes: stat.rss * page_size,
+            start_ticks: stat.starttime,
+        });
+    }
+
+    Ok(processes)
+}
+
+/// CPU time of each process at the last read, to compute the usage between two reads.
+#[derive(Debug, Default)]
+struct CpuUsage {
+    /// Seconds since boot of the last read.
+    last_uptime: f64,
+    /// Start time and CPU time of each process, the start time detects reused pids.
+    last_ticks: HashMap<i32, (u64, u64)>,
+}
+
+impl CpuUsage {
+    /// Returns the CPU percentage of each process since the last update, or since its start.
+    fn update(
+        &mut self,
+        processes: &[ProcessSample],
+        uptime: f64,
+        ticks_per_second: f64,
+    ) -> HashMap<i32, f64> {
+        let mut usage = HashMap::new();
+        let mut last_ticks = HashMap::new();
+
+        for process in processes {
+            let (since, ticks) = match self.last_ticks.get(&process.pid) {
+                Some((start, ticks)) if *start == process.start_ticks => {
+                    (self.last_uptime, process.cpu_ticks.saturating_sub(*ticks))
+                }
+                _ => (
+                    process.start_ticks as f64 / ticks_per_second,
+                    process.cpu_ticks,
+                ),
+            };
+
+            let elapsed = uptime - since;
+            let percent = if elapsed > 0.0 {
+                ticks as f64 / ticks_per_second / elapsed * 100.0
+            } else {
+                0.0
+            };
+
+            usage.insert(process.pid, percent);
+            last_ticks.insert(process.pid, (process.start_ticks, process.cpu_ticks));
+        }
+
+        self.last_uptime = uptime;
+        self.last_ticks = last_ticks;
+
+        usage
+    }
+}
+
+/// Process watchlist telemetry source.
+#[derive(Debug, Default)]
+pub struct ProcessWatch {
+    options: ProcessWatchOptions,
+    /// The usage is measured since the last sample of the same sampler.
+    cpu_usage: Mutex<HashMap<Sampler, CpuUsage>>,
+}
+
+impl ProcessWatch {
+    pub fn new(options: ProcessWatchOptions) -> Self {
+        ProcessWatch {
+            options,
+            cpu_usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// get structured data for `io.edgehog.devicemanager.ProcessStatus` interface
+    pub async fn get_process_status(
+        &self,
+        sampler: Sampler,
+    ) -> Result<HashMap<String, ProcessStatus>, DeviceManagerError> {
+        let processes = read_processes()?;
+        let uptime = procfs::Uptime::new()?.uptime;
+        let ticks_per_second = procfs::ticks_per_second() as f64;
+        let boot_time_millis = procfs::boot_time_secs()? as f64 * 1000.0;
+
+        let cpu_usage = self
+            .cpu_usage
+            .lock()
+            .await
+            .entry(sampler)
+            .or_default()
+            .update(&processes, uptime, ticks_per_second);
+
+        let status = self
+            .options
+            .processes
+            .iter()
+            .map(|watched| {
+                let matching: Vec<&ProcessSample> = processes
+                    .iter()
+                    .filter(|process| watched.matches(process))
+                    .collect();
+
+                let start_time_millis = matching
+                    .iter()
+                    .map(|process| process.start_ticks)
+                    .min()
+                    .map_or(0, |start_ticks| {
+                        (boot_time_millis + start_ticks as f64 / ticks_per_second * 1000.0) as i64
+                    });
+
+                let status = ProcessStatus {
+                    running: !matching.is_empty(),
+                    pidCount: matching.len() as i32,
+                    rssBytes: matching
+                        .iter()
+                        .map(|process| process.rss_bytes)
+                        .sum::<u64>() as i64,
+                    cpuPercent: matching
+                        .iter()
+                        .filter_map(|process| cpu_usage.get(&process.pid))
+                        .sum(),
+                    startTimeMillis: start_time_millis,
+                };
+
+                (watched.name.clone(), status)
+            })
+            .collect();
+
+        Ok(status)
+    }
+
+    /// Polls the processes and publishes an event when a watched process stops running.
+    pub async fn watch(
+        &self,
+        communication_channel: &Sender<TelemetryMessage>,
+    ) -> Result<(), DeviceManagerError> {
+        if self.options.processes.is_empty() {
+            return Ok(());
+        }
+
+        let mut liveness = Liveness::default();
+        let mut interval = interval(PROCESS_POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let processes = match read_processes() {
+                Ok(processes) => processes,
+                Err(err) => {
+                    warn!("couldn't read the processes: {err}");
+                    continue;
+                }
+            };
+            let running = self.options.processes.iter().map(|watched| {
+                let running = processes.iter().any(|process| watched.matches(process));
+
+                (watched.name.as_str(), running)
+            });
+
+            for name in liveness.disappeared(running) {
+                debug!("watched process {name} disappeared");
+
+                let _ = communication_channel
+                    .send(TelemetryMessage {
+                        path: "".to_string(),
+                        payload: TelemetryPayload::ProcessDisappeared(ProcessDisappearedEvent {
+                            name,
+                        }),
+                    })
+                    .await;
+            }
+        }
+    }
+}
+
+/// Last known running state of the watched processes.
+#[derive(Debug, Default)]
+struct Liveness {
+    running: HashMap<String, bool>,
+}
+
+impl Liveness {
+    /// Returns the processes that were running at the last poll and aren't running anymore.
+    fn disappeared<'a>(&mut self, running: impl Iterator<Item = (&'a str, bool)>) -> Vec<String> {
+        let mut disappeared = Vec::new();
+
+        for (name, running) in running {
+            let was_running = self.running.insert(name.to_string(), running);
+
+            if was_running == Some(true) && !running {
+                disappeared.push(name.to_string());
+            }
+        }
+
+        disappeared
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::telemetry::process_watch::{
+        CpuUsage, Liveness, ProcessSample, ProcessWatch, ProcessWatchOptions, WatchedProcess,
+    };
+    use crate::telemetry::Sampler;
+
+    fn process(pid: i32, comm: &str, cmdline: &str, cpu_ticks: u64) -> ProcessSample {
+        ProcessSample {
+            pid,
+            comm: comm.to_string(),
+            cmdline: cmdline.to_string(),
+            cpu_ticks,
+            rss_bytes: 4096,
+            start_ticks: 1000,
+        }
+    }
+
+    fn watched(name: &str, process_name: Option<&str>, cmdline: Option<&str>) -> WatchedProcess {
+        WatchedProcess {
+            name: name.to_string(),
+            process_name: process_name.map(str::to_string),
+            cmdline: cmdline.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn watched_process_matches_test() {
+        let nginx = process(1, "nginx", "nginx: worker process", 0);
+        let app = process(2, "python3", "/usr/bin/python3 /opt/app/main.py", 0);
+        let long = process(3, "very-long-daemo", "/usr/bin/very-long-daemon-name", 0);
+
+        assert!(watched("nginx", None, None).matches(&nginx));
+        assert!(!watched("nginx", None, None).matches(&app));
+        assert!(watched("app", None, Some("/opt/app/main.py")).matches(&app));
+        assert!(watched("app", Some("python3"), Some("main.py")).matches(&app));
+        assert!(!watched("app", Some("python2"), Some("main.py")).matches(&app));
+        assert!(watched("very-long-daemon-name", None, None).matches(&long));
+    }
+
+    #[test]
+    fn cpu_usage_test() {
+        let mut cpu_usage = CpuUsage::default();
+
+        // 5 seconds of CPU since the start at 10 seconds from boot
+        let usage = cpu_usage.update(&[process(1, "a", "", 500)], 20.0, 100.0);
+        assert_eq!(usage[&1], 50.0);
+
+        let usage = cpu_usage.update(&[process(1, "a", "", 700)], 30.0, 100.0);
+        assert_eq!(usage[&1], 20.0);
+
+        // reused pid
+        let mut reused = process(1, "b", "", 100);
+        reused.start_ticks = 2500;
+        let usage = cpu_usage.update(&[reused], 35.0, 100.0);
+        assert_eq!(usage[&1], 10.0);
+    }
+
+    #[test]
+    fn disappeared_test() {
+        let mut liveness = Liveness::default();
+
+        assert!(liveness
+            .disappeared([("nginx", true), ("app", false)].into_iter())
+            .is_empty());
+        assert_eq!(
+            liveness.disappeared([("nginx", false), ("app", false)].into_iter()),
+            ["nginx"]
+        );
+        assert!(liveness
+            .disappeared([("nginx", false), ("app", true)].into_iter())
+            .is_empty());
+    }
+
+    #[test]
+    fn process_watch_options_test() {
+        let options: ProcessWatchOptions = toml::from_str(
+            r#"
+            [[processes]]
+            name = "nginx"
+
+            [[processes]]
+            name = "app"
+            cmdline = "/opt/app/main.py"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            options.processes,
+            [
+                watched("nginx", None, None),
+                watched("app", None, Some("/opt/app/main.py"))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_process_status_test() {
+        let comm = procfs::process::Process::myself()
+            .unwrap()
+            .stat()
+            .unwrap()
+            .comm;
+        let process_watch = ProcessWatch::new(ProcessWatchOptions {
+            processes: vec![
+                watched("self", Some(comm.as_str()), None),
+                watched("missing", Some("edgehog-missing"), None),
+            ],
+        });
+
+        let status = process_watch
+            .get_process_status(Sampler::Telemetry)
+            .await
+            .unwrap();
+
+        let own = &status["self"];
+        assert!(own.running);
+        assert!(own.pidCount > 0);
+        assert!(own.rssBytes > 0);
+        assert!(own.startTimeMillis > 0);
+
+        let missing = &status["missing"];
+        assert!(!missing.running);
+        assert_eq!(missing.pidCount, 0);
+        assert_eq!(missing.startTimeMillis, 0);
+    }
+}
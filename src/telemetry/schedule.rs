@@ -0,0 +1,266 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Ticks of the periodic telemetry tasks, spread with random offsets or aligned to the wall clock.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::Rng;
+use tokio::time::{sleep, sleep_until, Duration, Instant};
+
+use crate::telemetry::cron::CronSchedule;
+
+/// Schedule of a telemetry interface, by default it ticks every period from the start.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Schedule {
+    /// Maximum random delay of the first tick.
+    pub initial_offset: Duration,
+    /// Maximum random delay of each tick from its nominal time.
+    pub jitter: Duration,
+    /// Tick on the multiples of the period since the Unix epoch, e.g. every 5 minutes on the :00.
+    pub align: bool,
+}
+
+impl Schedule {
+    pub fn new(initial_offset: Option<u64>, jitter: Option<u64>, align: Option<bool>) -> Self {
+        Schedule {
+            initial_offset: Duration::from_secs(initial_offset.unwrap_or(0)),
+            jitter: Duration::from_secs(jitter.unwrap_or(0)),
+            align: align.unwrap_or(false),
+        }
+    }
+}
+
+/// Periodic ticks following a [`Schedule`], or the fire times of a cron expression.
+///
+/// Waiting for a periodic tick is cancel safe: the deadline of a cancelled tick is kept for the
+/// next call.
+#[derive(Debug)]
+pub struct Ticker {
+    period: Duration,
+    schedule: Schedule,
+    cron: Option<CronSchedule>,
+    /// Nominal time of the next tick, before the jitter and the alignment.
+    next: Instant,
+    deadline: Option<Instant>,
+}
+
+impl Ticker {
+    pub fn new(period: Duration, schedule: Schedule) -> Self {
+        Ticker {
+            period,
+            schedule,
+            next: Instant::now() + random_delay(schedule.initial_offset),
+            deadline: None,
+            cron: None,
+        }
+    }
+
+    /// Ticks on the fire times of the cron expression, delayed by the jitter of the schedule.
+    pub fn with_cron(cron: CronSchedule, schedule: Schedule) -> Self {
+        Ticker {
+            cron: Some(cron),
+            ..Ticker::new(Duration::ZERO, schedule)
+        }
+    }
+
+    pub async fn tick(&mut self) {
+        if let Some(cron) = &self.cron {
+            cron.wait_next_fire().await;
+            sleep(random_delay(self.schedule.jitter)).await;
+
+            return;
+        }
+
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => {
+                let deadline = self.next_deadline();
+                self.deadline = Some(deadline);
+
+                deadline
+            }
+        };
+
+        sleep_until(deadline).await;
+
+        self.deadline = None;
+    }
+
+    fn next_deadline(&mut self) -> Instant {
+        let now = Instant::now();
+
+        // skip the ticks missed while the task was late, e.g. after a suspend
+        if self.next + self.period < now {
+            self.next = now;
+        }
+
+        let nominal = if self.schedule.align {
+            let wall_clock = SystemTime::now() + self.next.saturating_duration_since(now);
+            let nominal = self.next.max(now) + delay_to_boundary(wall_clock, self.period);
+
+            // half a period later, so that a small clock drift doesn't skip the next boundary
+            self.next = nominal + self.period / 2;
+
+            nominal
+        } else {
+            let nominal = self.next;
+            self.next += self.period;
+
+            nominal
+        };
+
+        nominal + random_delay(self.schedule.jitter.min(self.period))
+    }
+}
+
+/// Returns the time from the wall clock time to the next multiple of the period since the epoch.
+fn delay_to_boundary(wall_clock: SystemTime, period: Duration) -> Duration {
+    let period_millis = period.as_millis();
+    if period_millis == 0 {
+        return Duration::ZERO;
+    }
+
+    let since_epoch = wall_clock
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+
+    let remainder = since_epoch % period_millis;
+    if remainder == 0 {
+        return Duration::ZERO;
+    }
+
+    Duration::from_millis(u64::try_from(period_millis - remainder).unwrap_or(u64::MAX))
+}
+
+/// Returns a random delay up to the maximum, with millisecond resolution.
+fn random_delay(max: Duration) -> Duration {
+    let max_millis = u64::try_from(max.as_millis()).unwrap_or(u64::MAX);
+    if max_millis == 0 {
+        return Duration::ZERO;
+    }
+
+    Duration::from_millis(rand::thread_rng().gen_range(0..=max_millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration as StdDuration, UNIX_EPOCH};
+
+    use tokio::time::{Duration, Instant};
+
+    use crate::telemetry::schedule::{delay_to_boundary, random_delay, Schedule, Ticker};
+
+    #[test]
+    fn schedule_test() {
+        assert_eq!(Schedule::new(None, None, None), Schedule::default());
+        assert_eq!(
+            Schedule::new(Some(60), Some(5), Some(true)),
+            Schedule {
+                initial_offset: Duration::from_secs(60),
+                jitter: Duration::from_secs(5),
+                align: true,
+            }
+        );
+    }
+
+    #[test]
+    fn delay_to_boundary_test() {
+        let period = Duration::from_secs(300);
+
+        let wall_clock = UNIX_EPOCH + StdDuration::from_secs(1_700_000_000);
+        assert_eq!(
+            delay_to_boundary(wall_clock, period),
+            Duration::from_secs(100)
+        );
+
+        let wall_clock = UNIX_EPOCH + StdDuration::from_secs(1_700_000_100);
+        assert_eq!(
+            delay_to_boundary(wall_clock, Duration::from_secs(100)),
+            Duration::ZERO
+        );
+
+        let wall_clock = UNIX_EPOCH + StdDuration::from_millis(1_700_000_099_500);
+        assert_eq!(
+            delay_to_boundary(wall_clock, Duration::from_secs(100)),
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn random_delay_test() {
+        assert_eq!(random_delay(Duration::ZERO), Duration::ZERO);
+
+        for _ in 0..100 {
+            assert!(random_delay(Duration::from_secs(2)) <= Duration::from_secs(2));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ticker_default_test() {
+        let start = Instant::now();
+        let mut ticker = Ticker::new(Duration::from_secs(10), Schedule::default());
+
+        ticker.tick().await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        ticker.tick().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+
+        ticker.tick().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(20));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ticker_jitter_test() {
+        let start = Instant::now();
+        let schedule = Schedule::new(Some(30), Some(3), None);
+        let mut ticker = Ticker::new(Duration::from_secs(10), schedule);
+
+        ticker.tick().await;
+        let first = start.elapsed();
+        assert!(first <= Duration::from_secs(33));
+
+        for _ in 0..10 {
+            let previous = Instant::now();
+            ticker.tick().await;
+
+            // the jitter doesn't accumulate between the ticks
+            let elapsed = previous.elapsed();
+            assert!(elapsed >= Duration::from_secs(7), "{elapsed:?}");
+            assert!(elapsed <= Duration::from_secs(13), "{elapsed:?}");
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ticker_cancel_test() {
+        let start = Instant::now();
+        let mut ticker = Ticker::new(Duration::from_secs(10), Schedule::default());
+        ticker.tick().await;
+
+        // a cancelled tick keeps its deadline
+        let cancelled = tokio::time::timeout(Duration::from_secs(4), ticker.tick()).await;
+        assert!(cancelled.is_err());
+
+        ticker.tick().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,222 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Decides if the data collected by a periodic telemetry task has to be sent.
+
+use std::collections::HashMap;
+
+use astarte_device_sdk::types::AstarteType;
+use serde::{Deserialize, Serialize};
+use tokio::time::{Duration, Instant};
+
+/// Minimum variation of a numeric field for it to be considered changed.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Deadband {
+    /// Absolute difference from the last sent value.
+    Absolute(f64),
+    /// Difference in percentage of the last sent value.
+    Percent(f64),
+}
+
+impl Deadband {
+    fn is_exceeded(&self, last: f64, value: f64) -> bool {
+        let delta = (value - last).abs();
+
+        match self {
+            Deadband::Absolute(threshold) => delta > *threshold,
+            Deadband::Percent(percent) => delta > last.abs() * percent / 100.0,
+        }
+    }
+}
+
+/// Send policy of a telemetry interface, by default everything is sent.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SendPolicy {
+    /// Send a path only if its data changed from the last sent one.
+    pub on_change: bool,
+    pub deadband: Option<Deadband>,
+    /// Maximum time without sending a path when sending only on change.
+    pub max_silence: Option<Duration>,
+}
+
+impl SendPolicy {
+    pub fn new(
+        on_change: Option<bool>,
+        deadband: Option<Deadband>,
+        max_silence: Option<u64>,
+    ) -> Self {
+        SendPolicy {
+            on_change: on_change.unwrap_or(false) || deadband.is_some(),
+            deadband,
+            max_silence: max_silence.map(Duration::from_secs),
+        }
+    }
+}
+
+/// Keeps the last sent value of each path to apply a [`SendPolicy`].
+#[derive(Debug, Default)]
+pub struct ChangeFilter {
+    policy: SendPolicy,
+    last_sent: HashMap<String, (HashMap<String, AstarteType>, Instant)>,
+}
+
+impl ChangeFilter {
+    pub fn new(policy: SendPolicy) -> Self {
+        ChangeFilter {
+            policy,
+            last_sent: HashMap::new(),
+        }
+    }
+
+    /// Returns true if the values have to be sent on the path, remembering them as sent.
+    pub fn should_send(
+        &mut self,
+        path: &str,
+        values: HashMap<String, AstarteType>,
+        now: Instant,
+    ) -> bool {
+        if !self.policy.on_change {
+            return true;
+        }
+
+        let send = match self.last_sent.get(path) {
+            Some((last_values, last_time)) => {
+                let silence_exceeded = self.policy.max_silence.map_or(false, |max_silence| {
+                    now.duration_since(*last_time) >= max_silence
+                });
+
+                silence_exceeded || self.is_changed(last_values, &values)
+            }
+            None => true,
+        };
+
+        if send {
+            self.last_sent.insert(path.to_string(), (values, now));
+        }
+
+        send
+    }
+
+    fn is_changed(
+        &self,
+        last_values: &HashMap<String, AstarteType>,
+        values: &HashMap<String, AstarteType>,
+    ) -> bool {
+        if last_values.len() != values.len() {
+            return true;
+        }
+
+        values
+            .iter()
+            .any(|(key, value)| match last_values.get(key) {
+                Some(last) => match (self.policy.deadband, as_f64(last), as_f64(value)) {
+                    (Some(deadband), Some(last), Some(value)) => deadband.is_exceeded(last, value),
+                    _ => last != value,
+                },
+                None => true,
+            })
+    }
+}
+
+pub(crate) fn as_f64(value: &AstarteType) -> Option<f64> {
+    match value {
+        AstarteType::Double(value) => Some(*value),
+        AstarteType::Integer(value) => Some(f64::from(*value)),
+        AstarteType::LongInteger(value) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::telemetry::send_policy::{ChangeFilter, Deadband, SendPolicy};
+    use astarte_device_sdk::types::AstarteType;
+    use std::collections::HashMap;
+    use tokio::time::{Duration, Instant};
+
+    fn values(free_bytes: i64, name: &str) -> HashMap<String, AstarteType> {
+        HashMap::from([
+            (
+                "freeBytes".to_string(),
+                AstarteType::LongInteger(free_bytes),
+            ),
+            ("name".to_string(), AstarteType::String(name.to_string())),
+        ])
+    }
+
+    #[test]
+    fn send_always_test() {
+        let mut filter = ChangeFilter::default();
+        let now = Instant::now();
+
+        assert!(filter.should_send("/sda", values(100, "a"), now));
+        assert!(filter.should_send("/sda", values(100, "a"), now));
+    }
+
+    #[test]
+    fn send_on_change_test() {
+        let mut filter = ChangeFilter::new(SendPolicy::new(Some(true), None, None));
+        let now = Instant::now();
+
+        assert!(filter.should_send("/sda", values(100, "a"), now));
+        assert!(!filter.should_send("/sda", values(100, "a"), now));
+        assert!(filter.should_send("/sdb", values(100, "a"), now));
+        assert!(filter.should_send("/sda", values(101, "a"), now));
+        assert!(filter.should_send("/sda", values(101, "b"), now));
+    }
+
+    #[test]
+    fn send_absolute_deadband_test() {
+        let policy = SendPolicy::new(None, Some(Deadband::Absolute(10.0)), None);
+        assert!(policy.on_change);
+
+        let mut filter = ChangeFilter::new(policy);
+        let now = Instant::now();
+
+        assert!(filter.should_send("/sda", values(100, "a"), now));
+        assert!(!filter.should_send("/sda", values(110, "a"), now));
+        assert!(!filter.should_send("/sda", values(90, "a"), now));
+        assert!(filter.should_send("/sda", values(111, "a"), now));
+        assert!(filter.should_send("/sda", values(111, "b"), now));
+    }
+
+    #[test]
+    fn send_percent_deadband_test() {
+        let mut filter =
+            ChangeFilter::new(SendPolicy::new(None, Some(Deadband::Percent(5.0)), None));
+        let now = Instant::now();
+
+        assert!(filter.should_send("/sda", values(1000, "a"), now));
+        assert!(!filter.should_send("/sda", values(1049, "a"), now));
+        assert!(filter.should_send("/sda", values(949, "a"), now));
+    }
+
+    #[test]
+    fn send_max_silence_test() {
+        let mut filter = ChangeFilter::new(SendPolicy::new(Some(true), None, Some(60)));
+        let now = Instant::now();
+
+        assert!(filter.should_send("/sda", values(100, "a"), now));
+        assert!(!filter.should_send("/sda", values(100, "a"), now + Duration::from_secs(59)));
+        assert!(filter.should_send("/sda", values(100, "a"), now + Duration::from_secs(60)));
+        assert!(!filter.should_send("/sda", values(100, "a"), now + Duration::from_secs(61)));
+    }
+}
@@ -0,0 +1,488 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Wear and write counters of the block devices, for `io.edgehog.devicemanager.StorageHealth`.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use astarte_device_sdk::AstarteAggregate;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+use crate::error::DeviceManagerError;
+use crate::repository::file_state_repository::FileStateRepository;
+use crate::repository::StateRepository;
+
+const SYS_BLOCK_PATH: &str = "/sys/block";
+const WRITE_COUNTERS_PATH: &str = "storage_health.json";
+// sectors in /sys/block/*/stat are always 512 bytes
+const SECTOR_SIZE: u64 = 512;
+// limits the writes on the monitored flash, the counters are also saved when the runtime is
+// terminated
+const SAVE_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, AstarteAggregate, PartialEq)]
+#[allow(non_snake_case)]
+pub struct StorageHealth {
+    /// Estimated percentage of the device life used by type A and type B memory, upper bound of
+    /// the range reported by the device. Values over 100 mean the estimated life is exceeded, -1
+    /// that it's unknown.
+    pub lifeUsedPercentA: i32,
+    pub lifeUsedPercentB: i32,
+    /// "Pre end of life status of the reserved blocks, any of: Normal, Warning, Urgent, Unknown"
+    pub preEolStatus: String,
+    pub bytesWrittenBoot: i64,
+    /// Bytes written since the first sample, across reboots.
+    pub bytesWrittenTotal: i64,
+}
+
+/// Wear indicators of a block device.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Wear {
+    pub life_used_percent_a: Option<i32>,
+    pub life_used_percent_b: Option<i32>,
+    pub pre_eol_status: Option<String>,
+}
+
+/// Reads the wear indicators of a kind of block device, e.g. eMMC or SMART capable disks.
+pub trait WearSource: Debug + Send + Sync {
+    /// Returns `None` if the device, given as its `/sys/block` entry, is not supported.
+    fn read_wear(&self, device: &Path) -> io::Result<Option<Wear>>;
+}
+
+/// eMMC and SD wear reported by the kernel MMC driver.
+#[derive(Debug)]
+pub struct MmcWearSource;
+
+impl WearSource for MmcWearSource {
+    fn read_wear(&self, device: &Path) -> io::Result<Option<Wear>> {
+        let life_time = match read_optional(&device.join("device/life_time"))? {
+            Some(life_time) => life_time,
+            None => return Ok(None),
+        };
+
+        let mut estimates = life_time.split_whitespace().map(life_used_percent);
+        let pre_eol_status = read_optional(&device.join("device/pre_eol_info"))?.map(|pre_eol| {
+            match parse_hex(&pre_eol) {
+                Some(1) => "Normal",
+                Some(2) => "Warning",
+                Some(3) => "Urgent",
+                _ => "Unknown",
+            }
+            .to_string()
+        });
+
+        Ok(Some(Wear {
+            life_used_percent_a: estimates.next().flatten(),
+            life_used_percent_b: estimates.next().flatten(),
+            pre_eol_status,
+        }))
+    }
+}
+
+/// Converts a JEDEC device life time estimation, `0x01` is 0-10% used and `0x0B` exceeded.
+fn life_used_percent(estimate: &str) -> Option<i32> {
+    match parse_hex(estimate)? {
+        estimate @ 1..=11 => Some(i32::from(estimate) * 10),
+        _ => None,
+    }
+}
+
+fn parse_hex(value: &str) -> Option<u8> {
+    u8::from_str_radix(value.trim().trim_start_matches("0x"), 16).ok()
+}
+
+fn read_optional(path: &Path) -> io::Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Bytes written by a device in the current boot and the previous ones.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+struct WriteCounter {
+    boot_id: String,
+    boot_bytes: u64,
+    previous_boots_bytes: u64,
+}
+
+impl WriteCounter {
+    /// Updates the counter with the bytes written since boot, returning the total.
+    fn update(&mut self, boot_id: &str, boot_bytes: u64) -> u64 {
+        // the kernel counter restarts on reboot or when the device is added again
+        if self.boot_id != boot_id || boot_bytes < self.boot_bytes {
+            self.previous_boots_bytes += self.boot_bytes;
+            self.boot_id = boot_id.to_string();
+        }
+
+        self.boot_bytes = boot_bytes;
+
+        self.previous_boots_bytes + boot_bytes
+    }
+}
+
+#[derive(Debug, Default)]
+struct WriteCounters {
+    loaded: bool,
+    devices: HashMap<String, WriteCounter>,
+    last_saved: Option<Instant>,
+}
+
+/// Block devices health telemetry source.
+#[derive(Debug)]
+pub struct BlockDevices {
+    sys_block: PathBuf,
+    /// Directory where the write counters are saved, if any.
+    store_directory: Option<String>,
+    /// Sources of wear indicators, the first source supporting a device is used.
+    sources: Vec<Box<dyn WearSource>>,
+    counters: Mutex<WriteCounters>,
+}
+
+impl Default for BlockDevices {
+    fn default() -> Self {
+        BlockDevices {
+            sys_block: PathBuf::from(SYS_BLOCK_PATH),
+            store_directory: None,
+            sources: vec![Box::new(MmcWearSource)],
+            counters: Mutex::new(WriteCounters::default()),
+        }
+    }
+}
+
+impl BlockDevices {
+    pub fn new(store_directory: String) -> Self {
+        BlockDevices {
+            store_directory: Some(store_directory),
+            ..Default::default()
+        }
+    }
+
+    /// get structured data for `io.edgehog.devicemanager.StorageHealth` interface
+    pub async fn get_storage_health(
+        &self,
+    ) -> Result<HashMap<String, StorageHealth>, DeviceManagerError> {
+        let boot_id = procfs::sys::kernel::random::boot_id()?;
+
+        self.sample(&boot_id, false).await
+    }
+
+    /// Saves the write counters updated to the current writes, to not lose the ones since the
+    /// last periodic save when the runtime is terminated.
+    pub async fn save(&self) -> Result<(), DeviceManagerError> {
+        let boot_id = procfs::sys::kernel::random::boot_id()?;
+
+        self.flush(&boot_id).await
+    }
+
+    async fn flush(&self, boot_id: &str) -> Result<(), DeviceManagerError> {
+        // the counters are not in use if they were never sampled
+        if !self.counters.lock().await.loaded {
+            return Ok(());
+        }
+
+        self.sample(boot_id, true).await.map(drop)
+    }
+
+    async fn sample(
+        &self,
+        boot_id: &str,
+        force_save: bool,
+    ) -> Result<HashMap<String, StorageHealth>, DeviceManagerError> {
+        let mut counters = self.counters.lock().await;
+        if !counters.loaded {
+            counters.devices = self.load_counters().await;
+            counters.loaded = true;
+        }
+
+        let mut rebooted = false;
+        let mut ret = HashMap::new();
+        for (name, device) in list_block_devices(&self.sys_block)? {
+            let boot_bytes = read_written_bytes(&device)?;
+            let wear = self.read_wear(&device)?.unwrap_or_default();
+
+            let counter = counters.devices.entry(name.clone()).or_default();
+            rebooted |= counter.boot_id != boot_id;
+            let total_bytes = counter.update(boot_id, boot_bytes);
+
+            ret.insert(
+                name,
+                StorageHealth {
+                    lifeUsedPercentA: wear.life_used_percent_a.unwrap_or(-1),
+                    lifeUsedPercentB: wear.life_used_percent_b.unwrap_or(-1),
+                    preEolStatus: wear.pre_eol_status.unwrap_or_else(|| "Unknown".to_string()),
+                    bytesWrittenBoot: boot_bytes as i64,
+                    bytesWrittenTotal: total_bytes as i64,
+                },
+            );
+        }
+
+        let save_due = counters
+            .last_saved
+            .map_or(true, |last_saved| last_saved.elapsed() >= SAVE_INTERVAL);
+        if rebooted || save_due || force_save {
+            self.save_counters(&counters.devices).await;
+            counters.last_saved = Some(Instant::now());
+        }
+
+        Ok(ret)
+    }
+
+    fn read_wear(&self, device: &Path) -> io::Result<Option<Wear>> {
+        for source in &self.sources {
+            if let Some(wear) = source.read_wear(device)? {
+                return Ok(Some(wear));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn repository(&self) -> Option<FileStateRepository> {
+        self.store_directory.as_ref().map(|store_directory| {
+            FileStateRepository::new(store_directory.clone(), WRITE_COUNTERS_PATH.to_string())
+        })
+    }
+
+    async fn load_counters(&self) -> HashMap<String, WriteCounter> {
+        let repository = match self.repository() {
+            Some(repository) => repository,
+            None => return HashMap::new(),
+        };
+
+        if !StateRepository::<HashMap<String, WriteCounter>>::exists(&repository).await {
+            debug!("no saved write counters");
+
+            return HashMap::new();
+        }
+
+        repository.read().await.unwrap_or_else(|err| {
+            warn!("couldn't read the write counters, restarting them: {err}");
+
+            HashMap::new()
+        })
+    }
+
+    async fn save_counters(&self, devices: &HashMap<String, WriteCounter>) {
+        if let Some(repository) = self.repository() {
+            if let Err(err) = repository.write(devices).await {
+                warn!("couldn't save the write counters: {err}");
+            }
+        }
+    }
+}
+
+/// Lists the block devices backed by hardware, skipping virtual devices like loop and zram and
+/// the eMMC boot and RPMB partitions.
+fn list_block_devices(sys_block: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut devices = Vec::new();
+
+    for entry in std::fs::read_dir(sys_block)? {
+        let path = entry?.path();
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => continue,
+        };
+
+        let is_mmc_partition =
+            name.starts_with("mmcblk") && (name.contains("boot") || name.contains("rpmb"));
+
+        if path.join("device").exists() && !is_mmc_partition {
+            devices.push((name, path));
+        }
+    }
+
+    devices.sort();
+
+    Ok(devices)
+}
+
+fn read_written_bytes(device: &Path) -> io::Result<u64> {
+    let stat = std::fs::read_to_string(device.join("stat"))?;
+
+    // the 7th field is the number of sectors written
+    stat.split_whitespace()
+        .nth(6)
+        .and_then(|sectors| sectors.parse::<u64>().ok())
+        .map(|sectors| sectors * SECTOR_SIZE)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid stat file for {}", device.display()),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::telemetry::storage_health::{
+        BlockDevices, MmcWearSource, StorageHealth, Wear, WearSource, WriteCounter,
+    };
+    use std::path::Path;
+    use tempdir::TempDir;
+
+    fn write_device(
+        sys_block: &Path,
+        name: &str,
+        sectors_written: u64,
+        attributes: &[(&str, &str)],
+    ) {
+        let device = sys_block.join(name);
+        std::fs::create_dir_all(device.join("device")).unwrap();
+        std::fs::write(
+            device.join("stat"),
+            format!("    1200 0 84000 500 300 0 {sectors_written} 900 0 1000 1400 0 0 0 0\n"),
+        )
+        .unwrap();
+
+        for (attribute, value) in attributes {
+            std::fs::write(device.join("device").join(attribute), format!("{value}\n")).unwrap();
+        }
+    }
+
+    fn block_devices(sys_block: &Path, store_directory: &Path) -> BlockDevices {
+        BlockDevices {
+            sys_block: sys_block.to_path_buf(),
+            ..BlockDevices::new(store_directory.to_str().unwrap().to_string())
+        }
+    }
+
+    #[test]
+    fn mmc_wear_test() {
+        let dir = TempDir::new("sys_block").unwrap();
+        write_device(
+            dir.path(),
+            "mmcblk0",
+            0,
+            &[("life_time", "0x02 0x0b"), ("pre_eol_info", "0x02")],
+        );
+        write_device(dir.path(), "sda", 0, &[]);
+
+        let wear = MmcWearSource
+            .read_wear(&dir.path().join("mmcblk0"))
+            .unwrap();
+        assert_eq!(
+            wear,
+            Some(Wear {
+                life_used_percent_a: Some(20),
+                life_used_percent_b: Some(110),
+                pre_eol_status: Some("Warning".to_string()),
+            })
+        );
+
+        assert!(MmcWearSource
+            .read_wear(&dir.path().join("sda"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn write_counter_test() {
+        let mut counter = WriteCounter::default();
+
+        assert_eq!(counter.update("boot-1", 100), 100);
+        assert_eq!(counter.update("boot-1", 150), 150);
+        // reboot
+        assert_eq!(counter.update("boot-2", 20), 170);
+        // device added again
+        assert_eq!(counter.update("boot-2", 5), 175);
+    }
+
+    #[tokio::test]
+    async fn storage_health_test() {
+        let sys_block = TempDir::new("sys_block").unwrap();
+        let store = TempDir::new("store").unwrap();
+
+        write_device(
+            sys_block.path(),
+            "mmcblk0",
+            1000,
+            &[("life_time", "0x01 0x01"), ("pre_eol_info", "0x01")],
+        );
+        write_device(sys_block.path(), "mmcblk0boot0", 0, &[]);
+        write_device(sys_block.path(), "sda", 8, &[]);
+        // virtual devices have no device link
+        std::fs::create_dir(sys_block.path().join("loop0")).unwrap();
+
+        let devices = block_devices(sys_block.path(), store.path());
+        let health = devices.sample("boot-1", false).await.unwrap();
+
+        assert_eq!(health.len(), 2);
+        assert_eq!(
+            health["mmcblk0"],
+            StorageHealth {
+                lifeUsedPercentA: 10,
+                lifeUsedPercentB: 10,
+                preEolStatus: "Normal".to_string(),
+                bytesWrittenBoot: 512_000,
+                bytesWrittenTotal: 512_000,
+            }
+        );
+        assert_eq!(health["sda"].lifeUsedPercentA, -1);
+        assert_eq!(health["sda"].preEolStatus, "Unknown");
+
+        // the counters are restored after a restart of the runtime
+        write_device(
+            sys_block.path(),
+            "mmcblk0",
+            10,
+            &[("life_time", "0x01 0x01")],
+        );
+        let devices = block_devices(sys_block.path(), store.path());
+        let health = devices.sample("boot-2", false).await.unwrap();
+
+        assert_eq!(health["mmcblk0"].bytesWrittenBoot, 5_120);
+        assert_eq!(health["mmcblk0"].bytesWrittenTotal, 517_120);
+    }
+
+    #[tokio::test]
+    async fn save_on_termination_test() {
+        let sys_block = TempDir::new("sys_block").unwrap();
+        let store = TempDir::new("store").unwrap();
+
+        write_device(sys_block.path(), "sda", 1000, &[]);
+        let devices = block_devices(sys_block.path(), store.path());
+        devices.sample("boot-1", false).await.unwrap();
+
+        // written after the last periodic save, kept by the save on termination
+        write_device(sys_block.path(), "sda", 3000, &[]);
+        devices.sample("boot-1", false).await.unwrap();
+        devices.flush("boot-1").await.unwrap();
+
+        write_device(sys_block.path(), "sda", 10, &[]);
+        let devices = block_devices(sys_block.path(), store.path());
+        let health = devices.sample("boot-2", false).await.unwrap();
+
+        assert_eq!(health["sda"].bytesWrittenTotal, 3010 * 512);
+
+        // nothing is saved if the counters were never sampled
+        let unused = TempDir::new("store").unwrap();
+        let devices = block_devices(sys_block.path(), unused.path());
+        devices.flush("boot-2").await.unwrap();
+        assert!(std::fs::read_dir(unused.path()).unwrap().next().is_none());
+    }
+}
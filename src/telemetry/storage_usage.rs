@@ -20,32 +20,381 @@
 
 use crate::error::DeviceManagerError;
 use astarte_device_sdk::AstarteAggregate;
+use log::debug;
+use nix::sys::statvfs::{statvfs, FsFlags};
+use serde::Deserialize;
 use std::collections::HashMap;
-use sysinfo::{DiskExt, System, SystemExt};
 
-#[derive(Debug, AstarteAggregate)]
+const MOUNTS_PATH: &str = "/proc/self/mounts";
+
+/// Usage of a block device, for `io.edgehog.devicemanager.StorageUsage`.
+#[derive(Debug, Clone, AstarteAggregate)]
 #[allow(non_snake_case)]
 pub struct DiskUsage {
     pub totalBytes: i64,
     pub freeBytes: i64,
 }
 
+/// Usage of a mount point, for `io.edgehog.devicemanager.MountPointUsage`.
+#[derive(Debug, Clone, AstarteAggregate)]
+#[allow(non_snake_case)]
+pub struct MountPointUsage {
+    pub totalBytes: i64,
+    pub freeBytes: i64,
+    pub totalInodes: i64,
+    pub freeInodes: i64,
+    pub readOnly: bool,
+    pub mountPoint: String,
+    pub fsType: String,
+}
+
+/// Filters of the mount points reported in `io.edgehog.devicemanager.StorageUsage` and
+/// `io.edgehog.devicemanager.MountPointUsage`.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct StorageUsageOptions {
+    /// Only report these filesystem types, if not empty `exclude_fs_types` is ignored.
+    #[serde(default)]
+    pub include_fs_types: Vec<String>,
+    #[serde(default = "default_exclude_fs_types")]
+    pub exclude_fs_types: Vec<String>,
+    /// Only report the mount points equal to or under these paths.
+    #[serde(default)]
+    pub include_paths: Vec<String>,
+    #[serde(default)]
+    pub exclude_paths: Vec<String>,
+}
+
+fn default_exclude_fs_types() -> Vec<String> {
+    [
+        "autofs",
+        "binfmt_misc",
+        "bpf",
+        "cgroup",
+        "cgroup2",
+        "configfs",
+        "debugfs",
+        "devpts",
+        "devtmpfs",
+        "efivarfs",
+        "fusectl",
+        "hugetlbfs",
+        "mqueue",
+        "nsfs",
+        "overlay",
+        "proc",
+        "pstore",
+        "ramfs",
+        "rpc_pipefs",
+        "securityfs",
+        "selinuxfs",
+        "squashfs",
+        "sysfs",
+        "tmpfs",
+        "tracefs",
+    ]
+    .iter()
+    .map(ToString::to_string)
+    .collect()
+}
+
+impl Default for StorageUsageOptions {
+    fn default() -> Self {
+        StorageUsageOptions {
+            include_fs_types: Vec::new(),
+            exclude_fs_types: default_exclude_fs_types(),
+            include_paths: Vec::new(),
+            exclude_paths: Vec::new(),
+        }
+    }
+}
+
+impl StorageUsageOptions {
+    fn is_included(&self, mount: &Mount) -> bool {
+        let fs_type_included = if self.include_fs_types.is_empty() {
+            !self.exclude_fs_types.contains(&mount.fs_type)
+        } else {
+            self.include_fs_types.contains(&mount.fs_type)
+        };
+
+        let path_included = self.include_paths.is_empty()
+            || self
+                .include_paths
+                .iter()
+                .any(|path| is_under(&mount.mount_point, path));
+
+        let path_excluded = self
+            .exclude_paths
+            .iter()
+            .any(|path| is_under(&mount.mount_point, path));
+
+        fs_type_included && path_included && !path_excluded
+    }
+}
+
+/// An entry of the mount table.
+#[derive(Debug, Clone, PartialEq)]
+struct Mount {
+    device: String,
+    mount_point: String,
+    fs_type: String,
+}
+
 /// get structured data for `io.edgehog.devicemanager.StorageUsage` interface
-/// /dev/ is excluded from the device names since it is common for all devices
-pub fn get_storage_usage() -> Result<HashMap<String, DiskUsage>, DeviceManagerError> {
+///
+/// The usage is reported for each block device, with the `/dev/` prefix removed from its name as
+/// path; a device mounted more than once is reported for its first mount point.
+pub fn get_storage_usage(
+    options: &StorageUsageOptions,
+) -> Result<HashMap<String, DiskUsage>, DeviceManagerError> {
     let mut ret: HashMap<String, DiskUsage> = HashMap::new();
-    let mut sys = System::new_all();
-    sys.refresh_disks();
-
-    for disk in sys.disks() {
-        let disk_name = disk.name().to_str().unwrap().replace("/dev/", "");
-        ret.insert(
-            disk_name.to_owned() as String,
-            DiskUsage {
-                totalBytes: disk.total_space() as i64,
-                freeBytes: disk.available_space() as i64,
-            },
-        );
+    for (mount, usage) in stat_mounts(options)? {
+        let Some(device) = mount.device.strip_prefix("/dev/") else {
+            continue;
+        };
+
+        ret.entry(device.to_string()).or_insert(DiskUsage {
+            totalBytes: usage.totalBytes,
+            freeBytes: usage.freeBytes,
+        });
     }
+
+    Ok(ret)
+}
+
+/// get structured data for `io.edgehog.devicemanager.MountPointUsage` interface
+///
+/// The usage is reported for each mount point, using [`mount_point_key`] as path.
+pub fn get_mount_point_usage(
+    options: &StorageUsageOptions,
+) -> Result<HashMap<String, MountPointUsage>, DeviceManagerError> {
+    // when a mount point is mounted over, the last mount is the visible one
+    let ret = stat_mounts(options)?
+        .into_iter()
+        .map(|(mount, usage)| (mount_point_key(&mount.mount_point), usage))
+        .collect();
+
     Ok(ret)
 }
+
+/// Returns the usage of the included mount points, in the order of the mount table.
+// the integer types of the statvfs fields depend on the target
+#[allow(clippy::unnecessary_cast)]
+fn stat_mounts(
+    options: &StorageUsageOptions,
+) -> Result<Vec<(Mount, MountPointUsage)>, DeviceManagerError> {
+    let mounts = std::fs::read_to_string(MOUNTS_PATH)?;
+
+    let mut ret = Vec::new();
+    for mount in parse_mounts(&mounts) {
+        if !options.is_included(&mount) {
+            continue;
+        }
+
+        let stat = match statvfs(mount.mount_point.as_str()) {
+            Ok(stat) => stat,
+            Err(err) => {
+                debug!("couldn't stat {}: {err}", mount.mount_point);
+                continue;
+            }
+        };
+
+        // pseudo filesystems have no blocks
+        if stat.blocks() == 0 {
+            continue;
+        }
+
+        let block_size = stat.fragment_size() as u64;
+
+        let usage = MountPointUsage {
+            totalBytes: (stat.blocks() as u64 * block_size) as i64,
+            freeBytes: (stat.blocks_available() as u64 * block_size) as i64,
+            totalInodes: stat.files() as i64,
+            freeInodes: stat.files_available() as i64,
+            readOnly: stat.flags().contains(FsFlags::ST_RDONLY),
+            mountPoint: mount.mount_point.clone(),
+            fsType: mount.fs_type.clone(),
+        };
+        ret.push((mount, usage));
+    }
+
+    Ok(ret)
+}
+
+fn parse_mounts(mounts: &str) -> Vec<Mount> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+
+            Some(Mount {
+                device: unescape(fields.next()?),
+                mount_point: unescape(fields.next()?),
+                fs_type: fields.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Replaces the octal escapes used by the kernel for spaces, tabs, newlines and backslashes.
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            let octal: String = chars.clone().take(3).collect();
+            if let Ok(byte) = u8::from_str_radix(&octal, 8) {
+                unescaped.push(char::from(byte));
+                chars.nth(2);
+                continue;
+            }
+        }
+
+        unescaped.push(c);
+    }
+
+    unescaped
+}
+
+fn is_under(mount_point: &str, path: &str) -> bool {
+    let path = path.trim_end_matches('/');
+
+    mount_point == path
+        || path.is_empty()
+        || mount_point
+            .strip_prefix(path)
+            .map_or(false, |rest| rest.starts_with('/'))
+}
+
+/// Returns a unique path segment for a mount point.
+///
+/// The root is `-`, the other mount points have the leading slash removed and the other slashes
+/// replaced by `-`; characters other than ASCII letters, digits and non leading dots are escaped
+/// as `_xx` with their hex value, e.g. `/boot/efi` is `boot-efi` and `/data-1` is `data_2d1`.
+pub fn mount_point_key(mount_point: &str) -> String {
+    let trimmed = mount_point.trim_matches('/');
+    if trimmed.is_empty() {
+        return "-".to_string();
+    }
+
+    trimmed
+        .split('/')
+        .map(|segment| {
+            segment
+                .bytes()
+                .enumerate()
+                .map(|(i, byte)| match byte {
+                    b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => char::from(byte).to_string(),
+                    b'.' if i > 0 => ".".to_string(),
+                    _ => format!("_{byte:02x}"),
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<String>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::telemetry::storage_usage::{
+        get_mount_point_usage, get_storage_usage, mount_point_key, parse_mounts, Mount,
+        StorageUsageOptions,
+    };
+
+    const MOUNTS: &str = "\
+/dev/mmcblk0p2 / ext4 rw,relatime 0 0
+proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0
+tmpfs /run tmpfs rw,nosuid,nodev,mode=755 0 0
+/dev/mmcblk0p1 /boot/efi vfat ro,relatime 0 0
+/dev/mmcblk0p3 /data ext4 rw,relatime 0 0
+/dev/mmcblk0p3 /var/lib/docker ext4 rw,relatime 0 0
+overlay /var/lib/docker/overlay2/merged overlay rw,relatime 0 0
+/dev/sda1 /media/usb\\040stick vfat rw,relatime 0 0
+";
+
+    fn mount(device: &str, mount_point: &str, fs_type: &str) -> Mount {
+        Mount {
+            device: device.to_string(),
+            mount_point: mount_point.to_string(),
+            fs_type: fs_type.to_string(),
+        }
+    }
+
+    fn included(options: &StorageUsageOptions) -> Vec<String> {
+        parse_mounts(MOUNTS)
+            .into_iter()
+            .filter(|mount| options.is_included(mount))
+            .map(|mount| mount.mount_point)
+            .collect()
+    }
+
+    #[test]
+    fn parse_mounts_test() {
+        let mounts = parse_mounts(MOUNTS);
+
+        assert_eq!(mounts.len(), 8);
+        assert_eq!(mounts[0], mount("/dev/mmcblk0p2", "/", "ext4"));
+        assert_eq!(mounts[7], mount("/dev/sda1", "/media/usb stick", "vfat"));
+    }
+
+    #[test]
+    fn default_filter_test() {
+        assert_eq!(
+            included(&StorageUsageOptions::default()),
+            [
+                "/",
+                "/boot/efi",
+                "/data",
+                "/var/lib/docker",
+                "/media/usb stick"
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_filter_test() {
+        let options = StorageUsageOptions {
+            include_fs_types: vec!["ext4".to_string(), "overlay".to_string()],
+            exclude_paths: vec!["/var/lib/docker/".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(included(&options), ["/", "/data"]);
+
+        let options = StorageUsageOptions {
+            include_paths: vec!["/var".to_string(), "/dat".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(included(&options), ["/var/lib/docker"]);
+    }
+
+    #[test]
+    fn mount_point_key_test() {
+        assert_eq!(mount_point_key("/"), "-");
+        assert_eq!(mount_point_key("/data"), "data");
+        assert_eq!(mount_point_key("/boot/efi"), "boot-efi");
+        assert_eq!(mount_point_key("/data-1"), "data_2d1");
+        assert_eq!(mount_point_key("/media/usb stick"), "media-usb_20stick");
+        assert_eq!(mount_point_key("/my_dir/.cache"), "my_5fdir-_2ecache");
+        assert_ne!(mount_point_key("/a/b"), mount_point_key("/a-b"));
+    }
+
+    #[test]
+    fn get_storage_usage_test() {
+        let storage_usage = get_storage_usage(&StorageUsageOptions::default()).unwrap();
+
+        for (key, usage) in storage_usage {
+            assert!(!key.starts_with("/dev/"));
+            assert!(usage.freeBytes <= usage.totalBytes);
+        }
+    }
+
+    #[test]
+    fn get_mount_point_usage_test() {
+        let mount_point_usage = get_mount_point_usage(&StorageUsageOptions::default()).unwrap();
+
+        for (key, usage) in mount_point_usage {
+            assert_eq!(key, mount_point_key(&usage.mountPoint));
+            assert!(usage.freeBytes <= usage.totalBytes);
+        }
+    }
+}
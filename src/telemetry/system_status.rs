@@ -21,7 +21,7 @@
 use crate::error::DeviceManagerError;
 use astarte_device_sdk::AstarteAggregate;
 
-#[derive(Debug, AstarteAggregate)]
+#[derive(Debug, Clone, AstarteAggregate)]
 #[allow(non_snake_case)]
 pub struct SystemStatus {
     pub availMemoryBytes: i64,
@@ -0,0 +1,61 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+use zbus::dbus_proxy;
+
+#[dbus_proxy(
+    interface = "org.freedesktop.systemd1.Unit",
+    default_service = "org.freedesktop.systemd1"
+)]
+trait Unit {
+    /// Primary name of the unit.
+    #[dbus_proxy(property)]
+    fn id(&self) -> zbus::Result<String>;
+
+    /// Whether the unit configuration was loaded, e.g. loaded, not-found, masked.
+    #[dbus_proxy(property)]
+    fn load_state(&self) -> zbus::Result<String>;
+
+    /// High level state of the unit, e.g. active, inactive, failed, activating.
+    #[dbus_proxy(property)]
+    fn active_state(&self) -> zbus::Result<String>;
+
+    /// Low level state of the unit, depending on its type, e.g. running, exited, dead.
+    #[dbus_proxy(property)]
+    fn sub_state(&self) -> zbus::Result<String>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.systemd1.Service",
+    default_service = "org.freedesktop.systemd1"
+)]
+trait Service {
+    /// Number of automatic restarts of the service.
+    #[dbus_proxy(property, name = "NRestarts")]
+    fn n_restarts(&self) -> zbus::Result<u32>;
+
+    /// Exit status or signal number of the main process.
+    #[dbus_proxy(property)]
+    fn exec_main_status(&self) -> zbus::Result<i32>;
+
+    /// Result of the last run, e.g. success, exit-code, signal, core-dump, watchdog.
+    #[dbus_proxy(property)]
+    fn result(&self) -> zbus::Result<String>;
+}
@@ -0,0 +1,279 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Status of the configured systemd units and failure events of any unit.
+
+use std::collections::HashMap;
+
+use astarte_device_sdk::AstarteAggregate;
+use futures::StreamExt;
+use log::{debug, warn};
+use serde::Deserialize;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::OnceCell;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue};
+
+use crate::error::DeviceManagerError;
+use crate::telemetry::systemd::unit::{ServiceProxy, UnitProxy};
+use crate::telemetry::systemd::ManagerProxy;
+use crate::telemetry::{TelemetryMessage, TelemetryPayload};
+
+const UNIT_INTERFACE: &str = "org.freedesktop.systemd1.Unit";
+const PROPERTIES_CHANGED_RULE: &str = "type='signal',sender='org.freedesktop.systemd1',\
+interface='org.freedesktop.DBus.Properties',member='PropertiesChanged',\
+arg0='org.freedesktop.systemd1.Unit'";
+
+/// Configuration of the systemd units telemetry source.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct SystemdUnitsOptions {
+    /// Units whose status is published, e.g. `nginx.service`.
+    #[serde(default)]
+    pub units: Vec<String>,
+}
+
+#[derive(Debug, Clone, AstarteAggregate, PartialEq)]
+#[allow(non_snake_case)]
+pub struct SystemdUnitStatus {
+    pub loadState: String,
+    pub activeState: String,
+    pub subState: String,
+    /// Automatic restarts of a service, 0 for the other unit types.
+    pub restarts: i32,
+    /// Exit status of the main process of a service, 0 for the other unit types.
+    pub lastExitCode: i32,
+    /// Result of the last run of a service, empty for the other unit types.
+    pub result: String,
+}
+
+#[derive(Debug, Clone, AstarteAggregate, PartialEq)]
+#[allow(non_snake_case)]
+pub struct SystemdUnitFailedEvent {
+    pub unit: String,
+    pub restarts: i32,
+    pub lastExitCode: i32,
+    pub result: String,
+}
+
+/// Systemd telemetry source, shares a single system bus connection between the periodic reads and
+/// the failures watcher.
+#[derive(Debug, Default)]
+pub struct SystemdUnits {
+    options: SystemdUnitsOptions,
+    connection: OnceCell<zbus::Connection>,
+}
+
+impl SystemdUnits {
+    pub fn new(options: SystemdUnitsOptions) -> Self {
+        SystemdUnits {
+            options,
+            connection: OnceCell::new(),
+        }
+    }
+
+    async fn connection(&self) -> Result<&zbus::Connection, DeviceManagerError> {
+        let connection = self
+            .connection
+            .get_or_try_init(zbus::Connection::system)
+            .await?;
+
+        Ok(connection)
+    }
+
+    /// get structured data for `io.edgehog.devicemanager.SystemdUnitStatus` interface
+    pub async fn get_unit_status(
+        &self,
+    ) -> Result<HashMap<String, SystemdUnitStatus>, DeviceManagerError> {
+        let connection = self.connection().await?;
+        let manager = ManagerProxy::new(connection).await?;
+
+        let mut ret = HashMap::new();
+        for name in &self.options.units {
+            let path = manager.load_unit(name).await?;
+            ret.insert(name.clone(), get_status(connection, &path).await?);
+        }
+
+        Ok(ret)
+    }
+
+    /// Watches the state of all the units and publishes an event when one of them fails.
+    pub async fn watch(
+        &self,
+        communication_channel: &Sender<TelemetryMessage>,
+    ) -> Result<(), DeviceManagerError> {
+        let connection = self.connection().await?;
+
+        // systemd emits the property changes only while someone is subscribed
+        ManagerProxy::new(connection).await?.subscribe().await?;
+        zbus::fdo::DBusProxy::new(connection)
+            .await?
+            .add_match(PROPERTIES_CHANGED_RULE)
+            .await?;
+
+        let mut messages = zbus::MessageStream::from(connection);
+        let mut states = UnitStates::default();
+
+        while let Some(msg) = messages.next().await {
+            let (path, active_state) = match active_state_change(&msg?) {
+                Some(change) => change,
+                None => continue,
+            };
+
+            if !states.entered_failed(path.as_str(), active_state) {
+                continue;
+            }
+
+            match get_failed_event(connection, &path).await {
+                Ok(event) => {
+                    debug!("unit failed: {event:?}");
+
+                    let _ = communication_channel
+                        .send(TelemetryMessage {
+                            path: "".to_string(),
+                            payload: TelemetryPayload::SystemdUnitFailed(event),
+                        })
+                        .await;
+                }
+                Err(err) => warn!("couldn't read the failed unit {}: {err}", path.as_str()),
+            }
+        }
+
+        warn!("systemd signals stream closed");
+
+        Ok(())
+    }
+}
+
+async fn get_status(
+    connection: &zbus::Connection,
+    path: &ObjectPath<'_>,
+) -> Result<SystemdUnitStatus, DeviceManagerError> {
+    let unit = UnitProxy::builder(connection)
+        .path(path.to_owned())?
+        .build()
+        .await?;
+    let service = ServiceProxy::builder(connection)
+        .path(path.to_owned())?
+        .build()
+        .await?;
+
+    // the service properties are missing for the other unit types
+    Ok(SystemdUnitStatus {
+        loadState: unit.load_state().await?,
+        activeState: unit.active_state().await?,
+        subState: unit.sub_state().await?,
+        restarts: service.n_restarts().await.unwrap_or_default() as i32,
+        lastExitCode: service.exec_main_status().await.unwrap_or_default(),
+        result: service.result().await.unwrap_or_default(),
+    })
+}
+
+async fn get_failed_event(
+    connection: &zbus::Connection,
+    path: &ObjectPath<'_>,
+) -> Result<SystemdUnitFailedEvent, DeviceManagerError> {
+    let unit = UnitProxy::builder(connection)
+        .path(path.to_owned())?
+        .build()
+        .await?;
+    let status = get_status(connection, path).await?;
+
+    Ok(SystemdUnitFailedEvent {
+        unit: unit.id().await?,
+        restarts: status.restarts,
+        lastExitCode: status.lastExitCode,
+        result: status.result,
+    })
+}
+
+/// Returns the unit path and its new active state if the message is a change of it.
+fn active_state_change(msg: &zbus::Message) -> Option<(OwnedObjectPath, String)> {
+    if msg.member()?.as_str() != "PropertiesChanged" {
+        return None;
+    }
+
+    let path = OwnedObjectPath::from(msg.path()?.to_owned());
+    let (interface, changed, _): (String, HashMap<String, OwnedValue>, Vec<String>) =
+        msg.body().ok()?;
+
+    if interface != UNIT_INTERFACE {
+        return None;
+    }
+
+    let active_state = String::try_from(changed.get("ActiveState")?.clone()).ok()?;
+
+    Some((path, active_state))
+}
+
+/// Last known active state of the units, to send a single event when a unit fails.
+#[derive(Debug, Default)]
+struct UnitStates {
+    states: HashMap<String, String>,
+}
+
+impl UnitStates {
+    fn entered_failed(&mut self, path: &str, active_state: String) -> bool {
+        let entered = active_state == "failed"
+            && self
+                .states
+                .get(path)
+                .map_or(true, |previous| previous != "failed");
+
+        self.states.insert(path.to_string(), active_state);
+
+        entered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::telemetry::systemd_units::{SystemdUnitsOptions, UnitStates};
+
+    fn unit_path(name: &str) -> String {
+        format!("/org/freedesktop/systemd1/unit/{name}")
+    }
+
+    #[test]
+    fn entered_failed_test() {
+        let mut states = UnitStates::default();
+        let nginx = unit_path("nginx_2eservice");
+        let sshd = unit_path("sshd_2eservice");
+
+        assert!(!states.entered_failed(&nginx, "active".to_string()));
+        assert!(states.entered_failed(&nginx, "failed".to_string()));
+        assert!(!states.entered_failed(&nginx, "failed".to_string()));
+        assert!(states.entered_failed(&sshd, "failed".to_string()));
+
+        // crash loop
+        assert!(!states.entered_failed(&nginx, "activating".to_string()));
+        assert!(states.entered_failed(&nginx, "failed".to_string()));
+    }
+
+    #[test]
+    fn systemd_units_options_test() {
+        let options: SystemdUnitsOptions =
+            toml::from_str(r#"units = ["nginx.service", "getty@tty1.service"]"#).unwrap();
+
+        assert_eq!(options.units, ["nginx.service", "getty@tty1.service"]);
+        assert!(toml::from_str::<SystemdUnitsOptions>("")
+            .unwrap()
+            .units
+            .is_empty());
+    }
+}
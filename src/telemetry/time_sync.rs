@@ -0,0 +1,185 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! NTP synchronization state and timezone, read from systemd-timedated and systemd-timesyncd.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use astarte_device_sdk::AstarteAggregate;
+use log::debug;
+
+use crate::error::DeviceManagerError;
+use crate::telemetry::timedate::timesync::{ManagerProxy, NtpMessage, ServerAddress};
+use crate::telemetry::timedate::TimedateProxy;
+
+#[derive(Debug, Clone, AstarteAggregate, PartialEq)]
+#[allow(non_snake_case)]
+pub struct TimeSyncStatus {
+    pub ntpEnabled: bool,
+    pub synchronized: bool,
+    pub timezone: String,
+    pub localRtc: bool,
+    /// NTP server in use, empty when systemd-timesyncd is not running.
+    pub serverName: String,
+    pub serverAddress: String,
+    pub stratum: i32,
+    /// Offset of the system clock from the server one in the last NTP response, in microseconds.
+    pub offsetMicroseconds: i64,
+}
+
+/// get structured data for `io.edgehog.devicemanager.TimeSyncStatus` interface
+pub async fn get_time_sync_status() -> Result<TimeSyncStatus, DeviceManagerError> {
+    let connection = zbus::Connection::system().await?;
+    let timedate = TimedateProxy::new(&connection).await?;
+
+    let mut status = TimeSyncStatus {
+        ntpEnabled: timedate.ntp().await?,
+        synchronized: timedate.ntp_synchronized().await?,
+        timezone: timedate.timezone().await?,
+        localRtc: timedate.local_rtc().await?,
+        serverName: String::new(),
+        serverAddress: String::new(),
+        stratum: 0,
+        offsetMicroseconds: 0,
+    };
+
+    // other NTP clients, e.g. chrony, don't publish the server on the bus
+    if let Err(err) = read_timesync_status(&connection, &mut status).await {
+        debug!("couldn't read the systemd-timesyncd status: {err}");
+    }
+
+    Ok(status)
+}
+
+async fn read_timesync_status(
+    connection: &zbus::Connection,
+    status: &mut TimeSyncStatus,
+) -> Result<(), DeviceManagerError> {
+    let manager = ManagerProxy::new(connection).await?;
+
+    status.serverName = manager.server_name().await?;
+    status.serverAddress = server_address(&manager.server_address().await?)
+        .map(|address| address.to_string())
+        .unwrap_or_default();
+
+    let message = manager.ntp_message().await?;
+    status.stratum = i32::try_from(message.stratum).unwrap_or_default();
+    status.offsetMicroseconds = clock_offset(&message);
+
+    Ok(())
+}
+
+fn server_address(address: &ServerAddress) -> Option<IpAddr> {
+    if let Ok(octets) = <[u8; 4]>::try_from(address.address.as_slice()) {
+        return Some(Ipv4Addr::from(octets).into());
+    }
+
+    <[u8; 16]>::try_from(address.address.as_slice())
+        .ok()
+        .map(|octets| Ipv6Addr::from(octets).into())
+}
+
+/// Clock offset of RFC 5905, `((T2 - T1) + (T3 - T4)) / 2`, 0 until a response is received.
+fn clock_offset(message: &NtpMessage) -> i64 {
+    let [originate, receive, transmit, destination] = [
+        message.originate_timestamp,
+        message.receive_timestamp,
+        message.transmit_timestamp,
+        message.destination_timestamp,
+    ]
+    .map(|timestamp| i64::try_from(timestamp).unwrap_or_default());
+
+    if originate == 0 || destination == 0 {
+        return 0;
+    }
+
+    ((receive - originate) + (transmit - destination)) / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use crate::telemetry::time_sync::{clock_offset, server_address};
+    use crate::telemetry::timedate::timesync::{NtpMessage, ServerAddress};
+
+    fn ntp_message(timestamps: [u64; 4]) -> NtpMessage {
+        NtpMessage {
+            leap: 0,
+            version: 4,
+            mode: 4,
+            stratum: 2,
+            precision: -23,
+            root_delay: 0,
+            root_dispersion: 0,
+            reference: Vec::new(),
+            originate_timestamp: timestamps[0],
+            receive_timestamp: timestamps[1],
+            transmit_timestamp: timestamps[2],
+            destination_timestamp: timestamps[3],
+            ignored: false,
+            packet_count: 1,
+            jitter: 0,
+        }
+    }
+
+    #[test]
+    fn clock_offset_test() {
+        // the server clock is 5ms ahead, with a 2ms round trip
+        let message = ntp_message([1_000_000, 1_006_000, 1_006_100, 1_002_100]);
+        assert_eq!(clock_offset(&message), 5_000);
+
+        // the server clock is 3ms behind
+        let message = ntp_message([1_000_000, 998_000, 998_000, 1_002_000]);
+        assert_eq!(clock_offset(&message), -3_000);
+
+        assert_eq!(clock_offset(&ntp_message([0; 4])), 0);
+    }
+
+    #[test]
+    fn server_address_test() {
+        let ipv4 = ServerAddress {
+            family: 2,
+            address: vec![192, 0, 2, 1],
+        };
+        assert_eq!(
+            server_address(&ipv4),
+            Some("192.0.2.1".parse::<IpAddr>().unwrap())
+        );
+
+        let mut octets = vec![0x20, 0x01, 0x0d, 0xb8];
+        octets.extend([0; 11]);
+        octets.push(1);
+        let ipv6 = ServerAddress {
+            family: 10,
+            address: octets,
+        };
+        assert_eq!(
+            server_address(&ipv6),
+            Some("2001:db8::1".parse::<IpAddr>().unwrap())
+        );
+
+        let empty = ServerAddress {
+            family: 0,
+            address: Vec::new(),
+        };
+        assert_eq!(server_address(&empty), None);
+    }
+}
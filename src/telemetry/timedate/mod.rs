@@ -0,0 +1,52 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+use zbus::dbus_proxy;
+
+pub(crate) mod timesync;
+
+#[dbus_proxy(
+    interface = "org.freedesktop.timedate1",
+    default_service = "org.freedesktop.timedate1",
+    default_path = "/org/freedesktop/timedate1"
+)]
+trait Timedate {
+    /// Sets the system timezone, e.g. `Europe/Rome`.
+    fn set_timezone(&self, timezone: &str, interactive: bool) -> zbus::Result<()>;
+
+    /// Enables or disables the network time synchronization.
+    #[dbus_proxy(name = "SetNTP")]
+    fn set_ntp(&self, use_ntp: bool, interactive: bool) -> zbus::Result<()>;
+
+    #[dbus_proxy(property)]
+    fn timezone(&self) -> zbus::Result<String>;
+
+    /// Whether the RTC is kept in local time instead of UTC.
+    #[dbus_proxy(property, name = "LocalRTC")]
+    fn local_rtc(&self) -> zbus::Result<bool>;
+
+    /// Whether the network time synchronization is enabled.
+    #[dbus_proxy(property, name = "NTP")]
+    fn ntp(&self) -> zbus::Result<bool>;
+
+    /// Whether the kernel reports the system clock as synchronized.
+    #[dbus_proxy(property, name = "NTPSynchronized")]
+    fn ntp_synchronized(&self) -> zbus::Result<bool>;
+}
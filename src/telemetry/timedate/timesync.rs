@@ -0,0 +1,66 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+use zbus::dbus_proxy;
+use zbus::zvariant::{OwnedValue, Type};
+
+/// Address of the NTP server, with its address family.
+#[derive(Clone, Debug, PartialEq, Eq, Type, OwnedValue)]
+pub struct ServerAddress {
+    pub family: i32,
+    pub address: Vec<u8>,
+}
+
+/// Last NTP response received, the timestamps are in microseconds of `CLOCK_REALTIME`.
+#[derive(Clone, Debug, PartialEq, Eq, Type, OwnedValue)]
+pub struct NtpMessage {
+    pub leap: u32,
+    pub version: u32,
+    pub mode: u32,
+    pub stratum: u32,
+    pub precision: i32,
+    pub root_delay: u64,
+    pub root_dispersion: u64,
+    pub reference: Vec<u8>,
+    pub originate_timestamp: u64,
+    pub receive_timestamp: u64,
+    pub transmit_timestamp: u64,
+    pub destination_timestamp: u64,
+    pub ignored: bool,
+    pub packet_count: u64,
+    pub jitter: u64,
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.timesync1.Manager",
+    default_service = "org.freedesktop.timesync1",
+    default_path = "/org/freedesktop/timesync1"
+)]
+trait Manager {
+    /// Name of the NTP server in use.
+    #[dbus_proxy(property)]
+    fn server_name(&self) -> zbus::Result<String>;
+
+    #[dbus_proxy(property)]
+    fn server_address(&self) -> zbus::Result<ServerAddress>;
+
+    #[dbus_proxy(property, name = "NTPMessage")]
+    fn ntp_message(&self) -> zbus::Result<NtpMessage>;
+}
@@ -30,4 +30,12 @@ trait UPower {
     /// Indicates whether the system is running on battery power. This property is provided for convenience.
     #[dbus_proxy(property)]
     fn on_battery(&self) -> zbus::Result<bool>;
+
+    /// Emitted when a device is added.
+    #[dbus_proxy(signal)]
+    fn device_added(&self, device: zbus::zvariant::ObjectPath<'_>) -> zbus::Result<()>;
+
+    /// Emitted when a device is removed.
+    #[dbus_proxy(signal)]
+    fn device_removed(&self, device: zbus::zvariant::ObjectPath<'_>) -> zbus::Result<()>;
 }
@@ -18,11 +18,23 @@
  * SPDX-License-Identifier: Apache-2.0
  */
 
-use crate::DeviceManagerError;
+//! Access points scanned through the wpa_supplicant D-Bus interface.
+
+use std::collections::HashMap;
+
 use astarte_device_sdk::AstarteAggregate;
-use wifiscanner::Wifi;
+use futures::StreamExt;
+use log::{debug, warn};
+use tokio::time::{timeout, Duration};
+use zbus::zvariant::{ObjectPath, Value};
+
+use crate::telemetry::wpa_supplicant::interface::{BssProxy, InterfaceProxy};
+use crate::telemetry::wpa_supplicant::WpaSupplicantProxy;
+use crate::DeviceManagerError;
 
-#[derive(Debug, AstarteAggregate, PartialEq)]
+const SCAN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, AstarteAggregate, PartialEq)]
 #[allow(non_snake_case)]
 pub struct WifiScanResult {
     channel: i32,
@@ -32,61 +44,255 @@ pub struct WifiScanResult {
     rssi: i32,
 }
 
-pub fn get_wifi_scan_results() -> Result<Vec<WifiScanResult>, DeviceManagerError> {
+impl WifiScanResult {
+    pub(crate) fn bssid(&self) -> &str {
+        &self.macAddress
+    }
+}
+
+/// get structured data for `io.edgehog.devicemanager.WiFiScanResults` interface
+///
+/// Returns no access points if wpa_supplicant is not running.
+pub async fn get_wifi_scan_results() -> Result<Vec<WifiScanResult>, DeviceManagerError> {
+    let connection = match zbus::Connection::system().await {
+        Ok(connection) => connection,
+        Err(err) => {
+            warn!("couldn't connect to the system bus: {err}");
+            return Ok(Vec::new());
+        }
+    };
+
+    let interfaces = match WpaSupplicantProxy::new(&connection)
+        .await?
+        .interfaces()
+        .await
+    {
+        Ok(interfaces) => interfaces,
+        Err(err) => {
+            debug!("wpa_supplicant not available: {err}");
+            return Ok(Vec::new());
+        }
+    };
+
+    let mut ret = Vec::new();
+    for path in interfaces {
+        // an interface could be removed or fail while the others are scanned
+        match scan_interface(&connection, &path).await {
+            Ok(access_points) => ret.extend(access_points),
+            Err(err) => warn!("couldn't scan the interface {}: {err}", path.as_str()),
+        }
+    }
+
+    Ok(ret)
+}
+
+async fn scan_interface(
+    connection: &zbus::Connection,
+    path: &ObjectPath<'_>,
+) -> Result<Vec<WifiScanResult>, DeviceManagerError> {
+    let interface = InterfaceProxy::builder(connection)
+        .path(path.to_owned())?
+        .build()
+        .await?;
+
+    scan(&interface).await;
+
+    get_access_points(connection, &interface).await
+}
+
+/// Triggers a scan and waits for it, the last results are used if the scan fails.
+async fn scan(interface: &InterfaceProxy<'_>) {
+    let mut scan_done = match interface.receive_scan_done().await {
+        Ok(scan_done) => scan_done,
+        Err(err) => {
+            warn!("couldn't wait for the wifi scan: {err}");
+            return;
+        }
+    };
+
+    let args = HashMap::from([("Type", Value::from("active"))]);
+    if let Err(err) = interface.scan(args).await {
+        // e.g. a scan is already running or the interface is disabled
+        debug!("couldn't start the wifi scan: {err}");
+        return;
+    }
+
+    if timeout(SCAN_TIMEOUT, scan_done.next()).await.is_err() {
+        warn!("wifi scan timed out, sending the last results");
+    }
+}
+
+async fn get_access_points(
+    connection: &zbus::Connection,
+    interface: &InterfaceProxy<'_>,
+) -> Result<Vec<WifiScanResult>, DeviceManagerError> {
+    let current_bss = interface.current_bss().await?;
+
     let mut ret = Vec::new();
-    if let Ok(wifi_array) = wifiscanner::scan() {
-        for wifi in wifi_array {
-            let w = wifi.try_into()?;
-            ret.push(w);
+    for path in interface.bsss().await? {
+        let connected = path == current_bss;
+        match get_access_point(connection, &path, connected).await {
+            Ok(Some(access_point)) => ret.push(access_point),
+            Ok(None) => debug!("skipping unsupported BSS {}", path.as_str()),
+            // the BSS could expire while reading it
+            Err(err) => debug!("couldn't read BSS {}: {err}", path.as_str()),
         }
     }
 
     Ok(ret)
 }
 
-impl TryFrom<Wifi> for WifiScanResult {
-    type Error = DeviceManagerError;
-    fn try_from(wifi: Wifi) -> Result<Self, Self::Error> {
-        Ok(WifiScanResult {
-            channel: wifi.channel.parse::<i32>()?,
-            connected: false,
-            essid: wifi.ssid,
-            macAddress: wifi.mac,
-            rssi: wifi.signal_level.parse::<i32>()?,
-        })
+async fn get_access_point(
+    connection: &zbus::Connection,
+    path: &ObjectPath<'_>,
+    connected: bool,
+) -> Result<Option<WifiScanResult>, DeviceManagerError> {
+    let bss = BssProxy::builder(connection)
+        .path(path.to_owned())?
+        .build()
+        .await?;
+
+    Ok(to_scan_result(
+        &bss.bssid().await?,
+        &bss.ssid().await?,
+        bss.frequency().await?,
+        bss.signal().await?,
+        connected,
+    ))
+}
+
+/// Returns `None` for a malformed BSSID or a frequency outside of the 2.4, 5 and 6 GHz bands.
+fn to_scan_result(
+    bssid: &[u8],
+    ssid: &[u8],
+    frequency: u16,
+    signal: i16,
+    connected: bool,
+) -> Option<WifiScanResult> {
+    if bssid.len() != 6 {
+        return None;
+    }
+
+    let mac_address = bssid
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<String>>()
+        .join(":");
+
+    Some(WifiScanResult {
+        channel: frequency_to_channel(frequency)?,
+        connected,
+        essid: String::from_utf8_lossy(ssid).into_owned(),
+        macAddress: mac_address,
+        rssi: i32::from(signal),
+    })
+}
+
+fn frequency_to_channel(frequency: u16) -> Option<i32> {
+    let frequency = i32::from(frequency);
+
+    match frequency {
+        2484 => Some(14),
+        2412..=2472 => Some((frequency - 2407) / 5),
+        // the 6 GHz band starts at channel 1 on 5955 MHz, 5935 MHz is channel 2
+        5935 => Some(2),
+        5955..=7115 => Some((frequency - 5950) / 5),
+        5160..=5885 => Some((frequency - 5000) / 5),
+        _ => None,
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::telemetry::wifi_scan::{get_wifi_scan_results, WifiScanResult};
-    use wifiscanner::Wifi;
+    use tokio::time::Instant;
 
-    #[test]
-    fn wifi_scan_test() {
-        assert!(get_wifi_scan_results().is_ok());
+    use crate::telemetry::send_policy::{ChangeFilter, Deadband, SendPolicy};
+    use crate::telemetry::wifi_scan::{
+        frequency_to_channel, get_wifi_scan_results, to_scan_result, WifiScanResult,
+    };
+    use crate::telemetry::{TelemetryMessage, TelemetryPayload};
+
+    #[tokio::test]
+    async fn wifi_scan_test() {
+        assert!(get_wifi_scan_results().await.is_ok());
     }
 
     #[test]
-    fn get_hashmap_from_wifi_test() {
-        let wifi = Wifi {
-            mac: "ab:cd:ef:01:23:45".to_string(),
-            ssid: "Vodafone Hotspot".to_string(),
-            channel: "6".to_string(),
-            signal_level: "-92".to_string(),
-            security: "Open".to_string(),
-        };
-        let inter: Result<WifiScanResult, _> = wifi.try_into();
-        assert!(inter.is_ok());
+    fn to_scan_result_test() {
+        let scan_result = to_scan_result(
+            &[0xab, 0xcd, 0xef, 0x01, 0x23, 0x45],
+            b"Vodafone Hotspot",
+            2437,
+            -92,
+            true,
+        );
+
         assert_eq!(
-            inter.unwrap(),
-            WifiScanResult {
+            scan_result,
+            Some(WifiScanResult {
                 channel: 6,
-                connected: false,
+                connected: true,
                 essid: "Vodafone Hotspot".to_string(),
                 macAddress: "ab:cd:ef:01:23:45".to_string(),
                 rssi: -92
-            }
+            })
+        );
+    }
+
+    #[test]
+    fn skip_unparsable_scan_result_test() {
+        assert!(to_scan_result(&[0xab, 0xcd], b"short", 2437, -50, false).is_none());
+        assert!(to_scan_result(&[0; 6], b"60GHz", 58320, -50, false).is_none());
+    }
+
+    #[test]
+    fn frequency_to_channel_test() {
+        assert_eq!(frequency_to_channel(2412), Some(1));
+        assert_eq!(frequency_to_channel(2472), Some(13));
+        assert_eq!(frequency_to_channel(2484), Some(14));
+        assert_eq!(frequency_to_channel(5180), Some(36));
+        assert_eq!(frequency_to_channel(5825), Some(165));
+        assert_eq!(frequency_to_channel(5955), Some(1));
+        assert_eq!(frequency_to_channel(6115), Some(33));
+        assert_eq!(frequency_to_channel(2400), None);
+    }
+
+    fn access_point(mac_address: &str, rssi: i32) -> TelemetryMessage {
+        TelemetryMessage {
+            path: "ap".to_string(),
+            payload: TelemetryPayload::WifiScanResult(WifiScanResult {
+                channel: 6,
+                connected: false,
+                essid: "edgehog".to_string(),
+                macAddress: mac_address.to_string(),
+                rssi,
+            }),
+        }
+    }
+
+    #[test]
+    fn access_points_filter_test() {
+        let mut filter = ChangeFilter::new(SendPolicy::new(
+            Some(true),
+            Some(Deadband::Absolute(5.0)),
+            None,
+        ));
+        let now = Instant::now();
+        let mut should_send = |msg: TelemetryMessage| {
+            let key = msg.key();
+            filter.should_send(&key, msg.payload.values().unwrap(), now)
+        };
+
+        // the access points are published on the same path but filtered on their own
+        assert!(should_send(access_point("ab:cd:ef:01:23:45", -40)));
+        assert!(should_send(access_point("ab:cd:ef:01:23:46", -80)));
+        assert!(!should_send(access_point("ab:cd:ef:01:23:45", -42)));
+        assert!(!should_send(access_point("ab:cd:ef:01:23:46", -78)));
+        assert!(should_send(access_point("ab:cd:ef:01:23:46", -60)));
+
+        assert_eq!(
+            access_point("ab:cd:ef:01:23:45", -40).key(),
+            "ap/ab:cd:ef:01:23:45"
         );
     }
 }
@@ -0,0 +1,69 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+use std::collections::HashMap;
+
+use zbus::dbus_proxy;
+use zbus::zvariant::{OwnedObjectPath, Value};
+
+#[dbus_proxy(
+    interface = "fi.w1.wpa_supplicant1.Interface",
+    default_service = "fi.w1.wpa_supplicant1"
+)]
+trait Interface {
+    /// Triggers a scan, `Type` is either `active` or `passive`.
+    fn scan(&self, args: HashMap<&str, Value<'_>>) -> zbus::Result<()>;
+
+    /// Name of the network interface, e.g. wlan0.
+    #[dbus_proxy(property)]
+    fn ifname(&self) -> zbus::Result<String>;
+
+    /// BSSs found by the last scans.
+    #[dbus_proxy(property, name = "BSSs")]
+    fn bsss(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    /// BSS the interface is associated with, `/` if not associated.
+    #[dbus_proxy(property, name = "CurrentBSS")]
+    fn current_bss(&self) -> zbus::Result<OwnedObjectPath>;
+
+    /// Emitted when a scan is completed.
+    #[dbus_proxy(signal)]
+    fn scan_done(&self, success: bool) -> zbus::Result<()>;
+}
+
+#[dbus_proxy(
+    interface = "fi.w1.wpa_supplicant1.BSS",
+    default_service = "fi.w1.wpa_supplicant1"
+)]
+trait Bss {
+    #[dbus_proxy(property, name = "BSSID")]
+    fn bssid(&self) -> zbus::Result<Vec<u8>>;
+
+    #[dbus_proxy(property, name = "SSID")]
+    fn ssid(&self) -> zbus::Result<Vec<u8>>;
+
+    /// Frequency in MHz.
+    #[dbus_proxy(property)]
+    fn frequency(&self) -> zbus::Result<u16>;
+
+    /// Signal strength in dBm.
+    #[dbus_proxy(property)]
+    fn signal(&self) -> zbus::Result<i16>;
+}
@@ -0,0 +1,165 @@
+/*
+ * This file is part of Edgehog.
+ *
+ * Copyright 2023 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+ * SPDX-License-Identifier: Apache-2.0
+ */
+
+//! Remote control of the timezone and NTP servers, for
+//! `io.edgehog.devicemanager.config.TimeSettings`.
+
+use std::path::Path;
+
+use astarte_device_sdk::types::AstarteType;
+use log::{error, info, warn};
+
+use crate::error::DeviceManagerError;
+use crate::telemetry::systemd::ManagerProxy;
+use crate::telemetry::timedate::TimedateProxy;
+
+const TIMESYNCD_DROP_IN_DIRECTORY: &str = "/etc/systemd/timesyncd.conf.d";
+const TIMESYNCD_DROP_IN: &str = "edgehog.conf";
+const TIMESYNCD_UNIT: &str = "systemd-timesyncd.service";
+
+/// Applies a property of the time settings interface.
+pub(crate) async fn set_time_setting(endpoint: &str, data: &AstarteType) {
+    let res = match (endpoint, data) {
+        ("timezone", AstarteType::String(timezone)) => set_timezone(timezone).await,
+        ("timezone", AstarteType::Unset) => {
+            info!("timezone unset, keeping the current one");
+
+            Ok(())
+        }
+        ("ntpServers", AstarteType::StringArray(servers)) => {
+            set_ntp_servers(Path::new(TIMESYNCD_DROP_IN_DIRECTORY), servers).await
+        }
+        ("ntpServers", AstarteType::Unset) => {
+            reset_ntp_servers(Path::new(TIMESYNCD_DROP_IN_DIRECTORY)).await
+        }
+        _ => {
+            warn!("unexpected time setting {endpoint}: {data:?}");
+
+            Ok(())
+        }
+    };
+
+    if let Err(err) = res {
+        error!("couldn't apply the time setting {endpoint}: {err}");
+    }
+}
+
+async fn set_timezone(timezone: &str) -> Result<(), DeviceManagerError> {
+    let connection = zbus::Connection::system().await?;
+    let timedate = TimedateProxy::new(&connection).await?;
+
+    timedate.set_timezone(timezone, false).await?;
+
+    info!("timezone set to {timezone}");
+
+    Ok(())
+}
+
+/// Configures systemd-timesyncd to use the servers, enabling the network time synchronization.
+async fn set_ntp_servers(directory: &Path, servers: &[String]) -> Result<(), DeviceManagerError> {
+    let config = timesyncd_config(servers)?;
+
+    tokio::fs::create_dir_all(directory).await?;
+    tokio::fs::write(directory.join(TIMESYNCD_DROP_IN), config).await?;
+
+    let connection = zbus::Connection::system().await?;
+    restart_timesyncd(&connection).await?;
+    TimedateProxy::new(&connection)
+        .await?
+        .set_ntp(true, false)
+        .await?;
+
+    info!("NTP servers set to {}", servers.join(" "));
+
+    Ok(())
+}
+
+/// Restores the NTP servers of the image configuration.
+async fn reset_ntp_servers(directory: &Path) -> Result<(), DeviceManagerError> {
+    match tokio::fs::remove_file(directory.join(TIMESYNCD_DROP_IN)).await {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    }
+
+    let connection = zbus::Connection::system().await?;
+    restart_timesyncd(&connection).await?;
+
+    info!("NTP servers reset to the default ones");
+
+    Ok(())
+}
+
+async fn restart_timesyncd(connection: &zbus::Connection) -> Result<(), DeviceManagerError> {
+    ManagerProxy::new(connection)
+        .await?
+        .restart_unit(TIMESYNCD_UNIT, "replace")
+        .await?;
+
+    Ok(())
+}
+
+fn timesyncd_config(servers: &[String]) -> Result<String, DeviceManagerError> {
+    if servers.is_empty() {
+        return Err(DeviceManagerError::TimeSettingsError(
+            "empty NTP servers list".to_string(),
+        ));
+    }
+
+    if let Some(server) = servers
+        .iter()
+        .find(|server| server.is_empty() || server.contains(char::is_whitespace))
+    {
+        return Err(DeviceManagerError::TimeSettingsError(format!(
+            "invalid NTP server {server:?}"
+        )));
+    }
+
+    Ok(format!("[Time]\nNTP={}\n", servers.join(" ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use crate::time_settings::{reset_ntp_servers, timesyncd_config, TIMESYNCD_DROP_IN};
+
+    #[test]
+    fn timesyncd_config_test() {
+        let servers = vec!["0.pool.ntp.org".to_string(), "192.0.2.10".to_string()];
+        assert_eq!(
+            timesyncd_config(&servers).unwrap(),
+            "[Time]\nNTP=0.pool.ntp.org 192.0.2.10\n"
+        );
+
+        assert!(timesyncd_config(&[]).is_err());
+        assert!(timesyncd_config(&["pool.ntp.org\nFallbackNTP=evil".to_string()]).is_err());
+        assert!(timesyncd_config(&["".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn reset_missing_drop_in_test() {
+        let dir = TempDir::new("edgehog").unwrap();
+
+        reset_ntp_servers(dir.path()).await.unwrap();
+
+        assert!(!dir.path().join(TIMESYNCD_DROP_IN).exists());
+    }
+}